@@ -0,0 +1,32 @@
+-- Hashboard ID to submodel catalog
+CREATE TABLE IF NOT EXISTS hashboards (
+    id INTEGER PRIMARY KEY NOT NULL,
+    hashboard TEXT NOT NULL UNIQUE,
+    submodel TEXT NOT NULL,
+    chips INTEGER
+);
+
+-- Hashboard IDs seen during scans that aren't in the catalog yet
+CREATE TABLE IF NOT EXISTS unknown_hashboards (
+    id INTEGER PRIMARY KEY NOT NULL,
+    hashboard TEXT NOT NULL UNIQUE,
+    model TEXT,
+    ip TEXT NOT NULL,
+    last_seen INTEGER NOT NULL
+);
+
+INSERT OR IGNORE INTO hashboards (hashboard, submodel, chips) VALUES
+    -- T19
+    ('NBT1903', '240-Ca', NULL),
+    -- S19
+    ('NBS1902', '240-Ca', 76),
+    ('BHB42801', '240-Ch', 76),
+    ('BHB42831', '240-Ch', 76),
+    -- S19a
+    ('BHB28611', '240-Ce', NULL),
+    -- S19 Pro
+    ('BHB42601', '240-C', 114),
+    ('BHB42651', 'j1-11', NULL),
+    -- S19j Pro
+    ('BHB42603', '240-Cb', 126),
+    ('BHB42631', 'j1-11', NULL);
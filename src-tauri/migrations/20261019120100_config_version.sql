@@ -0,0 +1,2 @@
+-- Schema version of each settings value, values written before versioning are version 1
+ALTER TABLE config ADD COLUMN version INTEGER NOT NULL DEFAULT 1;
@@ -0,0 +1,4 @@
+-- API port for miners behind NAT or port forwarding, NULL uses the default
+ALTER TABLE miners ADD COLUMN port INTEGER;
+-- Reference to the miner in the tool the layout was imported from, such as Frontier's pickaxe_id
+ALTER TABLE miners ADD COLUMN external_id TEXT;
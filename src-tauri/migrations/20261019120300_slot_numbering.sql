@@ -0,0 +1,2 @@
+-- How slots are labelled for people, see Numbering
+ALTER TABLE racks ADD COLUMN numbering TEXT NOT NULL DEFAULT 'top_left';
@@ -0,0 +1,21 @@
+-- Optional hierarchy above cans, such as buildings and pods
+-- parent_id isn't a foreign key so backups can restore areas in any order, the app checks it
+CREATE TABLE IF NOT EXISTS areas (
+    id INTEGER PRIMARY KEY NOT NULL,
+    parent_id INTEGER,
+    name TEXT NOT NULL,
+    kind TEXT NOT NULL
+);
+
+ALTER TABLE cans ADD COLUMN area_id INTEGER REFERENCES areas(id);
+
+-- Latest scan reading of each miner, for totals at every level of the hierarchy
+CREATE TABLE IF NOT EXISTS miner_samples (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ip TEXT NOT NULL UNIQUE,
+    online INTEGER NOT NULL,
+    healthy INTEGER NOT NULL,
+    hashrate REAL,
+    power REAL,
+    sampled INTEGER NOT NULL
+);
@@ -0,0 +1,23 @@
+-- Power distribution, the PDUs of the site, their breakers and the rack slots each breaker feeds
+CREATE TABLE IF NOT EXISTS pdus (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS circuits (
+    id INTEGER PRIMARY KEY NOT NULL,
+    pdu_id INTEGER NOT NULL REFERENCES pdus(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    -- Watts the breaker can carry
+    capacity REAL NOT NULL,
+    UNIQUE (pdu_id, name)
+);
+
+-- A feed covers a whole rack, or the slots labelled first to last under the rack's numbering
+CREATE TABLE IF NOT EXISTS circuit_feeds (
+    id INTEGER PRIMARY KEY NOT NULL,
+    circuit_id INTEGER NOT NULL REFERENCES circuits(id) ON DELETE CASCADE,
+    rack_id INTEGER NOT NULL REFERENCES racks(id) ON DELETE CASCADE,
+    first INTEGER,
+    last INTEGER
+);
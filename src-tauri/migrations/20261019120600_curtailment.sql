@@ -0,0 +1,35 @@
+-- Readings curtailment plans are made from
+ALTER TABLE miner_samples ADD COLUMN efficiency REAL;
+ALTER TABLE miner_samples ADD COLUMN sleep INTEGER NOT NULL DEFAULT 0;
+-- Profile the miner was running and the profiles it offers, as JSON
+ALTER TABLE miner_samples ADD COLUMN profile TEXT;
+ALTER TABLE miner_samples ADD COLUMN profiles TEXT;
+
+-- Cuts of site load to a target, kept so they can be verified and reversed
+CREATE TABLE IF NOT EXISTS curtailments (
+    id INTEGER PRIMARY KEY NOT NULL,
+    -- kW the scope is brought down to
+    target REAL NOT NULL,
+    -- JSON list of the nodes curtailed
+    scope TEXT NOT NULL,
+    -- kW of the scope before, and expected after, from the last scan
+    before REAL NOT NULL,
+    expected REAL NOT NULL,
+    -- kW reduction measured when verified
+    achieved REAL,
+    state TEXT NOT NULL,
+    created INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS curtailment_actions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    curtailment_id INTEGER NOT NULL REFERENCES curtailments(id) ON DELETE CASCADE,
+    ip TEXT NOT NULL,
+    -- JSON of what was done to the miner and how to undo it
+    action TEXT NOT NULL,
+    -- Watts before, expected after, and measured after
+    before REAL NOT NULL,
+    expected REAL NOT NULL,
+    after REAL,
+    error TEXT
+);
@@ -0,0 +1,25 @@
+-- Model of each miner, to look up its breakeven price
+ALTER TABLE miner_samples ADD COLUMN model TEXT;
+
+-- Electricity price schedule in $/MWh, one row per interval
+CREATE TABLE IF NOT EXISTS prices (
+    id INTEGER PRIMARY KEY NOT NULL,
+    start INTEGER NOT NULL UNIQUE,
+    end INTEGER NOT NULL,
+    price REAL NOT NULL
+);
+
+-- Every automatic sleep or wake and the price that triggered it
+CREATE TABLE IF NOT EXISTS price_decisions (
+    id INTEGER PRIMARY KEY NOT NULL,
+    decided INTEGER NOT NULL,
+    ip TEXT NOT NULL,
+    model TEXT NOT NULL,
+    sleep INTEGER NOT NULL,
+    price REAL NOT NULL,
+    breakeven REAL NOT NULL,
+    -- Start of the price interval
+    interval INTEGER NOT NULL,
+    -- Why the job couldn't be started, such as a circuit budget
+    error TEXT
+);
@@ -0,0 +1,22 @@
+-- Power of each miner over time, a row is only added when a miner's reading changes
+CREATE TABLE IF NOT EXISTS power_readings (
+    id INTEGER PRIMARY KEY NOT NULL,
+    ip TEXT NOT NULL,
+    sampled INTEGER NOT NULL,
+    online INTEGER NOT NULL,
+    power REAL
+);
+CREATE INDEX IF NOT EXISTS power_readings_ip ON power_readings (ip, sampled);
+CREATE INDEX IF NOT EXISTS power_readings_sampled ON power_readings (sampled);
+
+-- Jobs run against miners, monitoring scans aren't recorded
+CREATE TABLE IF NOT EXISTS job_runs (
+    id INTEGER PRIMARY KEY NOT NULL,
+    -- Job type such as Sleep or Curtail, and the whole job as JSON
+    job TEXT NOT NULL,
+    detail TEXT NOT NULL,
+    started INTEGER NOT NULL,
+    finished INTEGER,
+    tasks INTEGER NOT NULL,
+    failed INTEGER
+);
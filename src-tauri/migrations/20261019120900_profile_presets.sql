@@ -0,0 +1,15 @@
+-- Named profile presets, each maps miner models to the profile they run under it
+CREATE TABLE IF NOT EXISTS profile_presets (
+    id INTEGER PRIMARY KEY NOT NULL,
+    name TEXT NOT NULL UNIQUE
+);
+
+CREATE TABLE IF NOT EXISTS profile_preset_models (
+    id INTEGER PRIMARY KEY NOT NULL,
+    preset_id INTEGER NOT NULL REFERENCES profile_presets(id) ON DELETE CASCADE,
+    -- Model as the miner reports it, such as Antminer S19j Pro
+    model TEXT NOT NULL,
+    -- models::Profile as JSON
+    profile TEXT NOT NULL,
+    UNIQUE (preset_id, model)
+);
@@ -0,0 +1,10 @@
+-- Which actions of a curtailment took effect and which have been undone, so a cancelled or
+-- partly failed apply or reverse can be picked up again
+ALTER TABLE curtailment_actions ADD COLUMN applied BOOLEAN NOT NULL DEFAULT 0;
+ALTER TABLE curtailment_actions ADD COLUMN reversed BOOLEAN NOT NULL DEFAULT 0;
+
+UPDATE curtailment_actions SET applied = 1
+WHERE error IS NULL AND curtailment_id IN (SELECT id FROM curtailments WHERE state != 'applying');
+
+UPDATE curtailment_actions SET reversed = 1
+WHERE curtailment_id IN (SELECT id FROM curtailments WHERE state = 'reversed');
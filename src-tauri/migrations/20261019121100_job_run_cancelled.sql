@@ -0,0 +1,2 @@
+-- Tasks of a job run that were cancelled before they finished, kept apart from failures
+ALTER TABLE job_runs ADD COLUMN cancelled INTEGER;
@@ -0,0 +1,253 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+
+use crate::models::{Anomaly, AnomalyScope, Metric, MinerEvent, Miner};
+
+/// Need at least this many peers with a reading before comparing against them
+const MIN_PEERS: usize = 4;
+/// Robust z-score above which a reading is an outlier
+const MAX_SCORE: f64 = 3.5;
+
+impl Metric {
+    fn value(&self, miner: &Miner) -> Option<f64> {
+        let value = match self {
+            Metric::Temp => miner.temp,
+            Metric::Fan => miner.fan.as_ref()
+                .filter(|f| !f.is_empty())
+                .map(|f| f.iter().sum::<u32>() as f64 / f.len() as f64),
+            Metric::Hashrate => miner.hashrate,
+            Metric::Efficiency => miner.efficiency,
+        };
+        // Firmware occasionally reports NaN, which has no place in a median
+        value.filter(|v| v.is_finite())
+    }
+
+    /// Smallest deviation from the median worth reporting
+    /// Keeps tightly grouped peers from flagging noise
+    fn min_deviation(&self, median: f64) -> f64 {
+        match self {
+            Metric::Temp => 5.0,
+            Metric::Fan => 500.0,
+            Metric::Hashrate | Metric::Efficiency => median.abs() * 0.05,
+        }
+    }
+
+    /// Only deviations in the bad direction are flagged, except fans where
+    /// a slow fan is failing and a fast fan is compensating for something
+    fn is_bad(&self, deviation: f64) -> bool {
+        match self {
+            Metric::Temp | Metric::Efficiency => deviation > 0.0,
+            Metric::Hashrate => deviation < 0.0,
+            Metric::Fan => true,
+        }
+    }
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+    let mid = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// Compare each member of a peer group against the group's median
+/// Returns (index into events, anomaly) pairs
+fn outliers(events: &[MinerEvent], group: &[usize], metric: Metric, scope: AnomalyScope) -> Vec<(usize, Anomaly)> {
+    let readings = group.iter()
+        .filter_map(|&i| metric.value(&events[i].miner).map(|v| (i, v)))
+        .collect::<Vec<(usize, f64)>>();
+    if readings.len() < MIN_PEERS {
+        return vec![];
+    }
+
+    let mut values = readings.iter().map(|(_, v)| *v).collect::<Vec<f64>>();
+    let med = median(&mut values);
+    let mut deviations = values.iter().map(|v| (v - med).abs()).collect::<Vec<f64>>();
+    let mad = median(&mut deviations);
+
+    readings.into_iter().filter_map(|(i, value)| {
+        let deviation = value - med;
+        if !metric.is_bad(deviation) || deviation.abs() < metric.min_deviation(med) {
+            return None;
+        }
+        // With identical peers the MAD is zero, anything past the minimum deviation stands out
+        let score = if mad > 0.0 { 0.6745 * deviation / mad } else { f64::INFINITY.copysign(deviation) };
+        if score.abs() < MAX_SCORE {
+            return None;
+        }
+        Some((i, Anomaly {
+            metric,
+            scope,
+            value,
+            median: med,
+            score: if score.is_finite() { score } else { 0.0 },
+        }))
+    }).collect()
+}
+
+/// Flag statistical outliers against same model peers in the same rack and can
+/// Miners that are sleeping or not hashing are left out of the comparison
+pub fn annotate(events: &mut [MinerEvent]) {
+    let mut racks: HashMap<(i64, &str), Vec<usize>> = HashMap::new();
+    let mut cans: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, event) in events.iter().enumerate() {
+        let miner = &event.miner;
+        if miner.sleep || miner.hashrate.unwrap_or(0.0) <= 0.0 {
+            continue;
+        }
+        if let Some(model) = miner.model.as_deref() {
+            racks.entry((event.rack, model)).or_default().push(i);
+            cans.entry(model).or_default().push(i);
+        }
+    }
+
+    let mut found = vec![];
+    for metric in [Metric::Temp, Metric::Fan, Metric::Hashrate, Metric::Efficiency] {
+        let mut flagged = vec![];
+        for group in racks.values() {
+            flagged.extend(outliers(events, group, metric, AnomalyScope::Rack));
+        }
+        // Can wide comparisons only add miners their rack didn't already catch
+        for group in cans.values() {
+            for (i, anomaly) in outliers(events, group, metric, AnomalyScope::Can) {
+                if !flagged.iter().any(|(j, _)| *j == i) {
+                    flagged.push((i, anomaly));
+                }
+            }
+        }
+        found.extend(flagged);
+    }
+
+    for (i, anomaly) in found {
+        events[i].miner.anomalies.push(anomaly);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(rack: i64, model: &str, temp: f64, hashrate: f64) -> MinerEvent {
+        MinerEvent {
+            rack,
+            row: 0,
+            index: 0,
+            miner: Miner {
+                ip: String::new(),
+                external_id: None,
+                make: None,
+                model: Some(model.to_string()),
+                submodel: None,
+                mac: None,
+                hashrate: Some(hashrate),
+                temp: Some(temp),
+                fan: None,
+                uptime: None,
+                errors: vec![],
+                pools: vec![],
+                pool_stats: vec![],
+                power: None,
+                efficiency: None,
+                profile: None,
+                profiles: None,
+                hashboard: None,
+                boards: vec![],
+                conditions: vec![],
+                anomalies: vec![],
+                sleep: false,
+                locate: false,
+                nameplate: None,
+            },
+        }
+    }
+
+    #[test]
+    fn median_of_values() {
+        assert_eq!(median(&mut [3.0, 1.0, 2.0]), 2.0);
+        assert_eq!(median(&mut [4.0, 1.0, 3.0, 2.0]), 2.5);
+    }
+
+    #[test]
+    fn hot_miner() {
+        let mut events = [60.0, 61.0, 62.0, 60.5, 80.0].iter()
+            .map(|t| event(1, "S19", *t, 100.0))
+            .collect::<Vec<_>>();
+        annotate(&mut events);
+        assert!(events[..4].iter().all(|e| e.miner.anomalies.is_empty()));
+        let anomalies = &events[4].miner.anomalies;
+        assert_eq!(anomalies.len(), 1);
+        assert!(matches!(anomalies[0].metric, Metric::Temp));
+        assert!(matches!(anomalies[0].scope, AnomalyScope::Rack));
+        assert_eq!(anomalies[0].median, 61.0);
+    }
+
+    #[test]
+    fn only_bad_direction() {
+        // A cool miner or a fast one is nothing to worry about
+        let mut events = [60.0, 61.0, 62.0, 60.5, 40.0].iter()
+            .map(|t| event(1, "S19", *t, 100.0))
+            .chain([100.0, 101.0, 99.0, 100.5, 130.0].iter().map(|h| event(2, "S19", 60.0, *h)))
+            .collect::<Vec<_>>();
+        annotate(&mut events);
+        assert!(events.iter().all(|e| e.miner.anomalies.is_empty()));
+    }
+
+    #[test]
+    fn small_deviation() {
+        // Identical peers give a zero MAD, a reading inside the minimum deviation isn't flagged
+        let mut events = [60.0, 60.0, 60.0, 60.0, 64.0].iter()
+            .map(|t| event(1, "S19", *t, 100.0))
+            .collect::<Vec<_>>();
+        annotate(&mut events);
+        assert!(events.iter().all(|e| e.miner.anomalies.is_empty()));
+
+        events[4].miner.temp = Some(70.0);
+        annotate(&mut events);
+        assert_eq!(events[4].miner.anomalies.len(), 1);
+        assert_eq!(events[4].miner.anomalies[0].score, 0.0);
+    }
+
+    #[test]
+    fn peers_grouped_by_model() {
+        // Too few peers of each model to compare
+        let mut events = vec![
+            event(1, "S19", 60.0, 100.0),
+            event(1, "S19", 61.0, 100.0),
+            event(1, "S19", 80.0, 100.0),
+            event(1, "M30S", 60.0, 100.0),
+            event(1, "M30S", 61.0, 100.0),
+        ];
+        annotate(&mut events);
+        assert!(events.iter().all(|e| e.miner.anomalies.is_empty()));
+    }
+
+    #[test]
+    fn can_catches_what_rack_cannot() {
+        // Two miners per rack is too few to compare, the can has enough
+        let mut events = vec![
+            event(1, "S19", 60.0, 100.0),
+            event(1, "S19", 61.0, 100.0),
+            event(2, "S19", 60.5, 100.0),
+            event(2, "S19", 80.0, 100.0),
+            event(3, "S19", 62.0, 100.0),
+        ];
+        annotate(&mut events);
+        let anomalies = &events[3].miner.anomalies;
+        assert_eq!(anomalies.len(), 1);
+        assert!(matches!(anomalies[0].scope, AnomalyScope::Can));
+    }
+
+    #[test]
+    fn skips_idle_and_nan() {
+        let mut events = [60.0, 61.0, 62.0, 60.5, 80.0].iter()
+            .map(|t| event(1, "S19", *t, 100.0))
+            .collect::<Vec<_>>();
+        events[4].miner.sleep = true;
+        events[0].miner.temp = Some(f64::NAN);
+        annotate(&mut events);
+        assert!(events.iter().all(|e| e.miner.anomalies.is_empty()));
+    }
+}
@@ -0,0 +1,285 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Result;
+use serde_json::{json, Value};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+use crate::models::{Hashboard, PoolStatus};
+
+/// Port of the cgminer style JSON API exposed by Antminer and Whatsminer firmware
+pub const API_PORT: u16 = 4028;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Send a single command to the miner's cgminer API and return the parsed response
+/// `port` overrides the default API port for miners behind NAT
+pub async fn query(ip: &str, port: Option<u16>, command: &str) -> Result<Value> {
+    let request = json!({ "command": command }).to_string();
+    let response = timeout(TIMEOUT, async {
+        let mut stream = TcpStream::connect((ip, port.unwrap_or(API_PORT))).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await?;
+        Ok::<_, std::io::Error>(buf)
+    }).await??;
+
+    // Responses are NUL terminated and some firmwares emit invalid whitespace
+    let response = String::from_utf8_lossy(&response)
+        .trim_end_matches('\0')
+        .replace("\n", "")
+        .replace("}{", "},{");
+    let value: Value = serde_json::from_str(&response)?;
+
+    if let Some(status) = value["STATUS"].get(0) {
+        if status["STATUS"] == "E" {
+            return Err(anyhow::anyhow!("{}: {}", command, status["Msg"].as_str().unwrap_or("API error")));
+        }
+    }
+    Ok(value)
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(n) => n.as_f64(),
+        Value::String(s) => s.trim().parse().ok(),
+        _ => None,
+    }
+}
+
+/// Average a dash separated temperature list such as "40-42-55-57"
+fn avg_temps(value: &Value) -> Option<f64> {
+    if let Some(s) = value.as_str() {
+        let temps = s.split('-').filter_map(|t| t.trim().parse::<f64>().ok()).collect::<Vec<f64>>();
+        if temps.is_empty() {
+            None
+        } else {
+            Some(temps.iter().sum::<f64>() / temps.len() as f64)
+        }
+    } else {
+        as_f64(value)
+    }
+}
+
+fn max_temps(value: &Value) -> Option<f64> {
+    if let Some(s) = value.as_str() {
+        s.split('-').filter_map(|t| t.trim().parse::<f64>().ok()).reduce(f64::max)
+    } else {
+        as_f64(value)
+    }
+}
+
+/// Antminer firmware reports chains in the second STATS object as chain_rate1, chain_acn1, ...
+fn parse_antminer(stats: &Value) -> Vec<Hashboard> {
+    let mut boards = vec![];
+    for i in 1..=16 {
+        let rate = &stats[format!("chain_rate{}", i)];
+        let chips = &stats[format!("chain_acn{}", i)];
+        if rate.is_null() && chips.is_null() {
+            continue;
+        }
+        // Unpopulated chain slots are reported with empty values
+        if rate.as_str().map(|s| s.is_empty()).unwrap_or(false) && as_f64(chips).unwrap_or(0.0) == 0.0 {
+            continue;
+        }
+        boards.push(Hashboard {
+            index: i as u32 - 1,
+            // chain_rate is reported in GH/s
+            hashrate: as_f64(rate).map(|r| r / 1000.0),
+            chips: as_f64(chips).map(|c| c as u32),
+            expected_chips: None,
+            board_temp: avg_temps(&stats[format!("temp_pcb{}", i)])
+                .or_else(|| as_f64(&stats[format!("temp{}", i)])),
+            chip_temp: max_temps(&stats[format!("temp_chip{}", i)])
+                .or_else(|| as_f64(&stats[format!("temp2_{}", i)])),
+            freq: as_f64(&stats[format!("freq_avg{}", i)])
+                .or_else(|| as_f64(&stats[format!("freq{}", i)])),
+            serial: stats[format!("chain_sn{}", i)].as_str()
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string()),
+        });
+    }
+    boards
+}
+
+/// Whatsminer firmware reports one DEVS entry per slot
+fn parse_whatsminer(devs: &[Value]) -> Vec<Hashboard> {
+    devs.iter().enumerate().map(|(i, dev)| {
+        Hashboard {
+            index: dev["Slot"].as_u64().map(|s| s as u32).unwrap_or(i as u32),
+            // MHS av is reported in MH/s
+            hashrate: as_f64(&dev["MHS av"]).map(|r| r / 1_000_000.0),
+            chips: as_f64(&dev["Effective Chips"]).map(|c| c as u32),
+            expected_chips: None,
+            board_temp: as_f64(&dev["Temperature"]),
+            chip_temp: as_f64(&dev["Chip Temp Max"]),
+            freq: as_f64(&dev["Chip Frequency"]),
+            serial: dev["PCB SN"].as_str()
+                .filter(|s| !s.is_empty())
+                .map(|s| s.to_string()),
+        }
+    }).collect()
+}
+
+/// Find the per chain data in a STATS or DEVS response
+fn parse_boards(response: &Value) -> Vec<Hashboard> {
+    if let Some(stats) = response["STATS"].as_array() {
+        if let Some(chains) = stats.iter().find(|s| s.get("chain_acn1").is_some() || s.get("chain_rate1").is_some()) {
+            return parse_antminer(chains);
+        }
+    }
+    if let Some(devs) = response["DEVS"].as_array() {
+        return parse_whatsminer(devs);
+    }
+    vec![]
+}
+
+/// Last Share Time is a unix timestamp on most firmwares, Antminer reports the time since as H:MM:SS
+fn last_share_time(value: &Value) -> Option<i64> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH).ok()?.as_secs() as i64;
+    match value {
+        Value::Number(n) => n.as_i64().filter(|t| *t > 0),
+        Value::String(s) if s.contains(':') => {
+            let secs = s.split(':')
+                .map(|p| p.trim().parse::<i64>().ok())
+                .collect::<Option<Vec<i64>>>()?
+                .into_iter()
+                .fold(0, |acc, p| acc * 60 + p);
+            // 0:00:00 means no share has been submitted
+            if secs == 0 { None } else { Some(now - secs) }
+        },
+        Value::String(s) => s.trim().parse::<i64>().ok().filter(|t| *t > 0),
+        _ => None,
+    }
+}
+
+fn parse_pools(response: &Value) -> Vec<PoolStatus> {
+    let pools = match response["POOLS"].as_array() {
+        Some(pools) => pools,
+        None => return vec![],
+    };
+    pools.iter().map(|pool| {
+        PoolStatus {
+            url: pool["URL"].as_str().unwrap_or_default().to_string(),
+            user: pool["User"].as_str().unwrap_or_default().to_string(),
+            status: pool["Status"].as_str().unwrap_or("Unknown").to_string(),
+            active: pool["Stratum Active"].as_bool().unwrap_or(false),
+            accepted: as_f64(&pool["Accepted"]).unwrap_or(0.0) as u64,
+            rejected: as_f64(&pool["Rejected"]).unwrap_or(0.0) as u64,
+            stale: as_f64(&pool["Stale"]).unwrap_or(0.0) as u64,
+            last_share_time: last_share_time(&pool["Last Share Time"]),
+        }
+    }).filter(|pool| !pool.url.is_empty()).collect()
+}
+
+/// Per chain data and pool share counters, which libminer doesn't expose
+#[derive(Default)]
+pub struct Status {
+    pub boards: Vec<Hashboard>,
+    pub pools: Vec<PoolStatus>,
+}
+
+/// Query chains and pools in a single request
+/// Whatsminer firmware reports chains in DEVS, everything else in STATS
+pub async fn get_status(ip: &str, port: Option<u16>, make: Option<&str>) -> Result<Status> {
+    let chains = match make {
+        Some(make) if make.eq_ignore_ascii_case("whatsminer") => "devs",
+        _ => "stats",
+    };
+    let response = query(ip, port, &format!("{}+pools", chains)).await?;
+    Ok(Status {
+        // Joined commands answer with one single element list per command
+        boards: parse_boards(&response[chains][0]),
+        pools: parse_pools(&response["pools"][0]),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn antminer_chains() {
+        let response = json!({
+            "STATS": [
+                { "CGMiner": "4.9.0", "Type": "Antminer S19" },
+                {
+                    "chain_rate1": "31000.5", "chain_acn1": 76, "temp_pcb1": "40-42-55-57", "temp_chip1": "60-62-70-72",
+                    "freq_avg1": 650, "chain_sn1": "ABC123",
+                    "chain_rate2": "", "chain_acn2": 0,
+                    "chain_rate3": 30500, "chain_acn3": "74", "temp1": 50, "temp2_3": 65, "chain_sn3": "",
+                }
+            ]
+        });
+        let boards = parse_boards(&response);
+        assert_eq!(boards.len(), 2);
+        assert_eq!(boards[0].index, 0);
+        assert_eq!(boards[0].hashrate, Some(31.0005));
+        assert_eq!(boards[0].chips, Some(76));
+        assert_eq!(boards[0].board_temp, Some(48.5));
+        assert_eq!(boards[0].chip_temp, Some(72.0));
+        assert_eq!(boards[0].freq, Some(650.0));
+        assert_eq!(boards[0].serial.as_deref(), Some("ABC123"));
+        assert_eq!(boards[1].index, 2);
+        assert_eq!(boards[1].hashrate, Some(30.5));
+        assert_eq!(boards[1].chips, Some(74));
+        assert_eq!(boards[1].board_temp, None);
+        assert_eq!(boards[1].chip_temp, Some(65.0));
+        assert_eq!(boards[1].serial, None);
+    }
+
+    #[test]
+    fn whatsminer_devs() {
+        let response = json!({
+            "DEVS": [
+                { "Slot": 0, "MHS av": 28_000_000.0, "Effective Chips": 156, "Temperature": 70.5, "Chip Temp Max": 85.0, "PCB SN": "W1" },
+                { "Slot": 2, "MHS av": 0, "Effective Chips": 0, "Temperature": 40 },
+            ]
+        });
+        let boards = parse_boards(&response);
+        assert_eq!(boards.len(), 2);
+        assert_eq!(boards[0].hashrate, Some(28.0));
+        assert_eq!(boards[0].chips, Some(156));
+        assert_eq!(boards[0].serial.as_deref(), Some("W1"));
+        assert_eq!(boards[1].index, 2);
+        assert_eq!(boards[1].chips, Some(0));
+        assert_eq!(boards[1].chip_temp, None);
+    }
+
+    #[test]
+    fn no_chains() {
+        assert!(parse_boards(&json!({ "STATS": [{ "Type": "Antminer S9" }] })).is_empty());
+        assert!(parse_boards(&Value::Null).is_empty());
+    }
+
+    #[test]
+    fn pools() {
+        let response = json!({
+            "POOLS": [
+                {
+                    "URL": "stratum+tcp://pool:3333", "User": "worker.1", "Status": "Alive", "Stratum Active": true,
+                    "Accepted": 100, "Rejected": "5", "Stale": 1, "Last Share Time": 1700000000,
+                },
+                { "URL": "stratum+tcp://backup:3333", "User": "worker.1", "Status": "Dead", "Last Share Time": "0:00:00" },
+                { "URL": "", "User": "" },
+            ]
+        });
+        let pools = parse_pools(&response);
+        assert_eq!(pools.len(), 2);
+        assert!(pools[0].active);
+        assert_eq!((pools[0].accepted, pools[0].rejected, pools[0].stale), (100, 5, 1));
+        assert_eq!(pools[0].last_share_time, Some(1700000000));
+        assert!(!pools[1].active);
+        assert_eq!(pools[1].status, "Dead");
+        assert_eq!(pools[1].last_share_time, None);
+    }
+
+    #[test]
+    fn share_time_since() {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_secs() as i64;
+        let time = last_share_time(&json!("0:01:30")).unwrap();
+        assert!((now - 90 - time).abs() <= 1);
+        assert_eq!(last_share_time(&json!(0)), None);
+        assert_eq!(last_share_time(&json!("bad:time")), None);
+    }
+}
@@ -0,0 +1,346 @@
+//! Cut the load of a site, or part of it, to a target for demand response
+//!
+//! Plans are made from the last scan. Miners are downclocked to presets least efficient first,
+//! and when that isn't enough the least efficient are put to sleep. Miners already cut by a
+//! curtailment that hasn't been reversed are left alone. Applied plans are stored with what
+//! each miner ran before so they can be verified against fresh readings and reversed.
+
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use sqlx::sqlite::SqlitePool;
+
+use crate::db::{MinerSample, Node};
+use crate::models::Profile;
+
+/// What is done to a miner to cut its draw
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Action {
+    Sleep,
+    /// Switch to a lower power preset, `previous` is restored on reversal
+    Profile { profile: Profile, previous: Option<Profile> },
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PlannedAction {
+    pub ip: String,
+    pub action: Action,
+    /// Watts before and expected after
+    pub before: f64,
+    pub expected: f64,
+    /// J/TH the miner was running at
+    pub efficiency: Option<f64>,
+}
+
+/// What curtailing would do, nothing is applied
+#[derive(Serialize, Debug, Clone)]
+pub struct CurtailmentPlan {
+    /// kW the scope should be brought down to
+    pub target: f64,
+    /// kW of the scope now and once the plan is applied
+    pub before: f64,
+    pub expected: f64,
+    /// kW still over the target once every candidate is used
+    pub shortfall: f64,
+    /// Miners in scope without a reading to plan with
+    pub unknown: usize,
+    /// Miners in scope already cut by a curtailment that hasn't been reversed, left out of the plan
+    pub curtailed: usize,
+    pub actions: Vec<PlannedAction>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CurtailmentAction {
+    pub id: i64,
+    pub ip: String,
+    pub action: Action,
+    pub before: f64,
+    pub expected: f64,
+    /// Watts measured when verified
+    pub after: Option<f64>,
+    pub error: Option<String>,
+    /// The miner took the action, and later had it undone
+    pub applied: bool,
+    pub reversed: bool,
+}
+
+impl CurtailmentAction {
+    /// Still to be undone, actions cancelled part way are included as they may have taken effect
+    pub fn reversible(&self) -> bool {
+        !self.reversed && (self.applied || self.error.is_none())
+    }
+}
+
+/// A curtailment that was applied
+#[derive(Serialize, Debug, Clone)]
+pub struct Curtailment {
+    pub id: i64,
+    pub target: f64,
+    pub scope: Vec<Node>,
+    pub before: f64,
+    pub expected: f64,
+    /// kW reduction measured when verified
+    pub achieved: Option<f64>,
+    /// applying, applied, verified or reversed
+    /// Stays applying when the apply was cancelled, and applied or verified until every action is reversed
+    pub state: String,
+    pub created: i64,
+    pub actions: Vec<CurtailmentAction>,
+}
+
+pub const APPLYING: &str = "applying";
+pub const APPLIED: &str = "applied";
+pub const VERIFIED: &str = "verified";
+pub const REVERSED: &str = "reversed";
+
+/// Every miner below any of the nodes, once
+pub async fn scope_ips(db: &SqlitePool, scope: &[Node]) -> Result<Vec<String>> {
+    let mut seen = HashSet::new();
+    let mut ips = vec![];
+    for node in scope {
+        for ip in node.ips(db).await? {
+            if seen.insert(ip.clone()) {
+                ips.push(ip);
+            }
+        }
+    }
+    Ok(ips)
+}
+
+/// J/TH, from the miner when it reports it
+fn efficiency(sample: &MinerSample, power: f64) -> f64 {
+    match (sample.efficiency, sample.hashrate) {
+        (Some(efficiency), _) => efficiency,
+        (None, Some(hashrate)) if hashrate > 0.0 => power / hashrate,
+        // Drawing power without hashing is the worst there is
+        _ => f64::INFINITY,
+    }
+}
+
+/// Miners with actions of curtailments not yet reversed, and what they're expected to draw
+/// Their last scan may predate the curtailment so it can't be planned with
+pub async fn curtailed(db: &SqlitePool) -> Result<HashMap<String, f64>> {
+    let rows = sqlx::query!(
+        r#"
+        SELECT a.ip, a.expected
+        FROM curtailment_actions a
+        JOIN curtailments c ON a.curtailment_id = c.id
+        WHERE c.state != ? AND NOT a.reversed AND (a.applied OR a.error IS NULL)
+        "#,
+        REVERSED
+    ).fetch_all(db).await?;
+    Ok(rows.into_iter().map(|r| (r.ip, r.expected)).collect())
+}
+
+/// Plan bringing the scope down to `target` kW from the last scan
+pub async fn plan(db: &SqlitePool, target: f64, scope: &[Node]) -> Result<CurtailmentPlan> {
+    if target.is_nan() || target < 0.0 {
+        return Err(anyhow::anyhow!("Target must be 0 kW or more"));
+    }
+    if scope.is_empty() {
+        return Err(anyhow::anyhow!("Nothing to curtail"));
+    }
+    let ips = scope_ips(db, scope).await?.into_iter().collect::<HashSet<_>>();
+    let curtailed = curtailed(db).await?
+        .into_iter()
+        .filter(|(ip, _)| ips.contains(ip))
+        .collect::<HashMap<_, _>>();
+    let samples = MinerSample::all(db).await?
+        .into_iter()
+        .filter(|s| ips.contains(&s.ip) && !curtailed.contains_key(&s.ip))
+        .collect::<Vec<_>>();
+
+    let before = samples.iter()
+        .filter(|s| s.online)
+        .filter_map(|s| s.power)
+        .sum::<f64>() + curtailed.values().sum::<f64>();
+    let known = samples.iter().filter(|s| s.online && s.power.is_some()).count() + curtailed.len();
+    let unknown = ips.len() - known;
+
+    let mut candidates = samples.iter()
+        .filter(|s| s.online && !s.sleep)
+        .filter_map(|s| s.power.filter(|p| *p > 0.0).map(|p| (s, p, efficiency(s, p))))
+        .collect::<Vec<_>>();
+    candidates.sort_by(|a, b| b.2.partial_cmp(&a.2).unwrap_or(Ordering::Equal).then_with(|| a.0.ip.cmp(&b.0.ip)));
+
+    // Downclock first, each miner to the gentlest preset covering what's left or else its
+    // lowest, then sleep the least efficient until the target is met
+    let mut needed = before - target * 1000.0;
+    let mut actions: Vec<PlannedAction> = vec![];
+    for (sample, power, efficiency) in &candidates {
+        if needed <= 0.0 {
+            break;
+        }
+        let presets = sample.profiles.iter()
+            .flatten()
+            .filter_map(|p| match p {
+                Profile::Preset { power: preset, .. } if preset < power => Some((p, *preset)),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+        let gentlest = presets.iter()
+            .filter(|(_, preset)| power - preset >= needed)
+            .max_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        let lowest = presets.iter()
+            .min_by(|a, b| a.1.partial_cmp(&b.1).unwrap_or(Ordering::Equal));
+        if let Some((profile, expected)) = gentlest.or(lowest) {
+            needed -= power - expected;
+            actions.push(PlannedAction {
+                ip: sample.ip.clone(),
+                action: Action::Profile { profile: (*profile).clone(), previous: sample.profile.clone() },
+                before: *power,
+                expected: *expected,
+                efficiency: if efficiency.is_finite() { Some(*efficiency) } else { None },
+            });
+        }
+    }
+    for (sample, power, efficiency) in &candidates {
+        if needed <= 0.0 {
+            break;
+        }
+        match actions.iter_mut().find(|a| a.ip == sample.ip) {
+            Some(action) => {
+                needed -= action.expected;
+                action.action = Action::Sleep;
+                action.expected = 0.0;
+            },
+            None => {
+                needed -= power;
+                actions.push(PlannedAction {
+                    ip: sample.ip.clone(),
+                    action: Action::Sleep,
+                    before: *power,
+                    expected: 0.0,
+                    efficiency: if efficiency.is_finite() { Some(*efficiency) } else { None },
+                });
+            },
+        }
+    }
+
+    let cut = actions.iter().map(|a| a.before - a.expected).sum::<f64>();
+    Ok(CurtailmentPlan {
+        target,
+        before: before / 1000.0,
+        expected: (before - cut) / 1000.0,
+        shortfall: needed.max(0.0) / 1000.0,
+        unknown,
+        curtailed: curtailed.len(),
+        actions,
+    })
+}
+
+impl Curtailment {
+    /// Store a plan about to be applied, returns the ids of its actions in plan order
+    pub async fn create(db: &SqlitePool, plan: &CurtailmentPlan, scope: &[Node]) -> Result<(i64, Vec<i64>)> {
+        let scope = serde_json::to_string(scope)?;
+        let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let mut tx = db.begin().await?;
+        let id = sqlx::query!(
+            "INSERT INTO curtailments (target, scope, before, expected, state, created) VALUES (?, ?, ?, ?, ?, ?)",
+            plan.target, scope, plan.before, plan.expected, APPLYING, created
+        ).execute(&mut tx).await?.last_insert_rowid();
+        let mut action_ids = vec![];
+        for action in &plan.actions {
+            let json = serde_json::to_string(&action.action)?;
+            let res = sqlx::query!(
+                "INSERT INTO curtailment_actions (curtailment_id, ip, action, before, expected) VALUES (?, ?, ?, ?, ?)",
+                id, action.ip, json, action.before, action.expected
+            ).execute(&mut tx).await?;
+            action_ids.push(res.last_insert_rowid());
+        }
+        tx.commit().await?;
+        Ok((id, action_ids))
+    }
+
+    pub async fn get(db: &SqlitePool, id: i64) -> Result<Curtailment> {
+        let row = sqlx::query!(
+            "SELECT id, target, scope, before, expected, achieved, state, created FROM curtailments WHERE id = ?",
+            id
+        )
+            .fetch_optional(db).await?
+            .ok_or_else(|| anyhow::anyhow!("No curtailment with id {}", id))?;
+        let actions = sqlx::query!(
+            r#"SELECT id, ip, action, before, expected, after, error, applied AS "applied: bool", reversed AS "reversed: bool"
+            FROM curtailment_actions WHERE curtailment_id = ? ORDER BY id"#,
+            id
+        ).fetch_all(db).await?;
+        Ok(Curtailment {
+            id: row.id,
+            target: row.target,
+            scope: serde_json::from_str(&row.scope)?,
+            before: row.before,
+            expected: row.expected,
+            achieved: row.achieved,
+            state: row.state,
+            created: row.created,
+            actions: actions.into_iter().map(|a| Ok(CurtailmentAction {
+                id: a.id,
+                ip: a.ip,
+                action: serde_json::from_str(&a.action)?,
+                before: a.before,
+                expected: a.expected,
+                after: a.after,
+                error: a.error,
+                applied: a.applied,
+                reversed: a.reversed,
+            })).collect::<Result<_>>()?,
+        })
+    }
+
+    /// Every curtailment, newest first
+    pub async fn all(db: &SqlitePool) -> Result<Vec<Curtailment>> {
+        let ids = sqlx::query!("SELECT id FROM curtailments ORDER BY created DESC, id DESC")
+            .fetch_all(db).await?;
+        let mut curtailments = vec![];
+        for row in ids {
+            curtailments.push(Self::get(db, row.id).await?);
+        }
+        Ok(curtailments)
+    }
+
+    pub async fn set_state(db: &SqlitePool, id: i64, state: &str) -> Result<()> {
+        sqlx::query!("UPDATE curtailments SET state = ? WHERE id = ?", state, id)
+            .execute(db).await?;
+        Ok(())
+    }
+
+    /// Record an action as applied, or the error that kept it from applying
+    pub async fn action_applied(db: &SqlitePool, action_id: i64, error: Option<String>) -> Result<()> {
+        let applied = error.is_none();
+        sqlx::query!("UPDATE curtailment_actions SET error = ?, applied = ? WHERE id = ?", error, applied, action_id)
+            .execute(db).await?;
+        Ok(())
+    }
+
+    pub async fn action_reversed(db: &SqlitePool, action_id: i64) -> Result<()> {
+        sqlx::query!("UPDATE curtailment_actions SET reversed = 1 WHERE id = ?", action_id)
+            .execute(db).await?;
+        Ok(())
+    }
+
+    pub async fn action_after(db: &SqlitePool, action_id: i64, after: Option<f64>) -> Result<()> {
+        sqlx::query!("UPDATE curtailment_actions SET after = ? WHERE id = ?", after, action_id)
+            .execute(db).await?;
+        Ok(())
+    }
+
+    /// Sum the measured cut of every action and mark the curtailment verified
+    pub async fn verified(db: &SqlitePool, id: i64) -> Result<()> {
+        sqlx::query!(r#"
+            UPDATE curtailments SET
+                achieved = (
+                    SELECT SUM(before - after) / 1000.0 FROM curtailment_actions
+                    WHERE curtailment_id = ? AND after IS NOT NULL
+                ),
+                state = ?
+            WHERE id = ?
+            "#,
+            id, VERIFIED, id
+        ).execute(db).await?;
+        Ok(())
+    }
+}
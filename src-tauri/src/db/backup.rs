@@ -0,0 +1,340 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+use serde_json::{Map, Value};
+use sqlx::sqlite::{SqlitePool, SqliteRow};
+use sqlx::{Row, ValueRef, TypeInfo};
+use anyhow::Result;
+
+/// Bump when the archive layout changes
+const FORMAT: u32 = 1;
+
+/// Tables exported as the site layout, in insert order
+const LAYOUT_TABLES: &[&str] = &["areas", "cans", "racks", "miners", "hashboards", "pdus", "circuits", "circuit_feeds"];
+const CONFIG_TABLES: &[&str] = &["config", "profile_presets", "profile_preset_models"];
+/// Tables that only record what happened, optional in an archive
+const HISTORY_TABLES: &[&str] = &["unknown_hashboards", "miner_samples", "curtailments", "curtailment_actions", "prices", "price_decisions", "power_readings", "job_runs"];
+
+type Tables = BTreeMap<String, Vec<Map<String, Value>>>;
+
+/// Everything needed to rebuild a site in a single JSON document
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Backup {
+    pub format: u32,
+    /// Latest migration applied to the database the archive came from
+    pub schema: i64,
+    pub created: i64,
+    pub layout: Tables,
+    pub config: Tables,
+    pub history: Option<Tables>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct BackupFile {
+    pub path: String,
+    pub created: i64,
+    pub size: u64,
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+pub async fn schema_version(db: &SqlitePool) -> Result<i64> {
+    let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success = TRUE")
+        .fetch_one(db).await?;
+    Ok(version.unwrap_or(0))
+}
+
+fn row_to_json(row: &SqliteRow) -> Result<Map<String, Value>> {
+    let mut map = Map::new();
+    for (i, column) in row.columns().iter().enumerate() {
+        use sqlx::Column;
+        let raw = row.try_get_raw(i)?;
+        let value = if raw.is_null() {
+            Value::Null
+        } else {
+            match raw.type_info().name() {
+                "INTEGER" => Value::from(row.try_get_unchecked::<i64, _>(i)?),
+                "REAL" => Value::from(row.try_get_unchecked::<f64, _>(i)?),
+                "BLOB" => Value::from(row.try_get_unchecked::<Vec<u8>, _>(i)?),
+                _ => Value::from(row.try_get_unchecked::<String, _>(i)?),
+            }
+        };
+        map.insert(column.name().to_string(), value);
+    }
+    Ok(map)
+}
+
+async fn dump(db: &SqlitePool, tables: &[&str]) -> Result<Tables> {
+    let mut out = Tables::new();
+    for table in tables {
+        let rows = sqlx::query(&format!("SELECT * FROM {} ORDER BY id", table))
+            .fetch_all(db).await?;
+        out.insert(table.to_string(), rows.iter().map(row_to_json).collect::<Result<_>>()?);
+    }
+    Ok(out)
+}
+
+async fn columns(db: &SqlitePool, table: &str) -> Result<HashSet<String>> {
+    let rows = sqlx::query(&format!("SELECT name FROM pragma_table_info('{}')", table))
+        .fetch_all(db).await?;
+    Ok(rows.iter().map(|r| r.get::<String, _>("name")).collect())
+}
+
+fn ids(tables: &Tables, table: &str) -> HashSet<i64> {
+    tables.get(table)
+        .map(|rows| rows.iter().filter_map(|r| r.get("id").and_then(|v| v.as_i64())).collect())
+        .unwrap_or_default()
+}
+
+impl Backup {
+    pub async fn create(db: &SqlitePool, history: bool) -> Result<Self> {
+        Ok(Self {
+            format: FORMAT,
+            schema: schema_version(db).await?,
+            created: now(),
+            layout: dump(db, LAYOUT_TABLES).await?,
+            config: dump(db, CONFIG_TABLES).await?,
+            history: if history { Some(dump(db, HISTORY_TABLES).await?) } else { None },
+        })
+    }
+
+    pub async fn export(db: &SqlitePool, path: &str, history: bool) -> Result<()> {
+        let backup = Backup::create(db, history).await?;
+        std::fs::write(path, serde_json::to_string_pretty(&backup)?)?;
+        Ok(())
+    }
+
+    /// Check the archive can be restored into this database
+    /// Returns every problem found rather than stopping at the first
+    pub async fn validate(&self, db: &SqlitePool) -> Result<Vec<String>> {
+        let mut problems = vec![];
+        if self.format != FORMAT {
+            problems.push(format!("Unsupported archive format {}", self.format));
+        }
+        let schema = schema_version(db).await?;
+        if self.schema > schema {
+            problems.push(format!("Archive schema {} is newer than this database ({})", self.schema, schema));
+        }
+
+        let sections = [
+            (&self.layout, LAYOUT_TABLES),
+            (&self.config, CONFIG_TABLES),
+        ];
+        let history = self.history.as_ref().map(|h| (h, HISTORY_TABLES));
+        for (tables, expected) in sections.into_iter().chain(history) {
+            for (table, rows) in tables {
+                if !expected.contains(&table.as_str()) {
+                    problems.push(format!("Unexpected table {}", table));
+                    continue;
+                }
+                let known = columns(db, table).await?;
+                let unknown = rows.iter()
+                    .flat_map(|r| r.keys())
+                    .filter(|c| !known.contains(*c))
+                    .collect::<HashSet<_>>();
+                for column in unknown {
+                    problems.push(format!("Unknown column {}.{}", table, column));
+                }
+            }
+        }
+        for table in LAYOUT_TABLES {
+            if !self.layout.contains_key(*table) {
+                problems.push(format!("Missing table {}", table));
+            }
+        }
+
+        // Every rack, miner and circuit must point at something in the archive
+        let cans = ids(&self.layout, "cans");
+        let racks = ids(&self.layout, "racks");
+        let pdus = ids(&self.layout, "pdus");
+        let circuits = ids(&self.layout, "circuits");
+        let references = [
+            ("racks", "can_id", &cans),
+            ("miners", "rack_id", &racks),
+            ("circuits", "pdu_id", &pdus),
+            ("circuit_feeds", "circuit_id", &circuits),
+            ("circuit_feeds", "rack_id", &racks),
+        ];
+        for (table, key, parents) in references {
+            for row in self.layout.get(table).into_iter().flatten() {
+                let parent = row.get(key).and_then(|v| v.as_i64());
+                if !parent.map(|p| parents.contains(&p)).unwrap_or(false) {
+                    problems.push(format!("{} row {} references missing {} {:?}", table, row.get("id").unwrap_or(&Value::Null), key, parent));
+                }
+            }
+        }
+        let presets = ids(&self.config, "profile_presets");
+        for row in self.config.get("profile_preset_models").into_iter().flatten() {
+            let parent = row.get("preset_id").and_then(|v| v.as_i64());
+            if !parent.map(|p| presets.contains(&p)).unwrap_or(false) {
+                problems.push(format!("profile_preset_models row {} references missing preset_id {:?}", row.get("id").unwrap_or(&Value::Null), parent));
+            }
+        }
+        // Areas are optional, a missing parent or area is null
+        let areas = ids(&self.layout, "areas");
+        for (table, key) in [("areas", "parent_id"), ("cans", "area_id")] {
+            for row in self.layout.get(table).into_iter().flatten() {
+                if let Some(parent) = row.get(key).and_then(|v| v.as_i64()) {
+                    if !areas.contains(&parent) {
+                        problems.push(format!("{} row {} references missing {} {}", table, row.get("id").unwrap_or(&Value::Null), key, parent));
+                    }
+                }
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Replace the site's layout and config with the archive's, and its history if present
+    pub async fn restore(&self, db: &SqlitePool) -> Result<()> {
+        let problems = self.validate(db).await?;
+        if !problems.is_empty() {
+            return Err(anyhow::anyhow!("Invalid backup:\n{}", problems.join("\n")));
+        }
+
+        let mut sections = vec![(&self.layout, LAYOUT_TABLES), (&self.config, CONFIG_TABLES)];
+        if let Some(history) = &self.history {
+            sections.push((history, HISTORY_TABLES));
+        }
+
+        let mut tx = db.begin().await?;
+        // Children first so the deletes don't orphan anything
+        for (_, tables) in sections.iter().rev() {
+            for table in tables.iter().rev() {
+                sqlx::query(&format!("DELETE FROM {}", table)).execute(&mut tx).await?;
+            }
+        }
+        for (rows, tables) in &sections {
+            for table in tables.iter() {
+                for row in rows.get(*table).into_iter().flatten() {
+                    let cols = row.keys().cloned().collect::<Vec<String>>();
+                    let sql = format!(
+                        "INSERT INTO {} ({}) VALUES ({})",
+                        table,
+                        cols.join(", "),
+                        vec!["?"; cols.len()].join(", "),
+                    );
+                    let mut query = sqlx::query(&sql);
+                    for value in row.values() {
+                        query = match value {
+                            Value::Null => query.bind(None::<i64>),
+                            Value::Bool(b) => query.bind(*b),
+                            Value::Number(n) if n.is_i64() => query.bind(n.as_i64()),
+                            Value::Number(n) => query.bind(n.as_f64()),
+                            Value::String(s) => query.bind(s.clone()),
+                            Value::Array(a) => query.bind(a.iter().filter_map(|b| b.as_u64().map(|b| b as u8)).collect::<Vec<u8>>()),
+                            Value::Object(_) => query.bind(value.to_string()),
+                        };
+                    }
+                    query.execute(&mut tx).await?;
+                }
+            }
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn import(db: &SqlitePool, path: &str, history: bool) -> Result<()> {
+        let mut backup: Backup = serde_json::from_str(&std::fs::read_to_string(path)?)?;
+        if !history {
+            backup.history = None;
+        }
+        backup.restore(db).await
+    }
+}
+
+/// Automatic backups live in a backups directory next to the database
+fn backup_dir(db_path: &str) -> PathBuf {
+    Path::new(db_path).parent().unwrap_or(Path::new(".")).join("backups")
+}
+
+fn backup_prefix(db_path: &str) -> String {
+    let stem = Path::new(db_path).file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    format!("{}-", stem)
+}
+
+/// Creation time and counter of a backup file, the counter tells apart backups taken within the same second
+fn parse_name(name: &str, prefix: &str) -> Option<(i64, u32)> {
+    let stem = name.strip_prefix(prefix)?.strip_suffix(".db")?;
+    match stem.split_once('-') {
+        Some((created, seq)) => Some((created.parse().ok()?, seq.parse().ok()?)),
+        None => Some((stem.parse().ok()?, 0)),
+    }
+}
+
+/// Automatic backups of this database, newest first
+pub fn list(db_path: &str) -> Result<Vec<BackupFile>> {
+    let dir = backup_dir(db_path);
+    if !dir.exists() {
+        return Ok(vec![]);
+    }
+    let prefix = backup_prefix(db_path);
+    let mut files = vec![];
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name().to_string_lossy().to_string();
+        if let Some((created, seq)) = parse_name(&name, &prefix) {
+            files.push((seq, BackupFile {
+                path: entry.path().to_string_lossy().to_string(),
+                created,
+                size: entry.metadata()?.len(),
+            }));
+        }
+    }
+    files.sort_by_key(|(seq, b)| Reverse((b.created, *seq)));
+    Ok(files.into_iter().map(|(_, b)| b).collect())
+}
+
+/// Snapshot the database next to itself and keep only the newest `generations`
+pub async fn snapshot(db: &SqlitePool, db_path: &str, generations: usize) -> Result<String> {
+    let dir = backup_dir(db_path);
+    std::fs::create_dir_all(&dir)?;
+    let prefix = backup_prefix(db_path);
+    let created = now();
+    let mut path = dir.join(format!("{}{}.db", prefix, created));
+    let mut seq = 1;
+    while path.exists() {
+        path = dir.join(format!("{}{}-{}.db", prefix, created, seq));
+        seq += 1;
+    }
+    let path = path.to_string_lossy().to_string();
+    sqlx::query("VACUUM INTO ?").bind(&path).execute(db).await?;
+
+    for old in list(db_path)?.into_iter().skip(generations.max(1)) {
+        if let Err(e) = std::fs::remove_file(&old.path) {
+            tracing::warn!("Failed to remove old backup {}: {}", old.path, e);
+        }
+    }
+    Ok(path)
+}
+
+/// Take an automatic backup if the configured interval has passed since the last one
+pub async fn scheduled(db: &SqlitePool, db_path: &str) -> Result<Option<String>> {
+    let config: super::Config = super::settings::load(db).await?;
+    if config.backup_interval == 0 {
+        return Ok(None);
+    }
+    let last = list(db_path)?.first().map(|b| b.created).unwrap_or(0);
+    if now() - last < config.backup_interval as i64 * 3600 {
+        return Ok(None);
+    }
+    Ok(Some(snapshot(db, db_path, config.backup_generations).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn backup_names() {
+        assert_eq!(parse_name("site-1700000000.db", "site-"), Some((1700000000, 0)));
+        assert_eq!(parse_name("site-1700000000-2.db", "site-"), Some((1700000000, 2)));
+        assert_eq!(parse_name("site-1700000000.db-journal", "site-"), None);
+        assert_eq!(parse_name("other-1700000000.db", "site-"), None);
+        assert_eq!(parse_name("site-latest.db", "site-"), None);
+    }
+}
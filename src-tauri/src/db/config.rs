@@ -1,67 +1,103 @@
 use serde::{Serialize, Deserialize};
+use serde_json::Value;
 use sqlx::sqlite::SqlitePool;
 use anyhow::Result;
 
+use super::settings::{self, Setting};
+use super::vault::{Vault, MASK};
+
+/// Replace a stored secret with the mask so it never reaches the UI
+fn mask(secret: &str) -> String {
+    if secret.is_empty() { String::new() } else { MASK.to_string() }
+}
+
+/// Give the entries of a stored list ids from 1 in order
+fn number(mut value: Value, list: &str) -> Value {
+    if let Some(entries) = value[list].as_array_mut() {
+        for (i, entry) in entries.iter_mut().enumerate() {
+            entry["id"] = (i as i64 + 1).into();
+        }
+    }
+    value
+}
+
 #[derive(Deserialize, Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
 pub struct Config {
-    pub refreshRate: u64,
-    pub maxConnections: usize,
-    pub connectionTimeout: u64,
-    pub readTimeout: u64,
+    pub refresh_rate: u64,
+    pub max_connections: usize,
+    pub connection_timeout: u64,
+    pub read_timeout: u64,
+    /// Hours between automatic backups, 0 disables them
+    pub backup_interval: u64,
+    /// Number of automatic backups to keep
+    pub backup_generations: usize,
+    /// Watts assumed for miners that haven't reported their power
+    pub miner_power: f64,
+    /// Seconds between the stages of a job staged to stay within circuit budgets
+    pub stage_delay: u64,
 }
 
-impl Config {
-    pub fn new() -> Self {
+impl Default for Config {
+    fn default() -> Self {
         Self {
-            refreshRate: 30,
-            maxConnections: 500,
-            connectionTimeout: 10,
-            readTimeout: 15,
-        }
-    }
-
-    pub async fn load(db: &SqlitePool) -> Result<Self> {
-        let row = sqlx::query!("SELECT value FROM config WHERE key = 'config'")
-            .fetch_one(db)
-            .await;
-        match row {
-            Err(sqlx::Error::RowNotFound) | Err(sqlx::Error::Database(_)) => {
-                let default = Config::new();
-                sqlx::query!("CREATE TABLE IF NOT EXISTS config (
-                    id INTEGER PRIMARY KEY NOT NULL,
-                    key TEXT NOT NULL UNIQUE,
-                    value TEXT NOT NULL
-                );")
-                    .execute(db)
-                    .await?;
-                let serial = serde_json::to_string(&default)?;
-                sqlx::query!("INSERT INTO config (key, value) VALUES ('config', ?)",
-                    serial
-                )
-                    .execute(db)
-                    .await?;
-                Ok(default)
-            }
-            Ok(row) => {
-                Ok(serde_json::from_str(&row.value)?)
-            }
-            Err(e) => Err(e.into()),
+            refresh_rate: 30,
+            max_connections: 500,
+            connection_timeout: 10,
+            read_timeout: 15,
+            backup_interval: 24,
+            backup_generations: 7,
+            miner_power: 3500.0,
+            stage_delay: 120,
         }
     }
+}
 
-    pub async fn save(&self, db: &SqlitePool) -> Result<()> {
-        let serial = serde_json::to_string(self)?;
-        sqlx::query!("UPDATE config SET value = ? WHERE key = 'config'",
-            serial
-        )
-            .execute(db)
-            .await?;
+impl Setting for Config {
+    const KEY: &'static str = "config";
+    const VERSION: i64 = 3;
+
+    fn validate(&self) -> Result<()> {
+        if self.refresh_rate == 0 || self.connection_timeout == 0 || self.read_timeout == 0 {
+            return Err(anyhow::anyhow!("Refresh rate and timeouts must be at least 1 second"));
+        }
+        if self.max_connections == 0 {
+            return Err(anyhow::anyhow!("Max connections must be at least 1"));
+        }
+        if self.miner_power.is_nan() || self.miner_power <= 0.0 {
+            return Err(anyhow::anyhow!("Miner power estimate must be more than 0 watts"));
+        }
         Ok(())
     }
+
+    fn migrate(version: i64, mut value: Value) -> Result<Value> {
+        let default = Config::default();
+        // Version 2 added automatic backups
+        if version == 1 {
+            or_default(&mut value, "backupInterval", default.backup_interval);
+            or_default(&mut value, "backupGenerations", default.backup_generations);
+        }
+        // Version 3 added circuit budgets
+        if version <= 2 {
+            or_default(&mut value, "minerPower", default.miner_power);
+            or_default(&mut value, "stageDelay", default.stage_delay);
+        }
+        Ok(value)
+    }
+}
+
+/// Set a key added by a migration only when it's missing, so a value already there is kept
+fn or_default(value: &mut Value, key: &str, default: impl Into<Value>) {
+    if let Some(map) = value.as_object_mut() {
+        map.entry(key).or_insert_with(|| default.into());
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Pool {
+    /// Stable across renames so a masked password finds its stored value, 0 until saved
+    #[serde(default)]
+    pub id: i64,
     pub name: String,
     pub url1: String,
     pub url2: String,
@@ -70,95 +106,293 @@ pub struct Pool {
     pub password: Option<String>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+impl Pool {
+    /// Fill in the {can}, {model} and {ip} placeholders of the worker name
+    pub fn worker(&self, can: i64, model: &str, ip: &str) -> String {
+        let mut worker = self.username.clone();
+        if worker.contains("{can}") {
+            worker = worker.replace("{can}", can.to_string().as_str());
+        }
+        if worker.contains("{model}") {
+            worker = worker.replace("{model}", model);
+        }
+        if worker.contains("{ip}") {
+            // Take the last 2 octets of the IP address
+            let ip = ip.split('.').collect::<Vec<&str>>();
+            let ip = format!("{}x{}", ip[ip.len().saturating_sub(2)], ip[ip.len() - 1]);
+            worker = worker.replace("{ip}", &ip);
+        }
+        worker
+    }
+
+    /// Swap a masked password for the stored one of the same pool
+    pub fn unmask(&mut self, stored: &Pools) {
+        if self.password.as_deref() == Some(MASK) {
+            self.password = stored.pools.iter()
+                .find(|p| self.id != 0 && p.id == self.id)
+                .and_then(|p| p.password.clone());
+        }
+    }
+
+    /// Configured urls, skipping empty backup slots
+    pub fn urls(&self) -> Vec<&str> {
+        [&self.url1, &self.url2, &self.url3].into_iter()
+            .map(|u| u.trim())
+            .filter(|u| !u.is_empty())
+            .collect()
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct Pools {
     pub pools: Vec<Pool>,
 }
 
-impl Pools {
-    pub fn new() -> Self {
-        Self {
-            pools: Vec::new(),
-        }
-    }
-
-    pub async fn load(db: &SqlitePool) -> Result<Self> {
-        let row = sqlx::query!("SELECT value FROM config WHERE key = 'pools'")
-            .fetch_one(db)
-            .await;
-        match row {
-            Err(sqlx::Error::RowNotFound) => {
-                let default = Pools::new();
-                let serial = serde_json::to_string(&default)?;
-                sqlx::query!("INSERT INTO config (key, value) VALUES ('pools', ?)",
-                    serial
-                )
-                    .execute(db)
-                    .await?;
-                Ok(default)
-            },
-            Ok(row) => Ok(serde_json::from_str(&row.value)?),
-            Err(e) => Err(e.into()),
-        }
-    }
-
-    pub async fn save(&self, db: &SqlitePool) -> Result<()> {
-        let serial = serde_json::to_string(self)?;
-        sqlx::query!("UPDATE config SET value = ? WHERE key = 'pools'",
-            serial
-        )
-            .execute(db)
-            .await?;
+impl Setting for Pools {
+    const KEY: &'static str = "pools";
+    const VERSION: i64 = 2;
+
+    fn validate(&self) -> Result<()> {
+        for (i, pool) in self.pools.iter().enumerate() {
+            if pool.name.trim().is_empty() {
+                return Err(anyhow::anyhow!("Pool {} has no name", i + 1));
+            }
+            if self.pools[..i].iter().any(|p| p.name == pool.name) {
+                return Err(anyhow::anyhow!("Duplicate pool name {}", pool.name));
+            }
+        }
         Ok(())
     }
+
+    fn migrate(version: i64, value: Value) -> Result<Value> {
+        // Version 2 gave every pool an id
+        if version == 1 {
+            return Ok(number(value, "pools"));
+        }
+        Ok(value)
+    }
+}
+
+impl Pools {
+    /// Load pools with their passwords decrypted
+    pub async fn load(db: &SqlitePool, vault: &Vault) -> Result<Self> {
+        let mut pools: Self = settings::load(db).await?;
+        for pool in &mut pools.pools {
+            if let Some(password) = &pool.password {
+                pool.password = Some(vault.open(password)?);
+            }
+        }
+        Ok(pools)
+    }
+
+    /// Load pools with their passwords masked, doesn't need the vault unlocked
+    pub async fn load_masked(db: &SqlitePool) -> Result<Self> {
+        let mut pools: Self = settings::load(db).await?;
+        for pool in &mut pools.pools {
+            pool.password = pool.password.as_deref().map(mask);
+        }
+        Ok(pools)
+    }
+
+    /// Swap masked passwords for the stored ones of the same pools
+    pub fn unmask(&mut self, stored: &Pools) {
+        for pool in &mut self.pools {
+            pool.unmask(stored);
+        }
+    }
+
+    /// Save pools, encrypting their passwords once the site has a passphrase
+    /// Masked passwords keep the value already stored, new pools are given ids
+    pub async fn save(&self, db: &SqlitePool, vault: &Vault) -> Result<()> {
+        let mut pools = self.clone();
+        if pools.pools.iter().any(|p| p.password.as_deref() == Some(MASK)) {
+            pools.unmask(&Self::load(db, vault).await?);
+        }
+        let mut next = pools.pools.iter().map(|p| p.id).max().unwrap_or(0);
+        let encrypt = vault.status(db).await?.configured;
+        for pool in &mut pools.pools {
+            if pool.id == 0 {
+                next += 1;
+                pool.id = next;
+            }
+            if let (Some(password), true) = (&pool.password, encrypt) {
+                pool.password = Some(vault.seal(password)?);
+            }
+        }
+        settings::store(db, &pools).await
+    }
 }
 
 #[derive(Deserialize, Serialize, Debug, Clone)]
 pub struct Auth {
+    /// Stable across edits so a masked password finds its stored value, 0 until saved
+    #[serde(default)]
+    pub id: i64,
     pub make: String,
     pub username: String,
     pub password: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone)]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
 pub struct MinerAuth {
     pub auths: Vec<Auth>,
 }
 
-impl MinerAuth {
-    pub async fn load(db: &SqlitePool) -> Result<Self> {
-        let row = sqlx::query!("SELECT value FROM config WHERE key = 'miner_auth'")
-            .fetch_one(db)
-            .await;
-        match row {
-            Err(sqlx::Error::RowNotFound) => {
-                let default = MinerAuth {
-                    auths: Vec::new(),
-                };
-                let serial = serde_json::to_string(&default)?;
-                sqlx::query!("INSERT INTO config (key, value) VALUES ('miner_auth', ?)",
-                    serial
-                )
-                    .execute(db)
-                    .await?;
-                Ok(default)
-            },
-            Ok(row) => Ok(serde_json::from_str(&row.value)?),
-            Err(e) => Err(e.into()),
-        }
-    }
-
-    pub async fn save(&self, db: &SqlitePool) -> Result<()> {
-        let serial = serde_json::to_string(self)?;
-        sqlx::query!("UPDATE config SET value = ? WHERE key = 'miner_auth'",
-            serial
-        )
-            .execute(db)
-            .await?;
+impl Setting for MinerAuth {
+    const KEY: &'static str = "miner_auth";
+    const VERSION: i64 = 2;
+
+    fn validate(&self) -> Result<()> {
+        if self.auths.iter().any(|a| a.make.trim().is_empty()) {
+            return Err(anyhow::anyhow!("Miner credentials need a make"));
+        }
         Ok(())
     }
 
+    fn migrate(version: i64, value: Value) -> Result<Value> {
+        // Version 2 gave every credential an id
+        if version == 1 {
+            return Ok(number(value, "auths"));
+        }
+        Ok(value)
+    }
+}
+
+impl MinerAuth {
+    /// Load credentials with their passwords decrypted
+    pub async fn load(db: &SqlitePool, vault: &Vault) -> Result<Self> {
+        let mut auths: Self = settings::load(db).await?;
+        for auth in &mut auths.auths {
+            auth.password = vault.open(&auth.password)?;
+        }
+        Ok(auths)
+    }
+
+    /// Load credentials with their passwords masked, doesn't need the vault unlocked
+    pub async fn load_masked(db: &SqlitePool) -> Result<Self> {
+        let mut auths: Self = settings::load(db).await?;
+        for auth in &mut auths.auths {
+            auth.password = mask(&auth.password);
+        }
+        Ok(auths)
+    }
+
+    /// Swap masked passwords for the stored ones of the same credentials
+    pub fn unmask(&mut self, stored: &MinerAuth) {
+        for auth in &mut self.auths {
+            if auth.password == MASK {
+                auth.password = stored.auths.iter()
+                    .find(|a| auth.id != 0 && a.id == auth.id)
+                    .map(|a| a.password.clone())
+                    .unwrap_or_default();
+            }
+        }
+    }
+
+    /// Save credentials, encrypting their passwords once the site has a passphrase
+    /// Masked passwords keep the value already stored, new credentials are given ids
+    pub async fn save(&self, db: &SqlitePool, vault: &Vault) -> Result<()> {
+        let mut auths = self.clone();
+        if auths.auths.iter().any(|a| a.password == MASK) {
+            auths.unmask(&Self::load(db, vault).await?);
+        }
+        let mut next = auths.auths.iter().map(|a| a.id).max().unwrap_or(0);
+        let encrypt = vault.status(db).await?.configured;
+        for auth in &mut auths.auths {
+            if auth.id == 0 {
+                next += 1;
+                auth.id = next;
+            }
+            if encrypt {
+                auth.password = vault.seal(&auth.password)?;
+            }
+        }
+        settings::store(db, &auths).await
+    }
+
     pub fn get(&self, make: &str) -> Vec<&Auth> {
         self.auths.iter().filter(|a| a.make.eq_ignore_ascii_case(make)).collect()
     }
 }
+
+/// What a model earns and draws, for its breakeven electricity price
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct ModelEconomics {
+    pub model: String,
+    /// TH/s
+    pub hashrate: f64,
+    /// Watts
+    pub power: f64,
+}
+
+impl ModelEconomics {
+    /// $/MWh above which the model earns less than its electricity costs at `hashprice` $/TH/day
+    pub fn breakeven(&self, hashprice: f64) -> f64 {
+        let mwh_per_day = self.power * 24.0 / 1_000_000.0;
+        self.hashrate * hashprice / mwh_per_day
+    }
+}
+
+/// Automatic sleeping and waking of miners as the electricity price crosses their breakeven
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct Pricing {
+    pub enabled: bool,
+    /// Local HTTP endpoint serving the price schedule, empty when schedules are imported from files
+    pub url: String,
+    /// Minutes between fetches of the endpoint
+    pub refresh: u64,
+    /// Revenue in $ per TH/s per day
+    pub hashprice: f64,
+    /// Miners of models not listed are left alone
+    pub models: Vec<ModelEconomics>,
+}
+
+impl Default for Pricing {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            url: String::new(),
+            refresh: 15,
+            hashprice: 0.05,
+            models: vec![],
+        }
+    }
+}
+
+impl Setting for Pricing {
+    const KEY: &'static str = "pricing";
+
+    fn validate(&self) -> Result<()> {
+        let url = self.url.trim();
+        if !url.is_empty() && !url.starts_with("http://") {
+            return Err(anyhow::anyhow!("Price endpoint must be an http:// url"));
+        }
+        if self.refresh == 0 {
+            return Err(anyhow::anyhow!("Price refresh must be at least 1 minute"));
+        }
+        if self.hashprice.is_nan() || self.hashprice <= 0.0 {
+            return Err(anyhow::anyhow!("Hashprice must be more than 0"));
+        }
+        for (i, model) in self.models.iter().enumerate() {
+            if model.model.trim().is_empty() {
+                return Err(anyhow::anyhow!("Model {} has no name", i + 1));
+            }
+            if self.models[..i].iter().any(|m| m.model.eq_ignore_ascii_case(&model.model)) {
+                return Err(anyhow::anyhow!("Duplicate model {}", model.model));
+            }
+            if model.hashrate.is_nan() || model.hashrate <= 0.0 || model.power.is_nan() || model.power <= 0.0 {
+                return Err(anyhow::anyhow!("{} needs a hashrate and power above 0", model.model));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Pricing {
+    /// Breakeven $/MWh of a model, None when it isn't configured
+    pub fn breakeven(&self, model: &str) -> Option<f64> {
+        self.models.iter()
+            .find(|m| m.model.eq_ignore_ascii_case(model))
+            .map(|m| m.breakeven(self.hashprice))
+    }
+}
@@ -1,103 +1,113 @@
 use sqlx::sqlite::{SqliteConnectOptions, SqlitePoolOptions};
 use sqlx::sqlite::SqlitePool;
+use sqlx::migrate::{Migrate, MigrateError, Migrator};
+use anyhow::Result;
 
 pub mod models;
+pub mod backup;
 mod config;
+pub mod settings;
+mod vault;
+pub use models::area::{DbArea, Node, NodeSummary};
 pub use models::can::DbCan;
-pub use models::miner::DbMiner;
+pub use models::circuit::{DbPdu, DbCircuit, DbFeed, PowerMap};
+pub use models::miner::{DbMiner, Slot};
 pub use models::rack::DbRack;
-pub use config::{Config, Pools, Pool, Auth, MinerAuth};
+pub use models::numbering::Numbering;
+pub use models::sample::MinerSample;
+pub use models::preset::{ProfilePreset, ModelProfile};
+pub use models::job_run::JobRun;
+pub use models::hashboard::{DbHashboard, DbUnknownHashboard, HashboardCatalog};
+pub use config::{Config, Pools, Pool, Auth, MinerAuth, Pricing};
+pub use vault::{Vault, VaultStatus, LOCKED, MASK};
+pub use settings::Settings;
 
-pub async fn connect() -> Result<SqlitePool, sqlx::Error> {
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations");
+
+/// Migrations that `create_tables` used to apply by hand, before migrations were tracked
+const LEGACY_INIT: i64 = 20221019143341;
+const LEGACY_CONFIG: i64 = 20221025184658;
+const LEGACY_CANNUM: i64 = 20221031212952;
+
+pub async fn connect(path: &str) -> Result<SqlitePool> {
+    if let Some(dir) = std::path::Path::new(path).parent() {
+        std::fs::create_dir_all(dir)?;
+    }
     let opts = SqliteConnectOptions::new()
-        .filename("./scanner.db")
+        .filename(path)
         .create_if_missing(true);
     let pool = SqlitePoolOptions::new()
         .max_connections(5)
         .connect_with(opts)
         .await?;
 
-    let row = sqlx::query!("SELECT version FROM version")
-        .fetch_one(&pool).await;
-    if row.is_err() {
-        create_tables(&pool).await?;
+    adopt_legacy(&pool).await?;
+    match MIGRATOR.run(&pool).await {
+        Err(MigrateError::VersionMissing(version)) => {
+            return Err(anyhow::anyhow!(
+                "Database schema version {} is newer than this application supports",
+                version
+            ));
+        }
+        res => res?,
     }
     Ok(pool)
 }
 
-pub async fn create_tables(pool: &SqlitePool) -> Result<(), sqlx::Error> {
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS cans (
-            id INTEGER PRIMARY KEY NOT NULL,
-            name TEXT NOT NULL UNIQUE
-        );
-        "#,
-    )
-    .execute(pool)
-    .await?;
-
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS racks (
-            id INTEGER PRIMARY KEY NOT NULL,
-            can_id INTEGER NOT NULL,
-            name TEXT NOT NULL,
-            index_ INTEGER NOT NULL,
-            width INTEGER NOT NULL,
-            height INTEGER NOT NULL,
-            FOREIGN KEY (can_id) REFERENCES cans(id)
-            CONSTRAINT unique_rack UNIQUE (can_id, name)
-        );
-        "#,
-    )
-    .execute(pool)
-    .await?;
+/// A temporary file path unique to this test run
+#[cfg(test)]
+pub fn test_path(name: &str) -> String {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let n = NEXT.fetch_add(1, Ordering::Relaxed);
+    let path = std::env::temp_dir().join(format!("anttools-{}-{}-{}", std::process::id(), n, name));
+    let _ = std::fs::remove_file(&path);
+    path.to_string_lossy().to_string()
+}
 
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS miners (
-            id INTEGER PRIMARY KEY NOT NULL,
-            rack_id INTEGER NOT NULL,
-            ip TEXT NOT NULL,
-            row INTEGER NOT NULL,
-            index_ INTEGER NOT NULL,
-            FOREIGN KEY (rack_id) REFERENCES racks(id)
-            CONSTRAINT unique_miner UNIQUE (rack_id, row, index_)
-        );
-        "#,
-    )
-    .execute(pool)
-    .await?;
+/// A migrated database in a new temporary file
+#[cfg(test)]
+pub async fn test_db() -> SqlitePool {
+    connect(&test_path("test.db")).await.unwrap()
+}
 
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS version (
-            version INTEGER NOT NULL
-        )"#
-    )
-    .execute(pool)
-    .await?;
+async fn table_exists(pool: &SqlitePool, table: &str) -> Result<bool> {
+    let row = sqlx::query!("SELECT name FROM sqlite_master WHERE type = 'table' AND name = ?", table)
+        .fetch_optional(pool).await?;
+    Ok(row.is_some())
+}
 
-    sqlx::query(
-        r#"
-        INSERT INTO version (version) VALUES (1)
-        "#,
-    )
-    .execute(pool)
-    .await?;
+/// Databases created before migrations were tracked have a version table but no
+/// migration history. Record the migrations their schema already reflects so the
+/// migrator only applies what's missing.
+async fn adopt_legacy(pool: &SqlitePool) -> Result<()> {
+    if table_exists(pool, "_sqlx_migrations").await? || !table_exists(pool, "version").await? {
+        return Ok(());
+    }
+    tracing::info!("Adopting untracked database schema");
 
-    sqlx::query(
-        r#"
-        CREATE TABLE IF NOT EXISTS config (
-            id INTEGER PRIMARY KEY NOT NULL,
-            key TEXT NOT NULL UNIQUE,
-            value TEXT NOT NULL
-        );
-        "#
-    )
-    .execute(pool)
-    .await?;
+    let has_num = sqlx::query("SELECT num FROM cans LIMIT 1")
+        .fetch_optional(pool).await
+        .is_ok();
+    let mut applied = vec![LEGACY_INIT];
+    if table_exists(pool, "config").await? {
+        applied.push(LEGACY_CONFIG);
+    }
+    if has_num {
+        applied.push(LEGACY_CANNUM);
+    }
 
+    let mut conn = pool.acquire().await?;
+    conn.ensure_migrations_table().await?;
+    for migration in MIGRATOR.iter().filter(|m| applied.contains(&m.version)) {
+        sqlx::query(r#"
+            INSERT INTO _sqlx_migrations (version, description, success, checksum, execution_time)
+            VALUES (?, ?, TRUE, ?, 0)
+            "#)
+            .bind(migration.version)
+            .bind(&*migration.description)
+            .bind(&*migration.checksum)
+            .execute(&mut conn).await?;
+    }
     Ok(())
 }
@@ -0,0 +1,284 @@
+use std::collections::HashMap;
+
+use sqlx::sqlite::SqlitePool;
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+/// A grouping above cans, such as a building or a pod
+/// Areas nest under other areas, cans without an area sit directly under the site
+#[derive(Serialize, Debug, Clone)]
+pub struct DbArea {
+    pub id: i64,
+    pub parent_id: Option<i64>,
+    pub name: String,
+    /// What the area is to the site, such as building or pod
+    pub kind: String,
+}
+
+/// Any level of the site, for jobs and exports that can target all of it or part of it
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(tag = "kind", content = "id", rename_all = "snake_case")]
+pub enum Node {
+    Site,
+    Area(i64),
+    Can(i64),
+    Rack(i64),
+}
+
+impl DbArea {
+    pub async fn all(db: &SqlitePool) -> Result<Vec<DbArea>> {
+        let rows = sqlx::query!("SELECT id, parent_id, name, kind FROM areas ORDER BY name")
+            .fetch_all(db).await?;
+        Ok(rows.into_iter().map(|row| DbArea {
+            id: row.id,
+            parent_id: row.parent_id,
+            name: row.name,
+            kind: row.kind,
+        }).collect())
+    }
+
+    /// Names only need to be unique among siblings
+    async fn check_name(db: &SqlitePool, id: Option<i64>, parent_id: Option<i64>, name: &str) -> Result<()> {
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Area name is required"));
+        }
+        let existing = sqlx::query!(
+            "SELECT id FROM areas WHERE parent_id IS ? AND name = ? AND id IS NOT ?",
+            parent_id, name, id
+        ).fetch_optional(db).await?;
+        if existing.is_some() {
+            return Err(anyhow::anyhow!("Area {} already exists here", name));
+        }
+        Ok(())
+    }
+
+    /// Walk up from `parent_id` to make sure it exists and isn't `id` or below it
+    async fn check_parent(db: &SqlitePool, id: Option<i64>, parent_id: Option<i64>) -> Result<()> {
+        let parents = Self::all(db).await?
+            .into_iter()
+            .map(|a| (a.id, a.parent_id))
+            .collect::<HashMap<_, _>>();
+        let mut next = parent_id;
+        while let Some(current) = next {
+            if Some(current) == id {
+                return Err(anyhow::anyhow!("An area can't be moved inside itself"));
+            }
+            next = *parents.get(&current)
+                .ok_or_else(|| anyhow::anyhow!("No area with id {}", current))?;
+        }
+        Ok(())
+    }
+
+    pub async fn create(db: &SqlitePool, parent_id: Option<i64>, name: &str, kind: &str) -> Result<i64> {
+        let name = name.trim();
+        Self::check_parent(db, None, parent_id).await?;
+        Self::check_name(db, None, parent_id, name).await?;
+        let kind = kind.trim();
+        let res = sqlx::query!("INSERT INTO areas (parent_id, name, kind) VALUES (?, ?, ?)", parent_id, name, kind)
+            .execute(db).await?;
+        Ok(res.last_insert_rowid())
+    }
+
+    /// Rename an area, change its kind or move it under another parent
+    pub async fn update(db: &SqlitePool, id: i64, parent_id: Option<i64>, name: &str, kind: &str) -> Result<()> {
+        let name = name.trim();
+        Self::check_parent(db, Some(id), parent_id).await?;
+        Self::check_name(db, Some(id), parent_id, name).await?;
+        let kind = kind.trim();
+        let res = sqlx::query!(
+            "UPDATE areas SET parent_id = ?, name = ?, kind = ? WHERE id = ?",
+            parent_id, name, kind, id
+        ).execute(db).await?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No area with id {}", id));
+        }
+        Ok(())
+    }
+
+    /// Delete an area, its child areas and cans move up to its parent
+    pub async fn delete(db: &SqlitePool, id: i64) -> Result<()> {
+        let mut tx = db.begin().await?;
+        let area = sqlx::query!("SELECT parent_id FROM areas WHERE id = ?", id)
+            .fetch_optional(&mut tx).await?
+            .ok_or_else(|| anyhow::anyhow!("No area with id {}", id))?;
+        sqlx::query!("UPDATE areas SET parent_id = ? WHERE parent_id = ?", area.parent_id, id)
+            .execute(&mut tx).await?;
+        sqlx::query!("UPDATE cans SET area_id = ? WHERE area_id = ?", area.parent_id, id)
+            .execute(&mut tx).await?;
+        sqlx::query!("DELETE FROM areas WHERE id = ?", id)
+            .execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Put a can in an area, None puts it directly under the site
+    pub async fn assign_can(db: &SqlitePool, can_id: i64, area_id: Option<i64>) -> Result<()> {
+        Self::check_parent(db, None, area_id).await?;
+        let res = sqlx::query!("UPDATE cans SET area_id = ? WHERE id = ?", area_id, can_id)
+            .execute(db).await?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No can with id {}", can_id));
+        }
+        Ok(())
+    }
+}
+
+impl Node {
+    fn key(&self) -> (&'static str, i64) {
+        match self {
+            Node::Site => ("site", 0),
+            Node::Area(id) => ("area", *id),
+            Node::Can(id) => ("can", *id),
+            Node::Rack(id) => ("rack", *id),
+        }
+    }
+
+    /// Ids of every rack at or below this node
+    pub async fn racks(&self, db: &SqlitePool) -> Result<Vec<i64>> {
+        let (kind, id) = self.key();
+        let rows = sqlx::query!(r#"
+            WITH RECURSIVE tree(id) AS (
+                SELECT id FROM areas WHERE ? = 'area' AND id = ?
+                UNION
+                SELECT a.id FROM areas a JOIN tree t ON a.parent_id = t.id
+            )
+            SELECT r.id
+            FROM racks r
+            JOIN cans c ON r.can_id = c.id
+            WHERE ? = 'site'
+                OR (? = 'area' AND c.area_id IN (SELECT id FROM tree))
+                OR (? = 'can' AND c.id = ?)
+                OR (? = 'rack' AND r.id = ?)
+            "#,
+            kind, id, kind, kind, kind, id, kind, id
+        ).fetch_all(db).await?;
+        Ok(rows.into_iter().map(|r| r.id).collect())
+    }
+
+    /// IPs of every miner at or below this node
+    pub async fn ips(&self, db: &SqlitePool) -> Result<Vec<String>> {
+        let (kind, id) = self.key();
+        let rows = sqlx::query!(r#"
+            WITH RECURSIVE tree(id) AS (
+                SELECT id FROM areas WHERE ? = 'area' AND id = ?
+                UNION
+                SELECT a.id FROM areas a JOIN tree t ON a.parent_id = t.id
+            )
+            SELECT m.ip
+            FROM miners m
+            JOIN racks r ON m.rack_id = r.id
+            JOIN cans c ON r.can_id = c.id
+            WHERE m.ip != '' AND (
+                ? = 'site'
+                OR (? = 'area' AND c.area_id IN (SELECT id FROM tree))
+                OR (? = 'can' AND c.id = ?)
+                OR (? = 'rack' AND r.id = ?)
+            )
+            ORDER BY c.num, r.index_, m.row, m.index_
+            "#,
+            kind, id, kind, kind, kind, id, kind, id
+        ).fetch_all(db).await?;
+        Ok(rows.into_iter().map(|r| r.ip).collect())
+    }
+}
+
+/// Scan totals for the miners at or below a node
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Totals {
+    /// Miners placed in the layout
+    pub miners: usize,
+    /// Miners with a stored scan reading
+    pub sampled: usize,
+    pub online: usize,
+    pub healthy: usize,
+    pub hashrate: f64,
+    pub power: f64,
+}
+
+impl Totals {
+    fn add(&mut self, other: &Totals) {
+        self.miners += other.miners;
+        self.sampled += other.sampled;
+        self.online += other.online;
+        self.healthy += other.healthy;
+        self.hashrate += other.hashrate;
+        self.power += other.power;
+    }
+}
+
+/// A node of the site with its totals and the nodes below it
+#[derive(Serialize, Debug, Clone)]
+pub struct NodeSummary {
+    pub node: Node,
+    pub name: String,
+    /// Area kind, or site, can or rack
+    pub kind: String,
+    pub totals: Totals,
+    pub children: Vec<NodeSummary>,
+}
+
+impl NodeSummary {
+    fn new(node: Node, name: String, kind: &str, children: Vec<NodeSummary>) -> Self {
+        let mut totals = Totals::default();
+        for child in &children {
+            totals.add(&child.totals);
+        }
+        Self { node, name, kind: kind.to_string(), totals, children }
+    }
+
+    /// The whole site as a tree, summing the latest scan of every miner up through its rack, can and areas
+    pub async fn site(db: &SqlitePool, name: String) -> Result<NodeSummary> {
+        let mut racks: HashMap<i64, Totals> = HashMap::new();
+        let miners = sqlx::query!(r#"
+            SELECT m.rack_id, s.online AS "online?: bool", s.healthy AS "healthy?: bool", s.hashrate, s.power
+            FROM miners m
+            LEFT JOIN miner_samples s ON s.ip = m.ip
+            WHERE m.ip != ''
+            "#
+        ).fetch_all(db).await?;
+        for miner in miners {
+            let totals = racks.entry(miner.rack_id).or_default();
+            totals.miners += 1;
+            if let Some(online) = miner.online {
+                totals.sampled += 1;
+                totals.online += online as usize;
+                totals.healthy += miner.healthy.unwrap_or(false) as usize;
+                totals.hashrate += miner.hashrate.unwrap_or(0.0);
+                totals.power += miner.power.unwrap_or(0.0);
+            }
+        }
+
+        let rack_rows = sqlx::query!("SELECT id, can_id, name FROM racks ORDER BY index_")
+            .fetch_all(db).await?;
+        let mut cans: HashMap<i64, Vec<NodeSummary>> = HashMap::new();
+        for rack in rack_rows {
+            let mut summary = NodeSummary::new(Node::Rack(rack.id), rack.name, "rack", vec![]);
+            summary.totals = racks.remove(&rack.id).unwrap_or_default();
+            cans.entry(rack.can_id).or_default().push(summary);
+        }
+
+        let can_rows = sqlx::query!("SELECT id, area_id, name FROM cans ORDER BY num, name")
+            .fetch_all(db).await?;
+        let mut areas: HashMap<Option<i64>, Vec<NodeSummary>> = HashMap::new();
+        for can in can_rows {
+            let children = cans.remove(&can.id).unwrap_or_default();
+            areas.entry(can.area_id).or_default().push(NodeSummary::new(Node::Can(can.id), can.name, "can", children));
+        }
+
+        let all = DbArea::all(db).await?;
+        fn build(area: &DbArea, all: &[DbArea], cans: &mut HashMap<Option<i64>, Vec<NodeSummary>>) -> NodeSummary {
+            let mut children = all.iter()
+                .filter(|a| a.parent_id == Some(area.id))
+                .map(|a| build(a, all, cans))
+                .collect::<Vec<_>>();
+            children.extend(cans.remove(&Some(area.id)).unwrap_or_default());
+            NodeSummary::new(Node::Area(area.id), area.name.clone(), &area.kind, children)
+        }
+        let mut children = all.iter()
+            .filter(|a| a.parent_id.is_none())
+            .map(|a| build(a, &all, &mut areas))
+            .collect::<Vec<_>>();
+        children.extend(areas.remove(&None).unwrap_or_default());
+        Ok(NodeSummary::new(Node::Site, name, "site", children))
+    }
+}
@@ -3,24 +3,28 @@ use anyhow::Result;
 use serde::Serialize;
 
 use super::rack::DbRack;
+use super::unique_violation;
 
 #[derive(Serialize, Debug)]
 pub struct DbCan {
     pub id: i64,
     pub name: String,
     pub num: i64,
+    /// Area the can is in, None when it sits directly under the site
+    pub area_id: Option<i64>,
     pub racks: Vec<DbRack>,
 }
 
 impl DbCan {
     pub async fn all(db: &SqlitePool) -> Result<Vec<DbCan>> {
-        let rows = sqlx::query!("SELECT id, num, name FROM cans")
+        let rows = sqlx::query!("SELECT id, num, name, area_id FROM cans")
             .fetch_all(db).await?;
         let cans = rows.into_iter().map(|row| {
             DbCan {
                 id: row.id,
                 name: row.name,
                 num: row.num,
+                area_id: row.area_id,
                 racks: vec![],
             }
         }).collect();
@@ -33,29 +37,56 @@ impl DbCan {
         Ok(())
     }
 
-    pub async fn query_name(db: &SqlitePool, name: &str) -> Result<DbCan> {
-        let row = sqlx::query!("SELECT id, num, name FROM cans WHERE name = ?", name)
+    pub async fn get(db: &SqlitePool, id: i64) -> Result<DbCan> {
+        let row = sqlx::query!("SELECT id, num, name, area_id FROM cans WHERE id = ?", id)
             .fetch_one(db).await?;
         let mut can = DbCan {
             id: row.id,
             name: row.name,
             num: row.num,
+            area_id: row.area_id,
             racks: vec![],
         };
         can.load_racks(db).await?;
         Ok(can)
     }
 
-    pub async fn get(db: &SqlitePool, id: i64) -> Result<DbCan> {
-        let row = sqlx::query!("SELECT id, num, name FROM cans WHERE id = ?", id)
-            .fetch_one(db).await?;
-        let mut can = DbCan {
-            id: row.id,
-            name: row.name,
-            num: row.num,
-            racks: vec![],
-        };
-        can.load_racks(db).await?;
-        Ok(can)
+    pub async fn create(db: &SqlitePool, name: &str, num: i64) -> Result<i64> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Can name is required"));
+        }
+        let res = sqlx::query!("INSERT INTO cans (name, num) VALUES (?, ?)", name, num)
+            .execute(db).await
+            .map_err(|e| unique_violation(e, || format!("Can {} already exists", name)))?;
+        Ok(res.last_insert_rowid())
+    }
+
+    /// Rename a can or change its number
+    pub async fn update(db: &SqlitePool, id: i64, name: &str, num: i64) -> Result<()> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Can name is required"));
+        }
+        let res = sqlx::query!("UPDATE cans SET name = ?, num = ? WHERE id = ?", name, num, id)
+            .execute(db).await
+            .map_err(|e| unique_violation(e, || format!("Can {} already exists", name)))?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No can with id {}", id));
+        }
+        Ok(())
+    }
+
+    /// Delete a can along with its racks and miners
+    pub async fn delete(db: &SqlitePool, id: i64) -> Result<()> {
+        let mut tx = db.begin().await?;
+        sqlx::query!("DELETE FROM miners WHERE rack_id IN (SELECT id FROM racks WHERE can_id = ?)", id)
+            .execute(&mut tx).await?;
+        sqlx::query!("DELETE FROM racks WHERE can_id = ?", id)
+            .execute(&mut tx).await?;
+        sqlx::query!("DELETE FROM cans WHERE id = ?", id)
+            .execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
     }
 }
@@ -0,0 +1,322 @@
+use std::collections::HashMap;
+
+use sqlx::sqlite::SqlitePool;
+use anyhow::Result;
+use serde::Serialize;
+
+use super::numbering::Numbering;
+use super::unique_violation;
+
+/// A power distribution unit and the breakers on it
+#[derive(Serialize, Debug, Clone)]
+pub struct DbPdu {
+    pub id: i64,
+    pub name: String,
+    pub circuits: Vec<DbCircuit>,
+}
+
+/// A breaker and what it feeds
+#[derive(Serialize, Debug, Clone)]
+pub struct DbCircuit {
+    pub id: i64,
+    pub pdu_id: i64,
+    pub name: String,
+    /// Watts the breaker can carry
+    pub capacity: f64,
+    pub feeds: Vec<DbFeed>,
+    pub load: Load,
+}
+
+/// Slots of a rack fed by a circuit, the whole rack when `first` and `last` are None
+#[derive(Serialize, Debug, Clone)]
+pub struct DbFeed {
+    pub id: i64,
+    pub circuit_id: i64,
+    pub rack_id: i64,
+    /// Slot labels under the rack's numbering, inclusive
+    pub first: Option<i64>,
+    pub last: Option<i64>,
+}
+
+/// Power drawn through a circuit
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct Load {
+    pub miners: usize,
+    /// Miners without a power reading, counted at the estimate
+    pub estimated: usize,
+    pub watts: f64,
+}
+
+/// A circuit's budget and the miners it feeds, for checking jobs against it
+#[derive(Debug, Clone)]
+pub struct Circuit {
+    pub id: i64,
+    /// PDU and breaker, such as `PDU-1/CB3`
+    pub name: String,
+    pub capacity: f64,
+    pub ips: Vec<String>,
+}
+
+/// Which circuit feeds each miner and what each miner draws
+#[derive(Debug, Clone, Default)]
+pub struct PowerMap {
+    pub circuits: Vec<Circuit>,
+    /// Circuit id of every fed miner
+    pub feeds: HashMap<String, i64>,
+    /// Latest reported draw of miners that were online
+    pub reported: HashMap<String, f64>,
+}
+
+impl PowerMap {
+    pub async fn load(db: &SqlitePool) -> Result<PowerMap> {
+        let circuits = sqlx::query!(r#"
+            SELECT c.id, c.name, c.capacity, p.name AS pdu
+            FROM circuits c
+            JOIN pdus p ON c.pdu_id = p.id
+            ORDER BY p.name, c.name
+            "#
+        ).fetch_all(db).await?;
+        let feeds = sqlx::query!("SELECT circuit_id, rack_id, first, last FROM circuit_feeds")
+            .fetch_all(db).await?;
+        let racks = sqlx::query!("SELECT id, width, height, numbering FROM racks")
+            .fetch_all(db).await?
+            .into_iter()
+            .map(|r| Ok((r.id, (r.width, r.height, Numbering::parse(&r.numbering)?))))
+            .collect::<Result<HashMap<_, _>>>()?;
+        let miners = sqlx::query!("SELECT ip, rack_id, row, index_ FROM miners WHERE ip != ''")
+            .fetch_all(db).await?;
+
+        let mut map = PowerMap::default();
+        for miner in miners {
+            let (width, height, numbering) = match racks.get(&miner.rack_id) {
+                Some(rack) => rack,
+                None => continue,
+            };
+            let label = match numbering.label(*width, *height, miner.row, miner.index_) {
+                Ok(label) => label,
+                Err(_) => continue,
+            };
+            let feed = feeds.iter().find(|f| {
+                f.rack_id == miner.rack_id && match (f.first, f.last) {
+                    (Some(first), Some(last)) => first <= label && label <= last,
+                    _ => true,
+                }
+            });
+            if let Some(feed) = feed {
+                map.feeds.insert(miner.ip, feed.circuit_id);
+            }
+        }
+        map.circuits = circuits.into_iter().map(|c| Circuit {
+            ips: map.feeds.iter().filter(|(_, id)| **id == c.id).map(|(ip, _)| ip.clone()).collect(),
+            id: c.id,
+            name: format!("{}/{}", c.pdu, c.name),
+            capacity: c.capacity,
+        }).collect();
+
+        let samples = sqlx::query!(r#"SELECT ip, power FROM miner_samples WHERE online AND power IS NOT NULL"#)
+            .fetch_all(db).await?;
+        map.reported = samples.into_iter()
+            .filter_map(|s| s.power.map(|p| (s.ip, p)))
+            .collect();
+        Ok(map)
+    }
+
+    /// What a miner draws now, the estimate when it hasn't reported
+    pub fn draw(&self, ip: &str, estimate: f64) -> f64 {
+        self.reported.get(ip).copied().unwrap_or(estimate)
+    }
+
+    pub fn circuit_load(&self, circuit: &Circuit, estimate: f64) -> Load {
+        let mut load = Load { miners: circuit.ips.len(), ..Default::default() };
+        for ip in &circuit.ips {
+            if !self.reported.contains_key(ip) {
+                load.estimated += 1;
+            }
+            load.watts += self.draw(ip, estimate);
+        }
+        load
+    }
+}
+
+impl DbPdu {
+    /// Every PDU with its circuits, feeds and current loads
+    /// Miners that haven't reported their power are counted at `estimate` watts
+    pub async fn all(db: &SqlitePool, estimate: f64) -> Result<Vec<DbPdu>> {
+        let map = PowerMap::load(db).await?;
+        let mut feeds: HashMap<i64, Vec<DbFeed>> = HashMap::new();
+        let rows = sqlx::query!("SELECT id, circuit_id, rack_id, first, last FROM circuit_feeds ORDER BY rack_id, first")
+            .fetch_all(db).await?;
+        for row in rows {
+            feeds.entry(row.circuit_id).or_default().push(DbFeed {
+                id: row.id,
+                circuit_id: row.circuit_id,
+                rack_id: row.rack_id,
+                first: row.first,
+                last: row.last,
+            });
+        }
+        let mut circuits: HashMap<i64, Vec<DbCircuit>> = HashMap::new();
+        let rows = sqlx::query!("SELECT id, pdu_id, name, capacity FROM circuits ORDER BY name")
+            .fetch_all(db).await?;
+        for row in rows {
+            let load = map.circuits.iter()
+                .find(|c| c.id == row.id)
+                .map(|c| map.circuit_load(c, estimate))
+                .unwrap_or_default();
+            circuits.entry(row.pdu_id).or_default().push(DbCircuit {
+                id: row.id,
+                pdu_id: row.pdu_id,
+                name: row.name,
+                capacity: row.capacity,
+                feeds: feeds.remove(&row.id).unwrap_or_default(),
+                load,
+            });
+        }
+        let rows = sqlx::query!("SELECT id, name FROM pdus ORDER BY name")
+            .fetch_all(db).await?;
+        Ok(rows.into_iter().map(|row| DbPdu {
+            circuits: circuits.remove(&row.id).unwrap_or_default(),
+            id: row.id,
+            name: row.name,
+        }).collect())
+    }
+
+    pub async fn create(db: &SqlitePool, name: &str) -> Result<i64> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("PDU name is required"));
+        }
+        let res = sqlx::query!("INSERT INTO pdus (name) VALUES (?)", name)
+            .execute(db).await
+            .map_err(|e| unique_violation(e, || format!("PDU {} already exists", name)))?;
+        Ok(res.last_insert_rowid())
+    }
+
+    pub async fn rename(db: &SqlitePool, id: i64, name: &str) -> Result<()> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("PDU name is required"));
+        }
+        let res = sqlx::query!("UPDATE pdus SET name = ? WHERE id = ?", name, id)
+            .execute(db).await
+            .map_err(|e| unique_violation(e, || format!("PDU {} already exists", name)))?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No PDU with id {}", id));
+        }
+        Ok(())
+    }
+
+    /// Delete a PDU with its circuits and their feeds
+    pub async fn delete(db: &SqlitePool, id: i64) -> Result<()> {
+        sqlx::query!("DELETE FROM pdus WHERE id = ?", id)
+            .execute(db).await?;
+        Ok(())
+    }
+}
+
+impl DbCircuit {
+    fn check(name: &str, capacity: f64) -> Result<()> {
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Circuit name is required"));
+        }
+        if capacity.is_nan() || capacity <= 0.0 {
+            return Err(anyhow::anyhow!("Circuit capacity must be more than 0 watts"));
+        }
+        Ok(())
+    }
+
+    pub async fn create(db: &SqlitePool, pdu_id: i64, name: &str, capacity: f64) -> Result<i64> {
+        let name = name.trim();
+        Self::check(name, capacity)?;
+        let res = sqlx::query!(
+            "INSERT INTO circuits (pdu_id, name, capacity) SELECT id, ?, ? FROM pdus WHERE id = ?",
+            name, capacity, pdu_id
+        )
+            .execute(db).await
+            .map_err(|e| unique_violation(e, || format!("Circuit {} already exists on this PDU", name)))?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No PDU with id {}", pdu_id));
+        }
+        Ok(res.last_insert_rowid())
+    }
+
+    pub async fn update(db: &SqlitePool, id: i64, name: &str, capacity: f64) -> Result<()> {
+        let name = name.trim();
+        Self::check(name, capacity)?;
+        let res = sqlx::query!("UPDATE circuits SET name = ?, capacity = ? WHERE id = ?", name, capacity, id)
+            .execute(db).await
+            .map_err(|e| unique_violation(e, || format!("Circuit {} already exists on this PDU", name)))?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No circuit with id {}", id));
+        }
+        Ok(())
+    }
+
+    /// Delete a circuit and its feeds
+    pub async fn delete(db: &SqlitePool, id: i64) -> Result<()> {
+        sqlx::query!("DELETE FROM circuits WHERE id = ?", id)
+            .execute(db).await?;
+        Ok(())
+    }
+}
+
+impl DbFeed {
+    /// Feed a whole rack, or the slots labelled `first` to `last`, from a circuit
+    /// A slot can only be fed by one circuit
+    pub async fn create(db: &SqlitePool, circuit_id: i64, rack_id: i64, first: Option<i64>, last: Option<i64>) -> Result<i64> {
+        let rack = sqlx::query!("SELECT name, width, height FROM racks WHERE id = ?", rack_id)
+            .fetch_optional(db).await?
+            .ok_or_else(|| anyhow::anyhow!("No rack with id {}", rack_id))?;
+        let slots = rack.width * rack.height;
+        let (first, last) = match (first, last) {
+            (None, None) => (None, None),
+            (Some(first), Some(last)) if 1 <= first && first <= last && last <= slots => (Some(first), Some(last)),
+            (Some(first), Some(last)) => {
+                return Err(anyhow::anyhow!("Slots {}-{} aren't a range within rack {} (1-{})", first, last, rack.name, slots));
+            },
+            _ => return Err(anyhow::anyhow!("A slot range needs both a first and last slot")),
+        };
+        let range = |f: Option<i64>, l: Option<i64>| (f.unwrap_or(1), l.unwrap_or(slots));
+        let (start, end) = range(first, last);
+        let existing = sqlx::query!("SELECT first, last FROM circuit_feeds WHERE rack_id = ?", rack_id)
+            .fetch_all(db).await?;
+        for feed in existing {
+            let (other_start, other_end) = range(feed.first, feed.last);
+            if start <= other_end && other_start <= end {
+                return Err(anyhow::anyhow!("Rack {} slots {}-{} are already fed by a circuit", rack.name, other_start.max(start), other_end.min(end)));
+            }
+        }
+        let res = sqlx::query!(
+            "INSERT INTO circuit_feeds (circuit_id, rack_id, first, last) SELECT id, ?, ?, ? FROM circuits WHERE id = ?",
+            rack_id, first, last, circuit_id
+        ).execute(db).await?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No circuit with id {}", circuit_id));
+        }
+        Ok(res.last_insert_rowid())
+    }
+
+    /// Refuse to change a rack's size or numbering while circuits feed it by slot range
+    /// The ranges are slot labels, relabelling the rack would silently feed other slots
+    pub async fn check_relabel<'e, E>(db: E, rack_id: i64) -> Result<()>
+    where
+        E: sqlx::Executor<'e, Database = sqlx::Sqlite>,
+    {
+        let ranges = sqlx::query!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM circuit_feeds WHERE rack_id = ? AND first IS NOT NULL"#,
+            rack_id
+        ).fetch_one(db).await?;
+        if ranges.count > 0 {
+            return Err(anyhow::anyhow!(
+                "Circuits feed slot ranges of this rack, remove those feeds before changing its size or numbering"
+            ));
+        }
+        Ok(())
+    }
+
+    pub async fn delete(db: &SqlitePool, id: i64) -> Result<()> {
+        sqlx::query!("DELETE FROM circuit_feeds WHERE id = ?", id)
+            .execute(db).await?;
+        Ok(())
+    }
+}
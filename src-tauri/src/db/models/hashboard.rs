@@ -0,0 +1,188 @@
+use std::collections::HashMap;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sqlx::sqlite::SqlitePool;
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DbHashboard {
+    pub hashboard: String,
+    pub submodel: String,
+    pub chips: Option<i64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct DbUnknownHashboard {
+    pub hashboard: String,
+    pub model: Option<String>,
+    pub ip: String,
+    pub last_seen: i64,
+}
+
+impl DbHashboard {
+    pub async fn all(db: &SqlitePool) -> Result<Vec<DbHashboard>> {
+        let rows = sqlx::query!("SELECT hashboard, submodel, chips FROM hashboards ORDER BY hashboard")
+            .fetch_all(db).await?;
+        Ok(
+            rows.into_iter().map(|row| {
+                DbHashboard {
+                    hashboard: row.hashboard,
+                    submodel: row.submodel,
+                    chips: row.chips,
+                }
+            }).collect()
+        )
+    }
+
+    fn check(&self) -> Result<()> {
+        if self.hashboard.trim().is_empty() || self.submodel.trim().is_empty() {
+            return Err(anyhow::anyhow!("Hashboard and submodel are required"));
+        }
+        Ok(())
+    }
+
+    async fn upsert(&self, tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>) -> Result<()> {
+        sqlx::query!(r#"
+            INSERT INTO hashboards (hashboard, submodel, chips)
+            VALUES (?, ?, ?)
+            ON CONFLICT (hashboard) DO UPDATE SET submodel = excluded.submodel, chips = excluded.chips
+            "#,
+            self.hashboard,
+            self.submodel,
+            self.chips,
+        ).execute(&mut *tx).await?;
+        sqlx::query!("DELETE FROM unknown_hashboards WHERE hashboard = ?", self.hashboard)
+            .execute(&mut *tx).await?;
+        Ok(())
+    }
+
+    /// Insert or update a catalog entry, clearing it from the unknown list
+    pub async fn save(&self, db: &SqlitePool) -> Result<()> {
+        self.check()?;
+        let mut tx = db.begin().await?;
+        self.upsert(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn delete(db: &SqlitePool, hashboard: &str) -> Result<()> {
+        sqlx::query!("DELETE FROM hashboards WHERE hashboard = ?", hashboard)
+            .execute(db).await?;
+        Ok(())
+    }
+
+    /// Import a CSV with hashboard,submodel,chips columns
+    /// Every row is checked first and nothing is imported if any has a problem
+    /// Returns the number of entries imported
+    pub async fn import_csv(db: &SqlitePool, path: &str) -> Result<usize> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(true)
+            .from_path(path)?;
+        let headers = rdr.headers()?.clone();
+        let mut boards = vec![];
+        let mut problems = vec![];
+        let mut seen = HashMap::new();
+        for result in rdr.records() {
+            let record = match result {
+                Ok(record) => record,
+                Err(e) => {
+                    problems.push(e.to_string());
+                    continue;
+                },
+            };
+            let line = record.position().map(|p| p.line()).unwrap_or(0);
+            let board = match record.deserialize::<DbHashboard>(Some(&headers)) {
+                Ok(board) => board,
+                Err(e) => {
+                    problems.push(format!("line {}: {}", line, e));
+                    continue;
+                },
+            };
+            if let Err(e) = board.check() {
+                problems.push(format!("line {}: {}", line, e));
+            } else if let Some(first) = seen.insert(board.hashboard.clone(), line) {
+                problems.push(format!("line {}: Duplicate hashboard {}, first on line {}", line, board.hashboard, first));
+            } else {
+                boards.push(board);
+            }
+        }
+        if !problems.is_empty() {
+            return Err(anyhow::anyhow!("Nothing was imported:\n{}", problems.join("\n")));
+        }
+
+        let mut tx = db.begin().await?;
+        for board in &boards {
+            board.upsert(&mut tx).await?;
+        }
+        tx.commit().await?;
+        Ok(boards.len())
+    }
+
+    pub async fn export_csv(db: &SqlitePool, path: &str) -> Result<()> {
+        let mut wtr = csv::Writer::from_path(path)?;
+        for board in DbHashboard::all(db).await? {
+            wtr.serialize(board)?;
+        }
+        wtr.flush()?;
+        Ok(())
+    }
+}
+
+impl DbUnknownHashboard {
+    pub async fn all(db: &SqlitePool) -> Result<Vec<DbUnknownHashboard>> {
+        let rows = sqlx::query!("SELECT hashboard, model, ip, last_seen FROM unknown_hashboards ORDER BY last_seen DESC")
+            .fetch_all(db).await?;
+        Ok(
+            rows.into_iter().map(|row| {
+                DbUnknownHashboard {
+                    hashboard: row.hashboard,
+                    model: row.model,
+                    ip: row.ip,
+                    last_seen: row.last_seen,
+                }
+            }).collect()
+        )
+    }
+}
+
+/// In memory copy of the catalog used while scanning
+pub struct HashboardCatalog {
+    boards: HashMap<String, DbHashboard>,
+    db: SqlitePool,
+}
+
+impl HashboardCatalog {
+    pub async fn load(db: &SqlitePool) -> Result<Self> {
+        let boards = DbHashboard::all(db).await?
+            .into_iter()
+            .map(|b| (b.hashboard.clone(), b))
+            .collect();
+        Ok(Self {
+            boards,
+            db: db.clone(),
+        })
+    }
+
+    /// Look up a hashboard ID, recording it as unknown if it isn't in the catalog
+    pub async fn lookup(&self, hashboard: &str, model: Option<&str>, ip: &str) -> Option<&DbHashboard> {
+        let board = self.boards.get(hashboard);
+        if board.is_none() {
+            let now = SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0);
+            let res = sqlx::query!(r#"
+                INSERT INTO unknown_hashboards (hashboard, model, ip, last_seen)
+                VALUES (?, ?, ?, ?)
+                ON CONFLICT (hashboard) DO UPDATE SET model = excluded.model, ip = excluded.ip, last_seen = excluded.last_seen
+                "#,
+                hashboard,
+                model,
+                ip,
+                now,
+            ).execute(&self.db).await;
+            if let Err(e) = res {
+                tracing::error!("Failed to record unknown hashboard {}: {}", hashboard, e);
+            }
+        }
+        board
+    }
+}
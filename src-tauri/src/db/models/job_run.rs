@@ -0,0 +1,68 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use sqlx::sqlite::SqlitePool;
+use anyhow::Result;
+use serde::Serialize;
+
+/// A job that was run against miners
+#[derive(Serialize, Debug, Clone)]
+pub struct JobRun {
+    pub id: i64,
+    /// Job type such as Sleep or Curtail
+    pub job: String,
+    /// The whole job as JSON
+    pub detail: String,
+    pub started: i64,
+    /// None while running, or when the app closed before it finished
+    pub finished: Option<i64>,
+    pub tasks: i64,
+    pub failed: Option<i64>,
+    /// Tasks stopped by a cancel before they finished, None for runs recorded before this was kept
+    pub cancelled: Option<i64>,
+}
+
+fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+impl JobRun {
+    pub async fn start(db: &SqlitePool, job: &str, detail: &str, tasks: usize) -> Result<i64> {
+        let started = now();
+        let tasks = tasks as i64;
+        let res = sqlx::query!(
+            "INSERT INTO job_runs (job, detail, started, tasks) VALUES (?, ?, ?, ?)",
+            job, detail, started, tasks
+        ).execute(db).await?;
+        Ok(res.last_insert_rowid())
+    }
+
+    pub async fn finish(db: &SqlitePool, id: i64, failed: usize, cancelled: usize) -> Result<()> {
+        let finished = now();
+        let failed = failed as i64;
+        let cancelled = cancelled as i64;
+        sqlx::query!(
+            "UPDATE job_runs SET finished = ?, failed = ?, cancelled = ? WHERE id = ?",
+            finished, failed, cancelled, id
+        )
+            .execute(db).await?;
+        Ok(())
+    }
+
+    /// Jobs started between `start` and `end`, in order
+    pub async fn between(db: &SqlitePool, start: i64, end: i64) -> Result<Vec<JobRun>> {
+        let rows = sqlx::query!(
+            "SELECT id, job, detail, started, finished, tasks, failed, cancelled FROM job_runs WHERE started BETWEEN ? AND ? ORDER BY started, id",
+            start, end
+        ).fetch_all(db).await?;
+        Ok(rows.into_iter().map(|r| JobRun {
+            id: r.id,
+            job: r.job,
+            detail: r.detail,
+            started: r.started,
+            finished: r.finished,
+            tasks: r.tasks,
+            failed: r.failed,
+            cancelled: r.cancelled,
+        }).collect())
+    }
+}
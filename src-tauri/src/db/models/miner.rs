@@ -1,83 +1,169 @@
+use std::collections::HashMap;
+
+use std::net::Ipv4Addr;
+
 use sqlx::sqlite::{SqlitePool};
 use anyhow::Result;
-use serde::Serialize;
+use serde::{Serialize, Deserialize};
 
 use super::rack::DbRack;
+use super::unique_violation;
 
 #[derive(Serialize, Debug)]
 pub struct DbMiner {
-    #[serde(skip)]
-    pub id: i64,
-    #[serde(skip)]
-    pub rack_id: i64,
     pub ip: String,
     #[serde(skip)]
     pub row: i64,
     #[serde(skip)]
     pub index: i64,
+    /// cgminer API port, None uses the default. libminer always connects on its own ports
+    pub port: Option<u16>,
+    /// Reference to the miner in the tool the layout was imported from
+    pub external_id: Option<String>,
+}
+
+/// Where a miner sits, as displayed to the user
+#[derive(Debug, Clone)]
+pub struct MinerLocation {
+    pub can: i64,
+    pub rack: i64,
+    pub row: i64,
+    pub index: i64,
+    pub port: Option<u16>,
+    pub external_id: Option<String>,
+}
+
+/// A position in a rack
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Slot {
+    pub rack_id: i64,
+    pub row: i64,
+    pub index: i64,
+}
+
+impl Slot {
+    async fn check(&self, db: &SqlitePool) -> Result<DbRack> {
+        let rack = DbRack::get(db, self.rack_id).await?;
+        if self.row < 0 || self.row >= rack.height || self.index < 0 || self.index >= rack.width {
+            return Err(anyhow::anyhow!(
+                "Slot {}-{} is outside rack {} ({}x{})",
+                self.row, self.index, rack.name, rack.width, rack.height
+            ));
+        }
+        Ok(rack)
+    }
 }
 
 impl DbMiner {
-    pub async fn all(db: &SqlitePool) -> Result<Vec<DbMiner>> {
-        let rows = sqlx::query!("SELECT id, rack_id, ip, row, index_ FROM miners")
-            .fetch_all(db).await?;
-        Ok(
-            rows.into_iter().map(|row| {
-                DbMiner {
-                    id: row.id,
-                    rack_id: row.rack_id,
-                    ip: row.ip,
-                    row: row.row,
-                    index: row.index_,
-                }
-            }).collect()
-        )
+    /// Placeholder for a slot with no miner
+    pub fn empty(row: i64, index: i64) -> Self {
+        Self {
+            ip: String::new(),
+            row,
+            index,
+            port: None,
+            external_id: None,
+        }
+    }
+
+    /// Miners in every rack of a can, grouped by rack id
+    pub async fn query_can(db: &SqlitePool, can_id: i64) -> Result<HashMap<i64, Vec<DbMiner>>> {
+        let rows = sqlx::query!(r#"
+            SELECT m.rack_id, m.ip, m.row, m.index_, m.port, m.external_id
+            FROM miners m
+            JOIN racks r ON m.rack_id = r.id
+            WHERE r.can_id = ?
+            ORDER BY m.rack_id, m.row, m.index_
+            "#,
+            can_id
+        ).fetch_all(db).await?;
+        let mut racks: HashMap<i64, Vec<DbMiner>> = HashMap::new();
+        for row in rows {
+            racks.entry(row.rack_id).or_default().push(DbMiner {
+                ip: row.ip,
+                row: row.row,
+                index: row.index_,
+                port: row.port.map(|p| p as u16),
+                external_id: row.external_id,
+            });
+        }
+        Ok(racks)
     }
 
-    pub async fn query_rack(db: &SqlitePool, rack_id: i64) -> Result<Vec<DbMiner>> {
-        let mut rows = sqlx::query!("SELECT id, rack_id, ip, row, index_ FROM miners WHERE rack_id = ? ORDER BY row, index_", rack_id)
-            .fetch_all(db).await?;
+    /// Resolve every miner's IP to its location in a single query
+    pub async fn locations(db: &SqlitePool) -> Result<HashMap<String, MinerLocation>> {
+        let rows = sqlx::query!(r#"
+            SELECT m.ip, m.row, m.index_, m.port, m.external_id, r.index_ AS rack, c.num AS can
+            FROM miners m
+            JOIN racks r ON m.rack_id = r.id
+            JOIN cans c ON r.can_id = c.id
+            "#
+        ).fetch_all(db).await?;
         Ok(
             rows.into_iter().map(|row| {
-                DbMiner {
-                    id: row.id,
-                    rack_id: row.rack_id,
-                    ip: row.ip,
+                (row.ip, MinerLocation {
+                    can: row.can,
+                    rack: row.rack,
                     row: row.row,
                     index: row.index_,
-                }
+                    port: row.port.map(|p| p as u16),
+                    external_id: row.external_id,
+                })
             }).collect()
         )
     }
 
-    pub async fn insert(db: &SqlitePool, miner: &DbMiner) -> Result<()> {
-        sqlx::query!(
-            "INSERT INTO miners (rack_id, ip, row, index_) VALUES (?, ?, ?, ?)",
-            miner.rack_id,
-            miner.ip,
-            miner.row,
-            miner.index,
-        )
-        .execute(db)
-        .await?;
+    /// Put an IP in a slot, replacing whatever was there
+    /// The IP can't already be assigned to another slot. A new IP drops the old miner's port and external id.
+    pub async fn assign(db: &SqlitePool, slot: Slot, ip: &str) -> Result<()> {
+        let ip = ip.trim().parse::<Ipv4Addr>()
+            .map_err(|_| anyhow::anyhow!("{} is not a valid IP address", ip.trim()))?
+            .to_string();
+        slot.check(db).await?;
+        let existing = sqlx::query!(r#"
+            SELECT r.name AS rack, m.row, m.index_
+            FROM miners m
+            JOIN racks r ON m.rack_id = r.id
+            WHERE m.ip = ? AND NOT (m.rack_id = ? AND m.row = ? AND m.index_ = ?)
+            "#,
+            ip, slot.rack_id, slot.row, slot.index
+        ).fetch_optional(db).await?;
+        if let Some(existing) = existing {
+            return Err(anyhow::anyhow!("{} is already in rack {} slot {}-{}", ip, existing.rack, existing.row, existing.index_));
+        }
+
+        sqlx::query!(r#"
+            INSERT INTO miners (rack_id, ip, row, index_) VALUES (?, ?, ?, ?)
+            ON CONFLICT (rack_id, row, index_) DO UPDATE SET
+                port = CASE WHEN ip = excluded.ip THEN port END,
+                external_id = CASE WHEN ip = excluded.ip THEN external_id END,
+                ip = excluded.ip
+            "#,
+            slot.rack_id, ip, slot.row, slot.index
+        ).execute(db).await?;
         Ok(())
     }
 
-    pub async fn find_ip(db: &SqlitePool, ip: &str) -> Result<DbMiner> {
-        let row = sqlx::query!("SELECT id, rack_id, ip, row, index_ FROM miners WHERE ip = ?", ip)
-            .fetch_one(db).await?;
-        Ok(
-            DbMiner {
-                id: row.id,
-                rack_id: row.rack_id,
-                ip: row.ip,
-                row: row.row,
-                index: row.index_,
-            }
+    /// Move the miner in one slot to an empty slot, possibly in another rack
+    pub async fn move_slot(db: &SqlitePool, from: Slot, to: Slot) -> Result<()> {
+        to.check(db).await?;
+        let res = sqlx::query!(
+            "UPDATE miners SET rack_id = ?, row = ?, index_ = ? WHERE rack_id = ? AND row = ? AND index_ = ?",
+            to.rack_id, to.row, to.index, from.rack_id, from.row, from.index
         )
+            .execute(db).await
+            .map_err(|e| unique_violation(e, || format!("Slot {}-{} is already occupied", to.row, to.index)))?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No miner in slot {}-{}", from.row, from.index));
+        }
+        Ok(())
     }
 
-    pub async fn get_rack(&self, db: &SqlitePool) -> Result<DbRack> {
-        DbRack::get(db, self.rack_id).await
+    pub async fn clear(db: &SqlitePool, slot: Slot) -> Result<()> {
+        sqlx::query!(
+            "DELETE FROM miners WHERE rack_id = ? AND row = ? AND index_ = ?",
+            slot.rack_id, slot.row, slot.index
+        ).execute(db).await?;
+        Ok(())
     }
 }
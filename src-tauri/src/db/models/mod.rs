@@ -1,3 +1,20 @@
+pub mod area;
 pub mod can;
+pub mod circuit;
+pub mod hashboard;
+pub mod job_run;
 pub mod miner;
+pub mod numbering;
+pub mod preset;
 pub mod rack;
+pub mod sample;
+
+/// Turn a unique constraint failure into a readable error, other errors pass through
+pub(crate) fn unique_violation(e: sqlx::Error, msg: impl FnOnce() -> String) -> anyhow::Error {
+    match &e {
+        sqlx::Error::Database(err) if err.message().contains("UNIQUE constraint failed") => {
+            anyhow::anyhow!(msg())
+        },
+        _ => e.into(),
+    }
+}
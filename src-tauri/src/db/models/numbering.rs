@@ -0,0 +1,148 @@
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+
+/// Corner of the rack holding slot 1
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Corner {
+    #[default]
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    fn as_str(self) -> &'static str {
+        match self {
+            Corner::TopLeft => "top_left",
+            Corner::TopRight => "top_right",
+            Corner::BottomLeft => "bottom_left",
+            Corner::BottomRight => "bottom_right",
+        }
+    }
+}
+
+/// How the slots of a rack are labelled for people
+/// Slots are counted a shelf at a time from `start`, serpentine racks reverse direction on every other shelf
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Numbering {
+    pub start: Corner,
+    pub serpentine: bool,
+}
+
+const SERPENTINE: &str = "_serpentine";
+
+impl Numbering {
+    /// Read the value stored in the racks table, such as `bottom_right_serpentine`
+    pub fn parse(value: &str) -> Result<Self> {
+        let (corner, serpentine) = match value.strip_suffix(SERPENTINE) {
+            Some(corner) => (corner, true),
+            None => (value, false),
+        };
+        let start = [Corner::TopLeft, Corner::TopRight, Corner::BottomLeft, Corner::BottomRight]
+            .into_iter()
+            .find(|c| c.as_str() == corner)
+            .ok_or_else(|| anyhow::anyhow!("Unknown slot numbering {}", value))?;
+        Ok(Self { start, serpentine })
+    }
+
+    pub fn to_db(self) -> String {
+        let suffix = if self.serpentine { SERPENTINE } else { "" };
+        format!("{}{}", self.start.as_str(), suffix)
+    }
+
+    /// Shelf counted from the starting corner and whether it's walked right to left
+    fn shelf(&self, height: i64, row: i64) -> (i64, bool) {
+        let shelf = match self.start {
+            Corner::TopLeft | Corner::TopRight => row,
+            Corner::BottomLeft | Corner::BottomRight => height - 1 - row,
+        };
+        let from_right = matches!(self.start, Corner::TopRight | Corner::BottomRight);
+        (shelf, from_right != (self.serpentine && shelf % 2 == 1))
+    }
+
+    /// Label of the slot at `row` and `index`, from 1
+    pub fn label(&self, width: i64, height: i64, row: i64, index: i64) -> Result<i64> {
+        if row < 0 || row >= height || index < 0 || index >= width {
+            return Err(anyhow::anyhow!("Slot {}-{} is outside a {}x{} rack", row, index, width, height));
+        }
+        let (shelf, from_right) = self.shelf(height, row);
+        let step = if from_right { width - 1 - index } else { index };
+        Ok(shelf * width + step + 1)
+    }
+
+    /// Row and index of the slot with a label
+    pub fn position(&self, width: i64, height: i64, label: i64) -> Result<(i64, i64)> {
+        if label < 1 || label > width * height {
+            return Err(anyhow::anyhow!("Slot {} is outside a {}x{} rack", label, width, height));
+        }
+        let shelf = (label - 1) / width;
+        let step = (label - 1) % width;
+        let row = match self.start {
+            Corner::TopLeft | Corner::TopRight => shelf,
+            Corner::BottomLeft | Corner::BottomRight => height - 1 - shelf,
+        };
+        let (_, from_right) = self.shelf(height, row);
+        let index = if from_right { width - 1 - step } else { step };
+        Ok((row, index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CORNERS: [Corner; 4] = [Corner::TopLeft, Corner::TopRight, Corner::BottomLeft, Corner::BottomRight];
+
+    #[test]
+    fn label_round_trip() {
+        for start in CORNERS {
+            for serpentine in [false, true] {
+                let numbering = Numbering { start, serpentine };
+                let mut labels = vec![];
+                for row in 0..5 {
+                    for index in 0..3 {
+                        let label = numbering.label(3, 5, row, index).unwrap();
+                        assert_eq!(numbering.position(3, 5, label).unwrap(), (row, index), "{:?}", numbering);
+                        labels.push(label);
+                    }
+                }
+                labels.sort_unstable();
+                assert_eq!(labels, (1..=15).collect::<Vec<_>>(), "{:?}", numbering);
+            }
+        }
+    }
+
+    #[test]
+    fn first_slots() {
+        let at = |start, serpentine, label| Numbering { start, serpentine }.position(3, 5, label).unwrap();
+        assert_eq!(at(Corner::TopLeft, false, 1), (0, 0));
+        assert_eq!(at(Corner::TopRight, false, 1), (0, 2));
+        assert_eq!(at(Corner::BottomLeft, false, 1), (4, 0));
+        assert_eq!(at(Corner::BottomRight, false, 1), (4, 2));
+        assert_eq!(at(Corner::TopLeft, false, 4), (1, 0));
+        assert_eq!(at(Corner::TopLeft, true, 4), (1, 2));
+        assert_eq!(at(Corner::BottomRight, true, 4), (3, 0));
+    }
+
+    #[test]
+    fn outside_rack() {
+        let numbering = Numbering::default();
+        assert!(numbering.label(3, 5, 5, 0).is_err());
+        assert!(numbering.label(3, 5, 0, -1).is_err());
+        assert!(numbering.position(3, 5, 0).is_err());
+        assert!(numbering.position(3, 5, 16).is_err());
+    }
+
+    #[test]
+    fn db_round_trip() {
+        for start in CORNERS {
+            for serpentine in [false, true] {
+                let numbering = Numbering { start, serpentine };
+                assert_eq!(Numbering::parse(&numbering.to_db()).unwrap(), numbering);
+            }
+        }
+        assert!(Numbering::parse("middle").is_err());
+    }
+}
@@ -0,0 +1,160 @@
+use std::collections::HashSet;
+
+use sqlx::sqlite::SqlitePool;
+use anyhow::Result;
+use serde::{Serialize, Deserialize};
+
+use crate::models::Profile;
+use super::unique_violation;
+
+/// A named set of profiles, one per model, so mixed models can be switched together
+#[derive(Serialize, Debug, Clone)]
+pub struct ProfilePreset {
+    pub id: i64,
+    pub name: String,
+    pub profiles: Vec<ModelProfile>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ModelProfile {
+    /// Model as the miner reports it
+    pub model: String,
+    pub profile: Profile,
+}
+
+impl ProfilePreset {
+    /// The profile for a model, matched ignoring case and surrounding whitespace
+    pub fn profile(&self, model: &str) -> Option<&Profile> {
+        let model = model.trim();
+        self.profiles.iter()
+            .find(|p| p.model.eq_ignore_ascii_case(model))
+            .map(|p| &p.profile)
+    }
+
+    fn check(name: &str, profiles: &[ModelProfile]) -> Result<()> {
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Preset name is required"));
+        }
+        if profiles.is_empty() {
+            return Err(anyhow::anyhow!("Preset {} needs a profile for at least one model", name));
+        }
+        let mut seen = HashSet::new();
+        for profile in profiles {
+            let model = profile.model.trim().to_lowercase();
+            if model.is_empty() {
+                return Err(anyhow::anyhow!("Every profile in preset {} needs a model", name));
+            }
+            if !seen.insert(model) {
+                return Err(anyhow::anyhow!("Preset {} has more than one profile for {}", name, profile.model.trim()));
+            }
+        }
+        Ok(())
+    }
+
+    async fn insert_profiles(tx: &mut sqlx::Transaction<'_, sqlx::Sqlite>, id: i64, profiles: &[ModelProfile]) -> Result<()> {
+        for profile in profiles {
+            let model = profile.model.trim();
+            let json = serde_json::to_string(&profile.profile)?;
+            sqlx::query!(
+                "INSERT INTO profile_preset_models (preset_id, model, profile) VALUES (?, ?, ?)",
+                id, model, json
+            ).execute(&mut *tx).await?;
+        }
+        Ok(())
+    }
+
+    /// Every preset by name, models in name order
+    pub async fn all(db: &SqlitePool) -> Result<Vec<ProfilePreset>> {
+        let presets = sqlx::query!("SELECT id, name FROM profile_presets ORDER BY name")
+            .fetch_all(db).await?;
+        let models = sqlx::query!("SELECT preset_id, model, profile FROM profile_preset_models ORDER BY model")
+            .fetch_all(db).await?;
+        presets.into_iter().map(|preset| Ok(ProfilePreset {
+            profiles: models.iter()
+                .filter(|m| m.preset_id == preset.id)
+                .map(|m| Ok(ModelProfile { model: m.model.clone(), profile: serde_json::from_str(&m.profile)? }))
+                .collect::<Result<_>>()?,
+            id: preset.id,
+            name: preset.name,
+        })).collect()
+    }
+
+    pub async fn get(db: &SqlitePool, name: &str) -> Result<ProfilePreset> {
+        Self::all(db).await?
+            .into_iter()
+            .find(|p| p.name == name)
+            .ok_or_else(|| anyhow::anyhow!("No profile preset named {}", name))
+    }
+
+    pub async fn create(db: &SqlitePool, name: &str, profiles: &[ModelProfile]) -> Result<i64> {
+        let name = name.trim();
+        Self::check(name, profiles)?;
+        let mut tx = db.begin().await?;
+        let id = sqlx::query!("INSERT INTO profile_presets (name) VALUES (?)", name)
+            .execute(&mut tx).await
+            .map_err(|e| unique_violation(e, || format!("Preset {} already exists", name)))?
+            .last_insert_rowid();
+        Self::insert_profiles(&mut tx, id, profiles).await?;
+        tx.commit().await?;
+        Ok(id)
+    }
+
+    /// Rename a preset and replace its profiles
+    pub async fn update(db: &SqlitePool, id: i64, name: &str, profiles: &[ModelProfile]) -> Result<()> {
+        let name = name.trim();
+        Self::check(name, profiles)?;
+        let mut tx = db.begin().await?;
+        let res = sqlx::query!("UPDATE profile_presets SET name = ? WHERE id = ?", name, id)
+            .execute(&mut tx).await
+            .map_err(|e| unique_violation(e, || format!("Preset {} already exists", name)))?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No profile preset with id {}", id));
+        }
+        sqlx::query!("DELETE FROM profile_preset_models WHERE preset_id = ?", id)
+            .execute(&mut tx).await?;
+        Self::insert_profiles(&mut tx, id, profiles).await?;
+        tx.commit().await?;
+        Ok(())
+    }
+
+    pub async fn delete(db: &SqlitePool, id: i64) -> Result<()> {
+        sqlx::query!("DELETE FROM profile_presets WHERE id = ?", id)
+            .execute(db).await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(model: &str, profile: Profile) -> ModelProfile {
+        ModelProfile { model: model.to_string(), profile }
+    }
+
+    #[test]
+    fn profile_for_model() {
+        let preset = ProfilePreset {
+            id: 1,
+            name: "Summer".to_string(),
+            profiles: vec![
+                entry("Antminer S19", Profile::LowPower),
+                entry("M30S", Profile::Default),
+            ],
+        };
+        assert!(matches!(preset.profile("Antminer S19"), Some(Profile::LowPower)));
+        assert!(matches!(preset.profile(" antminer s19 "), Some(Profile::LowPower)));
+        assert!(matches!(preset.profile("M30S"), Some(Profile::Default)));
+        assert!(preset.profile("Antminer S19 Pro").is_none());
+    }
+
+    #[test]
+    fn check_profiles() {
+        assert!(ProfilePreset::check("Summer", &[entry("S19", Profile::LowPower)]).is_ok());
+        assert!(ProfilePreset::check("", &[entry("S19", Profile::LowPower)]).is_err());
+        assert!(ProfilePreset::check("Summer", &[]).is_err());
+        assert!(ProfilePreset::check("Summer", &[entry("  ", Profile::LowPower)]).is_err());
+        let duplicate = ProfilePreset::check("Summer", &[entry("S19", Profile::LowPower), entry(" s19", Profile::Default)]);
+        assert_eq!(duplicate.unwrap_err().to_string(), "Preset Summer has more than one profile for s19");
+    }
+}
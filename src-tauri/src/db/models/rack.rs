@@ -1,89 +1,221 @@
 use serde::Serialize;
 use sqlx::sqlite::SqlitePool;
 use anyhow::Result;
-use tokio::{spawn, join};
 
+use super::circuit::DbFeed;
 use super::miner::DbMiner;
+use super::numbering::Numbering;
+use super::unique_violation;
 
 #[derive(Serialize, Debug)]
 pub struct DbRack {
-    #[serde(skip)]
     pub id: i64,
-    #[serde(skip)]
-    pub can_id: i64,
     pub name: String,
     pub index: i64,
     pub width: i64,
     pub height: i64,
+    pub numbering: Numbering,
 
     pub miners: Vec<Vec<DbMiner>>,
+    /// Slot labels laid out like `miners`
+    pub labels: Vec<Vec<i64>>,
 }
 
 impl DbRack {
-    pub async fn all(db: &SqlitePool) -> Result<Vec<DbRack>> {
-        let rows = sqlx::query!("SELECT id, can_id, index_, name, width, height FROM racks")
-            .fetch_all(db).await?;
-        let mut racks: Vec<DbRack> = rows.into_iter().map(|row| {
-            DbRack {
-                id: row.id,
-                can_id: row.can_id,
-                name: row.name,
-                index: row.index_,
-                width: row.width,
-                height: row.height,
-                miners: vec![],
-            }
-        }).collect();
-        Ok(racks)
-    }
-
     pub async fn query_can(db: &SqlitePool, can_id: i64) -> Result<Vec<DbRack>> {
-        let rows = sqlx::query!("SELECT id, can_id, name, index_, width, height FROM racks WHERE can_id = ? ORDER BY index_", can_id)
+        let rows = sqlx::query!("SELECT id, name, index_, width, height, numbering FROM racks WHERE can_id = ? ORDER BY index_", can_id)
             .fetch_all(db).await?;
-        let mut racks: Vec<DbRack> = rows.into_iter().map(|row| {
-            DbRack {
+        let mut racks = rows.into_iter().map(|row| {
+            Ok(DbRack {
                 id: row.id,
-                can_id: row.can_id,
                 name: row.name,
                 index: row.index_,
                 width: row.width,
                 height: row.height,
+                numbering: Numbering::parse(&row.numbering)?,
                 miners: vec![],
-            }
-        }).collect();
-        // TODO: We can do this better
+                labels: vec![],
+            })
+        }).collect::<Result<Vec<DbRack>>>()?;
+        let mut miners = DbMiner::query_can(db, can_id).await?;
         for rack in &mut racks {
-            rack.load_miners(db).await?;
+            let rack_miners = miners.remove(&rack.id).unwrap_or_default();
+            rack.place_miners(rack_miners);
         }
         Ok(racks)
     }
 
-    pub async fn load_miners(&mut self, db: &SqlitePool) -> Result<()> {
-        let miners = DbMiner::query_rack(db, self.id).await?;
+    /// Arrange miners sorted by row and index into the rack's rows
+    /// Slots without a miner are filled with an empty one so the grid stays aligned
+    fn place_miners(&mut self, miners: Vec<DbMiner>) {
         let mut rack_miners: Vec<Vec<DbMiner>> = vec![];
         for _ in 0..self.height {
             rack_miners.push(vec![]);
         }
         for miner in miners {
-            rack_miners[miner.row as usize].push(miner);
+            let row = match rack_miners.get_mut(miner.row as usize) {
+                Some(row) => row,
+                None => continue,
+            };
+            while (row.len() as i64) < miner.index {
+                row.push(DbMiner::empty(miner.row, row.len() as i64));
+            }
+            row.push(miner);
         }
         self.miners = rack_miners;
-        Ok(())
+        self.labels = self.slot_labels();
+    }
+
+    /// Labels of every slot laid out like `miners`
+    pub fn slot_labels(&self) -> Vec<Vec<i64>> {
+        (0..self.height)
+            .map(|row| (0..self.width).filter_map(|index| self.label(row, index).ok()).collect())
+            .collect()
+    }
+
+    /// Label people use for the slot at `row` and `index`
+    pub fn label(&self, row: i64, index: i64) -> Result<i64> {
+        self.numbering.label(self.width, self.height, row, index)
+    }
+
+    /// Row and index of the slot people call `label`
+    pub fn position(&self, label: i64) -> Result<(i64, i64)> {
+        self.numbering.position(self.width, self.height, label)
+    }
+
+    /// Change how a rack's slots are labelled, refused while circuits feed it by slot range
+    /// Returns the new slot labels
+    pub async fn set_numbering(db: &SqlitePool, id: i64, numbering: Numbering) -> Result<Vec<Vec<i64>>> {
+        let mut rack = Self::get(db, id).await?;
+        if rack.numbering == numbering {
+            return Ok(rack.slot_labels());
+        }
+        DbFeed::check_relabel(db, id).await?;
+        let value = numbering.to_db();
+        let res = sqlx::query!("UPDATE racks SET numbering = ? WHERE id = ?", value, id)
+            .execute(db).await?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No rack with id {}", id));
+        }
+        rack.numbering = numbering;
+        Ok(rack.slot_labels())
     }
 
     pub async fn get(db: &SqlitePool, id: i64) -> Result<DbRack> {
-        let row = sqlx::query!("SELECT id, can_id, name, index_, width, height FROM racks WHERE id = ?", id)
-            .fetch_one(db).await?;
-        let mut rack = DbRack {
+        let row = sqlx::query!("SELECT id, name, index_, width, height, numbering FROM racks WHERE id = ?", id)
+            .fetch_optional(db).await?
+            .ok_or_else(|| anyhow::anyhow!("No rack with id {}", id))?;
+        Ok(DbRack {
             id: row.id,
-            can_id: row.can_id,
             name: row.name,
             index: row.index_,
             width: row.width,
             height: row.height,
+            numbering: Numbering::parse(&row.numbering)?,
             miners: vec![],
-        };
-        rack.load_miners(db).await?;
-        Ok(rack)
+            labels: vec![],
+        })
+    }
+
+    fn check_size(width: i64, height: i64) -> Result<()> {
+        if width < 1 || height < 1 {
+            return Err(anyhow::anyhow!("Rack width and height must be at least 1"));
+        }
+        Ok(())
+    }
+
+    /// Add a rack to the end of a can
+    pub async fn create(db: &SqlitePool, can_id: i64, name: &str, width: i64, height: i64) -> Result<i64> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Rack name is required"));
+        }
+        Self::check_size(width, height)?;
+        let res = sqlx::query!(r#"
+            INSERT INTO racks (can_id, name, index_, width, height)
+            SELECT c.id, ?, COALESCE((SELECT MAX(index_) + 1 FROM racks WHERE can_id = c.id), 0), ?, ?
+            FROM cans c WHERE c.id = ?
+            "#,
+            name, width, height, can_id
+        )
+            .execute(db).await
+            .map_err(|e| unique_violation(e, || format!("Rack {} already exists in this can", name)))?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No can with id {}", can_id));
+        }
+        Ok(res.last_insert_rowid())
+    }
+
+    pub async fn rename(db: &SqlitePool, id: i64, name: &str) -> Result<()> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err(anyhow::anyhow!("Rack name is required"));
+        }
+        let res = sqlx::query!("UPDATE racks SET name = ? WHERE id = ?", name, id)
+            .execute(db).await
+            .map_err(|e| unique_violation(e, || format!("Rack {} already exists in this can", name)))?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No rack with id {}", id));
+        }
+        Ok(())
+    }
+
+    /// Change a rack's dimensions, refusing if it would leave miners outside the rack
+    /// or circuits feed it by slot range
+    pub async fn resize(db: &SqlitePool, id: i64, width: i64, height: i64) -> Result<()> {
+        Self::check_size(width, height)?;
+        let rack = Self::get(db, id).await?;
+        if rack.width == width && rack.height == height {
+            return Ok(());
+        }
+        DbFeed::check_relabel(db, id).await?;
+        let outside = sqlx::query!(
+            "SELECT COUNT(*) AS count FROM miners WHERE rack_id = ? AND (row >= ? OR index_ >= ?)",
+            id, height, width
+        ).fetch_one(db).await?;
+        if outside.count > 0 {
+            return Err(anyhow::anyhow!("{} miners are outside a {}x{} rack, move or clear them first", outside.count, width, height));
+        }
+        let res = sqlx::query!("UPDATE racks SET width = ?, height = ? WHERE id = ?", width, height, id)
+            .execute(db).await?;
+        if res.rows_affected() == 0 {
+            return Err(anyhow::anyhow!("No rack with id {}", id));
+        }
+        Ok(())
+    }
+
+    /// Set the order of a can's racks, every rack in the can must be listed once
+    pub async fn reorder(db: &SqlitePool, can_id: i64, rack_ids: &[i64]) -> Result<()> {
+        let mut current = sqlx::query!("SELECT id FROM racks WHERE can_id = ?", can_id)
+            .fetch_all(db).await?
+            .into_iter()
+            .map(|r| r.id)
+            .collect::<Vec<i64>>();
+        let mut requested = rack_ids.to_vec();
+        current.sort();
+        requested.sort();
+        if current != requested {
+            return Err(anyhow::anyhow!("Rack order must list every rack in the can exactly once"));
+        }
+
+        let mut tx = db.begin().await?;
+        for (index, id) in rack_ids.iter().enumerate() {
+            let index = index as i64;
+            sqlx::query!("UPDATE racks SET index_ = ? WHERE id = ?", index, id)
+                .execute(&mut tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Delete a rack and its miners
+    pub async fn delete(db: &SqlitePool, id: i64) -> Result<()> {
+        let mut tx = db.begin().await?;
+        sqlx::query!("DELETE FROM miners WHERE rack_id = ?", id)
+            .execute(&mut tx).await?;
+        sqlx::query!("DELETE FROM racks WHERE id = ?", id)
+            .execute(&mut tx).await?;
+        tx.commit().await?;
+        Ok(())
     }
 }
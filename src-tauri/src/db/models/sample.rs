@@ -0,0 +1,104 @@
+use sqlx::sqlite::SqlitePool;
+use anyhow::Result;
+use serde::Serialize;
+
+use crate::models::Profile;
+
+/// A reading is added to the power history when a miner's power moves by more than this fraction
+const POWER_CHANGE: f64 = 0.05;
+/// Days of power history kept
+const HISTORY_DAYS: i64 = 90;
+
+/// The latest scan reading of a miner, kept so totals don't need a live scan
+#[derive(Serialize, Debug, Clone)]
+pub struct MinerSample {
+    pub ip: String,
+    /// Answered the scan
+    pub online: bool,
+    /// Online without errors or board and pool conditions
+    pub healthy: bool,
+    pub hashrate: Option<f64>,
+    pub power: Option<f64>,
+    pub efficiency: Option<f64>,
+    pub model: Option<String>,
+    pub sleep: bool,
+    /// Profile the miner was running and the ones it offers
+    pub profile: Option<Profile>,
+    pub profiles: Option<Vec<Profile>>,
+    /// Unix time of the scan
+    pub sampled: i64,
+}
+
+impl MinerSample {
+    /// Replace the stored readings of these miners and extend the power history of those that changed
+    pub async fn record(db: &SqlitePool, samples: &[MinerSample]) -> Result<()> {
+        let mut tx = db.begin().await?;
+        for sample in samples {
+            let previous = sqlx::query!(r#"SELECT online AS "online: bool", power FROM miner_samples WHERE ip = ?"#, sample.ip)
+                .fetch_optional(&mut tx).await?;
+            let changed = match previous {
+                None => true,
+                Some(previous) => previous.online != sample.online || match (previous.power, sample.power) {
+                    (Some(before), Some(now)) => (now - before).abs() > before.abs() * POWER_CHANGE,
+                    (before, now) => before.is_some() != now.is_some(),
+                },
+            };
+            if changed {
+                sqlx::query!(
+                    "INSERT INTO power_readings (ip, sampled, online, power) VALUES (?, ?, ?, ?)",
+                    sample.ip, sample.sampled, sample.online, sample.power
+                ).execute(&mut tx).await?;
+            }
+            let profile = sample.profile.as_ref().map(serde_json::to_string).transpose()?;
+            let profiles = sample.profiles.as_ref().map(serde_json::to_string).transpose()?;
+            sqlx::query!(r#"
+                INSERT INTO miner_samples (ip, online, healthy, hashrate, power, efficiency, model, sleep, profile, profiles, sampled)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ON CONFLICT (ip) DO UPDATE SET
+                    online = excluded.online,
+                    healthy = excluded.healthy,
+                    hashrate = excluded.hashrate,
+                    power = excluded.power,
+                    efficiency = excluded.efficiency,
+                    model = excluded.model,
+                    sleep = excluded.sleep,
+                    profile = excluded.profile,
+                    profiles = excluded.profiles,
+                    sampled = excluded.sampled
+                "#,
+                sample.ip, sample.online, sample.healthy, sample.hashrate, sample.power,
+                sample.efficiency, sample.model, sample.sleep, profile, profiles, sample.sampled
+            ).execute(&mut tx).await?;
+        }
+        if let Some(latest) = samples.iter().map(|s| s.sampled).max() {
+            let cutoff = latest - HISTORY_DAYS * 86400;
+            sqlx::query!("DELETE FROM power_readings WHERE sampled < ?", cutoff)
+                .execute(&mut tx).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    /// Stored readings of the miners that have one
+    pub async fn all(db: &SqlitePool) -> Result<Vec<MinerSample>> {
+        let rows = sqlx::query!(r#"
+            SELECT ip, online AS "online: bool", healthy AS "healthy: bool", hashrate, power,
+                efficiency, model, sleep AS "sleep: bool", profile, profiles, sampled
+            FROM miner_samples
+            "#
+        ).fetch_all(db).await?;
+        rows.into_iter().map(|row| Ok(MinerSample {
+            ip: row.ip,
+            online: row.online,
+            healthy: row.healthy,
+            hashrate: row.hashrate,
+            power: row.power,
+            efficiency: row.efficiency,
+            model: row.model,
+            sleep: row.sleep,
+            profile: row.profile.as_deref().map(serde_json::from_str).transpose()?,
+            profiles: row.profiles.as_deref().map(serde_json::from_str).transpose()?,
+            sampled: row.sampled,
+        })).collect()
+    }
+}
@@ -0,0 +1,105 @@
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use sqlx::sqlite::SqlitePool;
+use tokio::sync::broadcast;
+use anyhow::Result;
+
+/// A settings value stored as JSON in the config table under its own key
+pub trait Setting: Serialize + DeserializeOwned + Default + Send + Sync {
+    const KEY: &'static str;
+    /// Bump when the stored shape changes and handle the old shape in `migrate`
+    const VERSION: i64 = 1;
+
+    /// Reject values that shouldn't be saved
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Upgrade a value stored as `version` to `version + 1`
+    fn migrate(version: i64, value: Value) -> Result<Value> {
+        let _ = version;
+        Ok(value)
+    }
+}
+
+/// Load a setting if it has been stored, upgrading it to the current version
+pub async fn get<T: Setting>(db: &SqlitePool) -> Result<Option<T>> {
+    let row = sqlx::query!("SELECT value, version FROM config WHERE key = ?", T::KEY)
+        .fetch_optional(db)
+        .await?;
+    let row = match row {
+        Some(row) => row,
+        None => return Ok(None),
+    };
+    if row.version > T::VERSION {
+        return Err(anyhow::anyhow!(
+            "Setting {} is version {}, newer than this application supports",
+            T::KEY, row.version
+        ));
+    }
+
+    let mut value: Value = serde_json::from_str(&row.value)?;
+    if row.version < T::VERSION {
+        for version in row.version..T::VERSION {
+            value = T::migrate(version, value)?;
+        }
+        let setting: T = serde_json::from_value(value)?;
+        store(db, &setting).await?;
+        tracing::info!("Migrated setting {} from version {} to {}", T::KEY, row.version, T::VERSION);
+        return Ok(Some(setting));
+    }
+    Ok(Some(serde_json::from_value(value)?))
+}
+
+/// Load a setting, storing the default the first time
+pub async fn load<T: Setting>(db: &SqlitePool) -> Result<T> {
+    match get(db).await? {
+        Some(setting) => Ok(setting),
+        None => {
+            let default = T::default();
+            store(db, &default).await?;
+            Ok(default)
+        }
+    }
+}
+
+/// Validate and write a setting without notifying anyone
+pub async fn store<T: Setting>(db: &SqlitePool, setting: &T) -> Result<()> {
+    setting.validate()?;
+    let serial = serde_json::to_string(setting)?;
+    sqlx::query!("INSERT INTO config (key, value, version) VALUES (?, ?, ?)
+        ON CONFLICT(key) DO UPDATE SET value = excluded.value, version = excluded.version",
+        T::KEY, serial, T::VERSION
+    )
+        .execute(db)
+        .await?;
+    Ok(())
+}
+
+/// Tells subsystems which setting keys changed so they can reload
+pub struct Settings {
+    changes: broadcast::Sender<&'static str>,
+}
+
+impl Settings {
+    pub fn new() -> Self {
+        let (changes, _) = broadcast::channel(16);
+        Self { changes }
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<&'static str> {
+        self.changes.subscribe()
+    }
+
+    pub fn notify(&self, key: &'static str) {
+        // No subscribers isn't an error
+        let _ = self.changes.send(key);
+    }
+
+    pub async fn save<T: Setting>(&self, db: &SqlitePool, setting: &T) -> Result<()> {
+        store(db, setting).await?;
+        self.notify(T::KEY);
+        Ok(())
+    }
+}
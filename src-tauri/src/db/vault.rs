@@ -0,0 +1,251 @@
+use aes_gcm::{Aes256Gcm, KeyInit, Nonce};
+use aes_gcm::aead::Aead;
+use argon2::Argon2;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use rand::RngCore;
+use serde::{Serialize, Deserialize};
+use sqlx::sqlite::SqlitePool;
+use anyhow::Result;
+
+use super::settings::{self, Setting};
+
+/// Prefix of sealed values, anything without it is a plaintext secret from before encryption
+const PREFIX: &str = "enc:v1:";
+/// Sealed with the key to tell a wrong passphrase from a right one
+const CHECK: &str = "anttools";
+/// What the UI sees in place of a secret, saving it back keeps the stored value
+pub const MASK: &str = "********";
+/// Why a secret can't be read or written while the vault is locked
+pub const LOCKED: &str = "Credentials are locked, unlock them with the site passphrase";
+
+/// Salt and check value stored with the other settings
+/// Only ever written by the vault, the default is never stored
+#[derive(Serialize, Deserialize, Debug, Default)]
+struct VaultMeta {
+    salt: String,
+    check: String,
+}
+
+impl Setting for VaultMeta {
+    const KEY: &'static str = "vault";
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct VaultStatus {
+    /// A passphrase has been set for this site
+    pub configured: bool,
+    pub unlocked: bool,
+}
+
+/// Session key for the secrets stored in the config table
+/// Unlocked once per session with the operator's passphrase, never persisted
+#[derive(Clone, Default)]
+pub struct Vault {
+    key: Option<[u8; 32]>,
+}
+
+impl std::fmt::Debug for Vault {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Vault").field("unlocked", &self.key.is_some()).finish()
+    }
+}
+
+fn derive(passphrase: &str, salt: &[u8]) -> Result<[u8; 32]> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive key: {}", e))?;
+    Ok(key)
+}
+
+impl Vault {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub async fn status(&self, db: &SqlitePool) -> Result<VaultStatus> {
+        Ok(VaultStatus {
+            configured: settings::get::<VaultMeta>(db).await?.is_some(),
+            unlocked: self.key.is_some(),
+        })
+    }
+
+    /// Secrets are encrypted and this session hasn't unlocked them
+    pub async fn locked(&self, db: &SqlitePool) -> Result<bool> {
+        let status = self.status(db).await?;
+        Ok(status.configured && !status.unlocked)
+    }
+
+    /// Unlock with the site's passphrase
+    /// The first unlock sets the passphrase and encrypts any plaintext secrets already stored
+    pub async fn unlock(&mut self, db: &SqlitePool, passphrase: &str) -> Result<()> {
+        if passphrase.is_empty() {
+            return Err(anyhow::anyhow!("Passphrase is required"));
+        }
+        match settings::get::<VaultMeta>(db).await? {
+            Some(meta) => {
+                let salt = BASE64.decode(&meta.salt)?;
+                let vault = Vault { key: Some(derive(passphrase, &salt)?) };
+                if vault.open(&meta.check).ok().as_deref() != Some(CHECK) {
+                    return Err(anyhow::anyhow!("Incorrect passphrase"));
+                }
+                *self = vault;
+            },
+            None => {
+                // Read with the plaintext passthrough before there's a key to seal with
+                let auths = super::MinerAuth::load(db, self).await?;
+                let pools = super::Pools::load(db, self).await?;
+                self.rekey(db, passphrase).await?;
+                auths.save(db, self).await?;
+                pools.save(db, self).await?;
+                tracing::info!("Encrypted stored credentials");
+            },
+        }
+        Ok(())
+    }
+
+    pub fn lock(&mut self) {
+        self.key = None;
+    }
+
+    /// Re-encrypt every secret under a new passphrase, the vault must be unlocked
+    pub async fn change_passphrase(&mut self, db: &SqlitePool, passphrase: &str) -> Result<()> {
+        if passphrase.is_empty() {
+            return Err(anyhow::anyhow!("Passphrase is required"));
+        }
+        // Plaintext secrets would load fine while locked and be sealed under a passphrase nobody checked
+        if self.key.is_none() {
+            return Err(anyhow::anyhow!(LOCKED));
+        }
+        let auths = super::MinerAuth::load(db, self).await?;
+        let pools = super::Pools::load(db, self).await?;
+        self.rekey(db, passphrase).await?;
+        auths.save(db, self).await?;
+        pools.save(db, self).await?;
+        Ok(())
+    }
+
+    async fn rekey(&mut self, db: &SqlitePool, passphrase: &str) -> Result<()> {
+        let mut salt = [0u8; 16];
+        rand::thread_rng().fill_bytes(&mut salt);
+        let vault = Vault { key: Some(derive(passphrase, &salt)?) };
+        settings::store(db, &VaultMeta {
+            salt: BASE64.encode(salt),
+            check: vault.seal(CHECK)?,
+        }).await?;
+        *self = vault;
+        Ok(())
+    }
+
+    fn cipher(&self) -> Result<Aes256Gcm> {
+        let key = self.key.as_ref()
+            .ok_or_else(|| anyhow::anyhow!(LOCKED))?;
+        Ok(Aes256Gcm::new(key.into()))
+    }
+
+    /// Encrypt a secret for storage, empty values are stored as is
+    /// Fails while locked, callers store plaintext until a passphrase is set
+    pub fn seal(&self, plain: &str) -> Result<String> {
+        if plain.is_empty() {
+            return Ok(String::new());
+        }
+        let mut nonce = [0u8; 12];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let mut sealed = nonce.to_vec();
+        sealed.extend(self.cipher()?
+            .encrypt(Nonce::from_slice(&nonce), plain.as_bytes())
+            .map_err(|_| anyhow::anyhow!("Failed to encrypt secret"))?);
+        Ok(format!("{}{}", PREFIX, BASE64.encode(sealed)))
+    }
+
+    /// Decrypt a stored secret, plaintext values are passed through
+    pub fn open(&self, stored: &str) -> Result<String> {
+        let sealed = match stored.strip_prefix(PREFIX) {
+            Some(sealed) => BASE64.decode(sealed)?,
+            None => return Ok(stored.to_string()),
+        };
+        if sealed.len() < 12 {
+            return Err(anyhow::anyhow!("Corrupt secret"));
+        }
+        let (nonce, data) = sealed.split_at(12);
+        let plain = self.cipher()?
+            .decrypt(Nonce::from_slice(nonce), data)
+            .map_err(|_| anyhow::anyhow!("Failed to decrypt secret"))?;
+        Ok(String::from_utf8(plain)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::{Auth, MinerAuth};
+
+    fn vault(passphrase: &str) -> Vault {
+        Vault { key: Some(derive(passphrase, b"0123456789abcdef").unwrap()) }
+    }
+
+    #[test]
+    fn seal_round_trip() {
+        let vault = vault("passphrase");
+        let sealed = vault.seal("secret").unwrap();
+        assert!(sealed.starts_with(PREFIX));
+        assert!(!sealed.contains("secret"));
+        assert_eq!(vault.open(&sealed).unwrap(), "secret");
+        // A fresh nonce every time
+        assert_ne!(vault.seal("secret").unwrap(), sealed);
+    }
+
+    #[test]
+    fn wrong_key() {
+        let sealed = vault("passphrase").seal("secret").unwrap();
+        assert!(vault("other").open(&sealed).is_err());
+        assert_eq!(Vault::new().open(&sealed).unwrap_err().to_string(), LOCKED);
+        assert!(Vault::new().seal("secret").is_err());
+        assert!(vault("passphrase").open(&format!("{}AAAA", PREFIX)).is_err());
+    }
+
+    #[test]
+    fn plaintext() {
+        // Secrets stored before a passphrase was set, readable with or without a key
+        assert_eq!(Vault::new().open("secret").unwrap(), "secret");
+        assert_eq!(vault("passphrase").open("secret").unwrap(), "secret");
+        assert_eq!(vault("passphrase").seal("").unwrap(), "");
+        assert_eq!(Vault::new().seal("").unwrap(), "");
+    }
+
+    #[tokio::test]
+    async fn unlock_and_mask() {
+        let db = crate::db::test_db().await;
+        let mut vault = Vault::new();
+        let auth = |password: &str| Auth {
+            id: 0,
+            make: "Antminer".to_string(),
+            username: "root".to_string(),
+            password: password.to_string(),
+        };
+        MinerAuth { auths: vec![auth("root")] }.save(&db, &vault).await.unwrap();
+        assert!(!vault.locked(&db).await.unwrap());
+        assert!(vault.change_passphrase(&db, "passphrase").await.is_err());
+
+        // The first unlock sets the passphrase and seals what was stored in plaintext
+        vault.unlock(&db, "passphrase").await.unwrap();
+        let stored: MinerAuth = settings::load(&db).await.unwrap();
+        assert!(stored.auths[0].password.starts_with(PREFIX));
+        assert_eq!(MinerAuth::load(&db, &vault).await.unwrap().auths[0].password, "root");
+
+        // Saving the masked value back keeps the stored password
+        let masked = MinerAuth::load_masked(&db).await.unwrap();
+        assert_eq!(masked.auths[0].password, MASK);
+        masked.save(&db, &vault).await.unwrap();
+        assert_eq!(MinerAuth::load(&db, &vault).await.unwrap().auths[0].password, "root");
+
+        vault.lock();
+        assert!(vault.locked(&db).await.unwrap());
+        assert!(MinerAuth::load(&db, &vault).await.is_err());
+        assert!(vault.change_passphrase(&db, "other").await.is_err());
+        assert!(vault.unlock(&db, "wrong").await.is_err());
+        vault.unlock(&db, "passphrase").await.unwrap();
+        assert_eq!(MinerAuth::load(&db, &vault).await.unwrap().auths[0].password, "root");
+    }
+}
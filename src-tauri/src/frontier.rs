@@ -1,10 +1,16 @@
-use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+
+use serde::{Serialize, Deserialize};
 use anyhow::Result;
-use csv;
 use sqlx::sqlite::SqlitePool;
 use tracing::info;
 
-#[derive(Deserialize)]
+use crate::db::{DbFeed, Numbering};
+use crate::layout::{Areas, Builder, ImportProblem, NewMiner, NewRack, Sitemap, SlotRef, Source, file_name, format_problems, read_rows, replace};
+
+#[derive(Serialize, Deserialize)]
 struct SitemapRecord {
     pickaxe_id: String,
     miner_ip: String,
@@ -14,15 +20,15 @@ struct SitemapRecord {
     index: i64,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 enum RackRecordType {
     #[serde(rename = "group")]
-    GROUP,
+    Group,
     #[serde(rename = "rack")]
-    RACK,
+    Rack,
 }
 
-#[derive(Deserialize)]
+#[derive(Serialize, Deserialize)]
 struct RackRecord {
     group_name: Option<String>,
     #[serde(rename = "type")]
@@ -34,79 +40,533 @@ struct RackRecord {
     rack_height: Option<i64>,
 }
 
-pub async fn import_sitemap(db: &SqlitePool, layout: String, sitemap: String) -> Result<()> {
-    // Drop all data in existing tables
-    sqlx::query("DELETE FROM miners").execute(db).await?;
-    sqlx::query("DELETE FROM racks").execute(db).await?;
-    sqlx::query("DELETE FROM cans").execute(db).await?;
+/// Read and validate an export, returning every problem found rather than the first
+fn parse(layout: &str, sitemap: &str) -> Result<(Sitemap, Vec<ImportProblem>)> {
+    let layout_file = file_name(layout);
+    let sitemap_file = file_name(sitemap);
+    let mut builder = Builder::new();
+
+    let records = read_rows::<RackRecord>(layout, &layout_file, &mut builder)?;
+    let src = |line: u64| Source { file: &layout_file, line, path: None };
+    // Groups first so racks can reference groups listed after them
+    for (line, record) in &records {
+        if let RackRecordType::Group = record.type_ {
+            builder.can(&src(*line), &record.name, record.row, None);
+        }
+    }
+    for (line, record) in &records {
+        if let RackRecordType::Group = record.type_ {
+            continue;
+        }
+        match &record.group_name {
+            Some(group) => builder.rack(&src(*line), NewRack {
+                can: group,
+                name: &record.name,
+                index: record.column,
+                width: record.rack_width,
+                height: record.rack_height,
+                numbering: Numbering::default(),
+            }),
+            None => builder.problem(ImportProblem {
+                file: layout_file.clone(),
+                line: *line,
+                reason: format!("Rack {} has no group", record.name),
+            }),
+        }
+    }
+
+    let records = read_rows::<SitemapRecord>(sitemap, &sitemap_file, &mut builder)?;
+    for (line, record) in records {
+        if record.miner_ip.trim().is_empty() {
+            continue;
+        }
+        let src = Source { file: &sitemap_file, line, path: None };
+        // Only keep ports that differ from the default so exports round trip
+        let port = Some(record.miner_port).filter(|p| *p != crate::cgminer::API_PORT);
+        builder.miner(&src, NewMiner {
+            ip: &record.miner_ip,
+            // Sitemaps only name the rack
+            can: None,
+            rack: &record.rack,
+            row: record.row,
+            index: record.index,
+            port,
+            external_id: Some(&record.pickaxe_id),
+        });
+    }
+    Ok(builder.finish(&[&layout_file, &sitemap_file]))
+}
+
+/// Replace the whole layout with an export
+/// Nothing is written if any row has a problem, or circuit feeds can't be kept, the problems are returned instead
+pub async fn import_sitemap(db: &SqlitePool, layout: &str, sitemap: &str) -> Result<Vec<ImportProblem>> {
+    let (map, problems) = parse(layout, sitemap)?;
+    if !problems.is_empty() {
+        return Ok(problems);
+    }
 
     info!("Importing sitemap");
-    // Parse layout csv file first
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(layout)?;
-    // let mut cans = Vec::new();
-    // let mut racks = Vec::new();
-
-    for result in rdr.deserialize::<RackRecord>() {
-        let record = result?;
-        match record.type_ {
-            RackRecordType::GROUP => {
-                info!("Group: {}", record.name);
-                sqlx::query!(r#"
-                    INSERT INTO cans (name, num)
-                    VALUES (?, ?)
-                    "#,
-                    record.name,
-                    record.row,
-                ).execute(db).await?;
+    // Frontier has no areas, the ones set up here stay
+    replace(db, &map, Areas::Keep, &file_name(layout)).await
+}
+
+/// One change a merge import would make, ids are those of the existing rows
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum LayoutChange {
+    AddCan { name: String, num: i64 },
+    UpdateCan {
+        #[serde(skip)]
+        id: i64,
+        name: String,
+        num: i64,
+    },
+    RemoveCan {
+        #[serde(skip)]
+        id: i64,
+        name: String,
+    },
+    AddRack { can: String, name: String, index: i64, width: i64, height: i64 },
+    UpdateRack {
+        #[serde(skip)]
+        id: i64,
+        can: String,
+        name: String,
+        index: i64,
+        width: i64,
+        height: i64,
+    },
+    RemoveRack {
+        #[serde(skip)]
+        id: i64,
+        can: String,
+        name: String,
+    },
+    AddMiner {
+        ip: String,
+        slot: SlotRef,
+        port: Option<u16>,
+        external_id: Option<String>,
+    },
+    MoveMiner {
+        #[serde(skip)]
+        id: i64,
+        ip: String,
+        from: SlotRef,
+        to: SlotRef,
+    },
+    ChangeIp {
+        #[serde(skip)]
+        id: i64,
+        slot: SlotRef,
+        old: String,
+        new: String,
+    },
+    /// The port or external id of a miner that's kept changed
+    UpdateMiner {
+        #[serde(skip)]
+        id: i64,
+        ip: String,
+        port: Option<u16>,
+        external_id: Option<String>,
+    },
+    RemoveMiner {
+        #[serde(skip)]
+        id: i64,
+        ip: String,
+        slot: SlotRef,
+    },
+}
+
+/// Compare an export against the database, matching cans by name, racks by can and name and miners by IP
+async fn diff(db: &SqlitePool, map: &Sitemap) -> Result<Vec<LayoutChange>> {
+    let mut changes = vec![];
+
+    let cans = sqlx::query!("SELECT id, name, num FROM cans")
+        .fetch_all(db).await?;
+    let new_cans = map.cans.iter().map(|c| c.name.as_str()).collect::<HashSet<_>>();
+    for can in &map.cans {
+        match cans.iter().find(|c| c.name == can.name) {
+            None => changes.push(LayoutChange::AddCan { name: can.name.clone(), num: can.num }),
+            Some(c) if c.num != can.num => changes.push(LayoutChange::UpdateCan { id: c.id, name: can.name.clone(), num: can.num }),
+            Some(_) => {},
+        }
+    }
+
+    let racks = sqlx::query!(r#"
+        SELECT r.id, r.name, r.index_, r.width, r.height, c.name AS can
+        FROM racks r
+        JOIN cans c ON r.can_id = c.id
+        "#
+    ).fetch_all(db).await?;
+    let new_racks = map.racks.iter().map(|r| (r.can.as_str(), r.name.as_str())).collect::<HashSet<_>>();
+    for rack in &map.racks {
+        match racks.iter().find(|r| r.can == rack.can && r.name == rack.name) {
+            None => changes.push(LayoutChange::AddRack {
+                can: rack.can.clone(),
+                name: rack.name.clone(),
+                index: rack.index,
+                width: rack.width,
+                height: rack.height,
+            }),
+            Some(r) if r.index_ != rack.index || r.width != rack.width || r.height != rack.height => {
+                changes.push(LayoutChange::UpdateRack {
+                    id: r.id,
+                    can: rack.can.clone(),
+                    name: rack.name.clone(),
+                    index: rack.index,
+                    width: rack.width,
+                    height: rack.height,
+                });
+            },
+            Some(_) => {},
+        }
+    }
+
+    let miners = sqlx::query!(r#"
+        SELECT m.id, m.ip, m.row, m.index_, m.port, m.external_id, r.name AS rack, c.name AS can
+        FROM miners m
+        JOIN racks r ON m.rack_id = r.id
+        JOIN cans c ON r.can_id = c.id
+        "#
+    ).fetch_all(db).await?;
+    let current = miners.iter()
+        .map(|m| (m.ip.as_str(), (m.id, SlotRef { can: m.can.clone(), rack: m.rack.clone(), row: m.row, index: m.index_ })))
+        .collect::<HashMap<_, _>>();
+    let occupants = current.iter()
+        .map(|(ip, (id, slot))| (slot.clone(), (*id, *ip)))
+        .collect::<HashMap<_, _>>();
+    let details = miners.iter()
+        .map(|m| (m.id, (m.port.map(|p| p as u16), m.external_id.as_deref())))
+        .collect::<HashMap<_, _>>();
+    let new_ips = map.miners.iter().map(|m| m.ip.as_str()).collect::<HashSet<_>>();
+    let mut renamed = HashSet::new();
+    for miner in &map.miners {
+        let kept = match current.get(miner.ip.as_str()) {
+            Some((id, slot)) if *slot == miner.slot => Some(*id),
+            Some((id, slot)) => {
+                changes.push(LayoutChange::MoveMiner {
+                    id: *id,
+                    ip: miner.ip.clone(),
+                    from: slot.clone(),
+                    to: miner.slot.clone(),
+                });
+                Some(*id)
             },
-            RackRecordType::RACK => {
-                let group_name = record.group_name.unwrap();
-                let row = sqlx::query!(r#"
-                    SELECT id FROM cans WHERE name = ?
-                    "#,
-                    group_name,
-                ).fetch_one(db).await?;
-                info!("Rack: {} in {}", record.name, group_name);
-                let width = record.rack_width.unwrap();
-                let height = record.rack_height.unwrap();
-                sqlx::query!(r#"
-                    INSERT INTO racks (name, index_, width, height, can_id)
-                    VALUES (?, ?, ?, ?, ?)
-                    "#,
-                    record.name,
-                    record.column,
-                    width,
-                    height,
-                    row.id
-                ).execute(db).await?;
+            None => match occupants.get(&miner.slot) {
+                // The slot's old IP is gone from the sitemap, treat it as the same miner readdressed
+                Some((id, old)) if !new_ips.contains(old) => {
+                    renamed.insert(*old);
+                    changes.push(LayoutChange::ChangeIp {
+                        id: *id,
+                        slot: miner.slot.clone(),
+                        old: old.to_string(),
+                        new: miner.ip.clone(),
+                    });
+                    Some(*id)
+                },
+                _ => {
+                    changes.push(LayoutChange::AddMiner {
+                        ip: miner.ip.clone(),
+                        slot: miner.slot.clone(),
+                        port: miner.port,
+                        external_id: miner.external_id.clone(),
+                    });
+                    None
+                },
             },
+        };
+        if let Some(id) = kept {
+            if details[&id] != (miner.port, miner.external_id.as_deref()) {
+                changes.push(LayoutChange::UpdateMiner {
+                    id,
+                    ip: miner.ip.clone(),
+                    port: miner.port,
+                    external_id: miner.external_id.clone(),
+                });
+            }
+        }
+    }
+    for miner in &miners {
+        let ip = miner.ip.as_str();
+        if !new_ips.contains(ip) && !renamed.contains(ip) {
+            let slot = SlotRef { can: miner.can.clone(), rack: miner.rack.clone(), row: miner.row, index: miner.index_ };
+            changes.push(LayoutChange::RemoveMiner { id: miner.id, ip: miner.ip.clone(), slot });
         }
     }
 
-    // Parse sitemap csv file
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .from_path(sitemap)?;
-    for result in rdr.deserialize::<SitemapRecord>() {
-        let record = result?;
-        info!("Miner: {} at {} {} {}", record.miner_ip, record.rack, record.row, record.index);
-        let rack = sqlx::query!(r#"
-            SELECT id FROM racks WHERE name = ?
-            "#,
-            record.rack
-        ).fetch_one(db).await?;
-        sqlx::query!(r#"
-            INSERT INTO miners (ip, rack_id, row, index_)
-            VALUES (?, ?, ?, ?)
-            "#,
-            record.miner_ip,
-            rack.id,
-            record.row,
-            record.index
-        ).execute(db).await?;
+    for rack in racks.iter().filter(|r| !new_racks.contains(&(r.can.as_str(), r.name.as_str()))) {
+        changes.push(LayoutChange::RemoveRack { id: rack.id, can: rack.can.clone(), name: rack.name.clone() });
+    }
+    for can in cans.iter().filter(|c| !new_cans.contains(c.name.as_str())) {
+        changes.push(LayoutChange::RemoveCan { id: can.id, name: can.name.clone() });
+    }
+    Ok(changes)
+}
+
+/// Changes a merge import would make and a hash of them to confirm the merge with
+#[derive(Serialize, Debug, Clone)]
+pub struct MergePreview {
+    pub changes: Vec<LayoutChange>,
+    /// Kept as a string, a u64 doesn't fit in a JS number
+    pub hash: String,
+}
+
+/// Hash of a change list including the ids it would write to, so any difference from
+/// the preview is caught and not just a different number of changes
+fn fingerprint(changes: &[LayoutChange]) -> String {
+    let mut hasher = DefaultHasher::new();
+    format!("{:?}", changes).hash(&mut hasher);
+    format!("{:016x}", hasher.finish())
+}
+
+/// Changes a merge import of this export would make, nothing is written
+pub async fn preview_merge(db: &SqlitePool, layout: &str, sitemap: &str) -> Result<MergePreview> {
+    let (map, problems) = parse(layout, sitemap)?;
+    if !problems.is_empty() {
+        return Err(anyhow::anyhow!("{}", format_problems(&problems)));
     }
+    let changes = diff(db, &map).await?;
+    let hash = fingerprint(&changes);
+    Ok(MergePreview { changes, hash })
+}
+
+/// Apply an export on top of the existing layout in one transaction
+/// Matched cans, racks and miners keep their ids. `expected` is the hash of the preview
+/// the user confirmed, if the diff no longer matches nothing is applied.
+pub async fn merge_sitemap(db: &SqlitePool, layout: &str, sitemap: &str, expected: &str) -> Result<Vec<LayoutChange>> {
+    let MergePreview { changes, hash } = preview_merge(db, layout, sitemap).await?;
+    if hash != expected {
+        return Err(anyhow::anyhow!("The layout changed since the preview, preview the import again"));
+    }
+
+    let mut can_ids = sqlx::query!("SELECT id, name FROM cans")
+        .fetch_all(db).await?
+        .into_iter()
+        .map(|c| (c.name, c.id))
+        .collect::<HashMap<String, i64>>();
+    let mut rack_ids = sqlx::query!("SELECT r.id, r.name, c.name AS can FROM racks r JOIN cans c ON r.can_id = c.id")
+        .fetch_all(db).await?
+        .into_iter()
+        .map(|r| ((r.can, r.name), r.id))
+        .collect::<HashMap<(String, String), i64>>();
+    let lookup = |ids: &HashMap<String, i64>, name: &str| {
+        ids.get(name).copied().ok_or_else(|| anyhow::anyhow!("Unknown can {}", name))
+    };
+    let lookup_rack = |ids: &HashMap<(String, String), i64>, slot: &SlotRef| {
+        ids.get(&(slot.can.clone(), slot.rack.clone())).copied()
+            .ok_or_else(|| anyhow::anyhow!("Unknown rack {} in {}", slot.rack, slot.can))
+    };
 
+    let mut tx = db.begin().await?;
+    // Cans and racks first so miners have somewhere to go
+    for change in &changes {
+        match change {
+            LayoutChange::AddCan { name, num } => {
+                let id = sqlx::query!("INSERT INTO cans (name, num) VALUES (?, ?)", name, num)
+                    .execute(&mut tx).await?
+                    .last_insert_rowid();
+                can_ids.insert(name.clone(), id);
+            },
+            LayoutChange::UpdateCan { id, num, .. } => {
+                sqlx::query!("UPDATE cans SET num = ? WHERE id = ?", num, id)
+                    .execute(&mut tx).await?;
+            },
+            _ => {},
+        }
+    }
+    for change in &changes {
+        match change {
+            LayoutChange::AddRack { can, name, index, width, height } => {
+                let can_id = lookup(&can_ids, can)?;
+                let id = sqlx::query!(
+                    "INSERT INTO racks (can_id, name, index_, width, height) VALUES (?, ?, ?, ?, ?)",
+                    can_id, name, index, width, height
+                ).execute(&mut tx).await?.last_insert_rowid();
+                rack_ids.insert((can.clone(), name.clone()), id);
+            },
+            LayoutChange::UpdateRack { id, name, index, width, height, .. } => {
+                let size = sqlx::query!("SELECT width, height FROM racks WHERE id = ?", id)
+                    .fetch_one(&mut tx).await?;
+                if (size.width, size.height) != (*width, *height) {
+                    DbFeed::check_relabel(&mut tx, *id).await
+                        .map_err(|e| anyhow::anyhow!("Rack {}: {}", name, e))?;
+                }
+                sqlx::query!(
+                    "UPDATE racks SET index_ = ?, width = ?, height = ? WHERE id = ?",
+                    index, width, height, id
+                ).execute(&mut tx).await?;
+            },
+            _ => {},
+        }
+    }
+
+    // Removed miners free their slots, moved miners are parked on negative rows so
+    // miners swapping places don't collide on unique_miner
+    for change in &changes {
+        match change {
+            LayoutChange::RemoveMiner { id, .. } => {
+                sqlx::query!("DELETE FROM miners WHERE id = ?", id)
+                    .execute(&mut tx).await?;
+            },
+            LayoutChange::MoveMiner { id, .. } => {
+                sqlx::query!("UPDATE miners SET row = -id WHERE id = ?", id)
+                    .execute(&mut tx).await?;
+            },
+            _ => {},
+        }
+    }
+    for change in &changes {
+        match change {
+            LayoutChange::MoveMiner { id, to, .. } => {
+                let rack_id = lookup_rack(&rack_ids, to)?;
+                sqlx::query!(
+                    "UPDATE miners SET rack_id = ?, row = ?, index_ = ? WHERE id = ?",
+                    rack_id, to.row, to.index, id
+                ).execute(&mut tx).await?;
+            },
+            LayoutChange::ChangeIp { id, new, .. } => {
+                sqlx::query!("UPDATE miners SET ip = ? WHERE id = ?", new, id)
+                    .execute(&mut tx).await?;
+            },
+            LayoutChange::AddMiner { ip, slot, port, external_id } => {
+                let rack_id = lookup_rack(&rack_ids, slot)?;
+                sqlx::query!(
+                    "INSERT INTO miners (rack_id, ip, row, index_, port, external_id) VALUES (?, ?, ?, ?, ?, ?)",
+                    rack_id, ip, slot.row, slot.index, port, external_id
+                ).execute(&mut tx).await?;
+            },
+            LayoutChange::UpdateMiner { id, port, external_id, .. } => {
+                sqlx::query!("UPDATE miners SET port = ?, external_id = ? WHERE id = ?", port, external_id, id)
+                    .execute(&mut tx).await?;
+            },
+            _ => {},
+        }
+    }
+
+    for change in &changes {
+        match change {
+            LayoutChange::RemoveRack { id, .. } => {
+                sqlx::query!("DELETE FROM miners WHERE rack_id = ?", id)
+                    .execute(&mut tx).await?;
+                sqlx::query!("DELETE FROM racks WHERE id = ?", id)
+                    .execute(&mut tx).await?;
+            },
+            LayoutChange::RemoveCan { id, .. } => {
+                sqlx::query!("DELETE FROM racks WHERE can_id = ?", id)
+                    .execute(&mut tx).await?;
+                sqlx::query!("DELETE FROM cans WHERE id = ?", id)
+                    .execute(&mut tx).await?;
+            },
+            _ => {},
+        }
+    }
+    tx.commit().await?;
+    info!("Merged sitemap with {} changes", changes.len());
+    Ok(changes)
+}
+
+fn write_csv<T: Serialize>(path: &str, records: impl Iterator<Item = T>) -> Result<()> {
+    let mut wtr = csv::Writer::from_path(path)?;
+    for record in records {
+        wtr.serialize(record)?;
+    }
+    wtr.flush()?;
     Ok(())
 }
+
+/// Write the layout and sitemap back out as CSVs Frontier can import
+/// Miners without a port get the default API port, miners without an external id an empty pickaxe_id
+pub async fn export_sitemap(db: &SqlitePool, layout: &str, sitemap: &str) -> Result<()> {
+    let cans = sqlx::query!("SELECT name, num FROM cans ORDER BY num, name")
+        .fetch_all(db).await?;
+    let racks = sqlx::query!(r#"
+        SELECT r.name, r.index_, r.width, r.height, c.name AS can, c.num AS can_num
+        FROM racks r
+        JOIN cans c ON r.can_id = c.id
+        ORDER BY c.num, c.name, r.index_
+        "#
+    ).fetch_all(db).await?;
+    let miners = sqlx::query!(r#"
+        SELECT m.ip, m.port, m.external_id, m.row, m.index_, r.name AS rack
+        FROM miners m
+        JOIN racks r ON m.rack_id = r.id
+        JOIN cans c ON r.can_id = c.id
+        WHERE m.ip != ''
+        ORDER BY c.num, c.name, r.index_, m.row, m.index_
+        "#
+    ).fetch_all(db).await?;
+
+    let groups = cans.iter().map(|can| RackRecord {
+        group_name: None,
+        type_: RackRecordType::Group,
+        name: can.name.clone(),
+        row: can.num,
+        column: 0,
+        rack_width: None,
+        rack_height: None,
+    });
+    // Racks sit on the row of their group
+    let rack_records = racks.into_iter().map(|rack| RackRecord {
+        group_name: Some(rack.can),
+        type_: RackRecordType::Rack,
+        name: rack.name,
+        row: rack.can_num,
+        column: rack.index_,
+        rack_width: Some(rack.width),
+        rack_height: Some(rack.height),
+    });
+    let sitemap_records = miners.iter().map(|miner| SitemapRecord {
+        pickaxe_id: miner.external_id.clone().unwrap_or_default(),
+        miner_ip: miner.ip.clone(),
+        miner_port: miner.port.map(|p| p as u16).unwrap_or(crate::cgminer::API_PORT),
+        rack: miner.rack.clone(),
+        row: miner.row,
+        index: miner.index_,
+    });
+
+    // Both files are written aside and only moved into place once both are complete,
+    // a failed export leaves the previous pair untouched
+    let layout_tmp = format!("{}.tmp", layout);
+    let sitemap_tmp = format!("{}.tmp", sitemap);
+    let written = write_csv(&layout_tmp, groups.chain(rack_records))
+        .and_then(|_| write_csv(&sitemap_tmp, sitemap_records));
+    if let Err(e) = written {
+        let _ = std::fs::remove_file(&layout_tmp);
+        let _ = std::fs::remove_file(&sitemap_tmp);
+        return Err(e);
+    }
+    std::fs::rename(&layout_tmp, layout)?;
+    std::fs::rename(&sitemap_tmp, sitemap)?;
+    info!("Exported {} cans, {} miners to {} and {}", cans.len(), miners.len(), layout, sitemap);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    #[tokio::test]
+    async fn import_keeps_areas() {
+        let db = db::test_db().await;
+        let area = sqlx::query("INSERT INTO areas (name, kind) VALUES ('Building A', 'building')")
+            .execute(&db).await.unwrap()
+            .last_insert_rowid();
+        sqlx::query("INSERT INTO cans (name, num, area_id) VALUES ('C1', 1, ?), ('C9', 9, ?)")
+            .bind(area).bind(area)
+            .execute(&db).await.unwrap();
+
+        let layout = db::test_path("layout.csv");
+        let sitemap = db::test_path("sitemap.csv");
+        std::fs::write(&layout, "group_name,type,name,row,column,rack_width,rack_height\n,group,C1,1,0,,\n,group,C2,2,0,,\nC1,rack,R1,1,0,2,2\n").unwrap();
+        std::fs::write(&sitemap, "pickaxe_id,miner_ip,miner_port,rack,row,index\np1,10.0.0.1,4028,R1,0,0\n").unwrap();
+        assert!(import_sitemap(&db, &layout, &sitemap).await.unwrap().is_empty());
+
+        let cans: Vec<(String, Option<i64>)> = sqlx::query_as("SELECT name, area_id FROM cans ORDER BY name")
+            .fetch_all(&db).await.unwrap();
+        assert_eq!(cans, [("C1".to_string(), Some(area)), ("C2".to_string(), None)]);
+        let _ = std::fs::remove_file(&layout);
+        let _ = std::fs::remove_file(&sitemap);
+    }
+}
@@ -21,7 +21,11 @@ mod sleep;
 mod miner;
 mod logs;
 mod profile;
-pub use miner::Miner;
+mod preset;
+mod budget;
+mod curtail;
+pub use miner::{Miner, MinerContext};
+pub use sleep::SleepJob;
 
 #[derive(Serialize, Debug, Clone)]
 pub struct Progress {
@@ -57,6 +61,28 @@ impl Progress {
     }
 }
 
+/// The session's credential vault, jobs read secrets through it
+pub async fn vault(app: &AppHandle) -> crate::db::Vault {
+    app.state::<Mutex<crate::db::Vault>>().lock().await.clone()
+}
+
+/// Miner credentials for jobs that log into miners, a locked vault is an error
+pub async fn miner_auths(db: &SqlitePool, app: &AppHandle) -> Result<crate::db::MinerAuth> {
+    let vault = vault(app).await;
+    if vault.locked(db).await? {
+        return Err(anyhow::anyhow!(crate::db::LOCKED));
+    }
+    crate::db::MinerAuth::load(db, &vault).await
+}
+
+/// How the prepared futures of a job ended
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Outcome {
+    pub failed: usize,
+    /// Stopped by a cancel before they finished
+    pub cancelled: usize,
+}
+
 #[async_trait]
 pub trait JobDef {
     /// Prepare jobs for execution
@@ -68,6 +94,11 @@ pub trait JobDef {
         app: AppHandle,
         client: Client,
     ) -> Result<Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>>;
+
+    /// Called once every prepared future has finished or been cancelled
+    async fn complete(&self, _db: &SqlitePool, _app: AppHandle, _outcome: Outcome) -> Result<()> {
+        Ok(())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -80,6 +111,8 @@ pub enum Job {
     Sleep(sleep::SleepJob),
     Log(logs::LogJob),
     Profile(profile::ProfileJob),
+    Preset(preset::PresetJob),
+    Curtail(curtail::CurtailJob),
 }
 
 impl Deref for Job {
@@ -94,11 +127,27 @@ impl Deref for Job {
             Job::Sleep(job) => job,
             Job::Log(job) => job,
             Job::Profile(job) => job,
+            Job::Preset(job) => job,
+            Job::Curtail(job) => job,
         }
     }
 }
 
+/// Record a job in the history, monitoring scans are left out as they run constantly
+async fn record(job: &Job, db: &SqlitePool, tasks: usize) -> Result<Option<i64>> {
+    if let Job::Scan(_) = job {
+        return Ok(None);
+    }
+    let detail = serde_json::to_value(job)?;
+    let kind = detail["job"].as_str().unwrap_or_default().to_string();
+    let id = crate::db::JobRun::start(db, &kind, &detail.to_string(), tasks).await?;
+    Ok(Some(id))
+}
+
 pub struct JobRunner {
+    job: Job,
+    db: SqlitePool,
+    app: AppHandle,
     tasks: Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>,
     cancel: broadcast::Sender<()>,
     progress: Arc<Mutex<Progress>>,
@@ -109,10 +158,13 @@ impl JobRunner {
     /// Primarily to handle the cancellation of jobs
     /// Returns a JobRunner and a broadcast::Sender
     pub async fn new(job: Job, db: &SqlitePool, app: AppHandle, client: Client) -> Result<(Self, broadcast::Sender<()>)> {
-        let tasks = job.prepare(&db, app.clone(), client).await?;
+        let tasks = job.prepare(db, app.clone(), client).await?;
         let (cancel, _) = broadcast::channel(1);
-        let progress = Arc::new(Mutex::new(Progress::new(app, "".to_string(), tasks.len())));
+        let progress = Arc::new(Mutex::new(Progress::new(app.clone(), "".to_string(), tasks.len())));
         Ok((Self {
+            job,
+            db: db.clone(),
+            app,
             tasks,
             cancel: cancel.clone(),
             progress,
@@ -121,6 +173,12 @@ impl JobRunner {
 
     pub async fn run(self) -> Result<()> {
         let _ = self.progress.lock().await.emit();
+        // Job history is for reports, it shouldn't stop the job
+        let run = record(&self.job, &self.db, self.tasks.len()).await.unwrap_or_else(|e| {
+            tracing::warn!("Failed to record job: {}", e);
+            None
+        });
+        let mut outcome = Outcome::default();
         let mut futures = vec![];
         for task in self.tasks {
             let progress = self.progress.clone();
@@ -128,10 +186,10 @@ impl JobRunner {
             futures.push(
                 tokio::spawn(async move {
                     tokio::select! {
-                        _ = cancel.recv() => {Ok(())}
+                        _ = cancel.recv() => None,
                         res = task => {
                             progress.lock().await.increment().unwrap();
-                            res
+                            Some(res)
                         }
                     }
                 })
@@ -140,17 +198,24 @@ impl JobRunner {
 
         for future in futures {
             match future.await {
-                Ok(res) => {
-                    if let Err(e) = res {
-                        eprintln!("Error: {}", e);
-                    }
+                Ok(Some(Ok(()))) => {}
+                Ok(Some(Err(e))) => {
+                    outcome.failed += 1;
+                    eprintln!("Error: {}", e);
                 }
+                Ok(None) => outcome.cancelled += 1,
                 Err(e) => {
+                    outcome.failed += 1;
                     eprintln!("Failed to join: {:?}", e);
                 }
             }
         }
+        if let Some(run) = run {
+            if let Err(e) = crate::db::JobRun::finish(&self.db, run, outcome.failed, outcome.cancelled).await {
+                tracing::warn!("Failed to record job: {}", e);
+            }
+        }
 
-        Ok(())
+        self.job.complete(&self.db, self.app, outcome).await
     }
 }
@@ -0,0 +1,116 @@
+//! Keep jobs that raise power draw within the budgets of the circuits feeding the miners
+//!
+//! A job that would leave a circuit above its capacity is refused. A job that only goes over
+//! while miners start up, such as a reboot, is split into stages that each fit, started
+//! `stage_delay` seconds apart.
+
+use std::collections::{HashMap, HashSet};
+use std::future::Future;
+use std::time::Duration;
+
+use sqlx::sqlite::SqlitePool;
+use anyhow::Result;
+
+use crate::db::{settings, Config, PowerMap};
+
+/// Watts a miner draws once the job is done and at most while it's being applied
+pub(super) struct Change {
+    pub end: f64,
+    pub peak: f64,
+}
+
+/// Stage of every miner, miners not listed start straight away
+pub(super) struct Stages {
+    stages: HashMap<String, u32>,
+    delay: Duration,
+}
+
+impl Stages {
+    /// For jobs that can only lower power draw
+    pub fn none() -> Self {
+        Self { stages: HashMap::new(), delay: Duration::ZERO }
+    }
+
+    /// How long the miner waits for earlier stages
+    pub fn delay(&self, ip: &str) -> Duration {
+        self.delay * self.stages.get(ip).copied().unwrap_or(0)
+    }
+}
+
+/// Check a job against every circuit it touches and stage it where starting at once would go over
+/// `change` gets a miner's IP, current and estimated draw, `action` names the job in the error
+pub(super) async fn plan(
+    db: &SqlitePool,
+    action: &str,
+    ips: &[String],
+    change: impl Fn(&str, f64, f64) -> Change,
+) -> Result<Stages> {
+    let config: Config = settings::load(db).await?;
+    let estimate = config.miner_power;
+    let map = PowerMap::load(db).await?;
+    let targets = ips.iter().map(|ip| ip.as_str()).collect::<HashSet<_>>();
+
+    let mut stages = HashMap::new();
+    let mut over = vec![];
+    for circuit in &map.circuits {
+        let mut pending = vec![];
+        for ip in circuit.ips.iter().filter(|ip| targets.contains(ip.as_str())) {
+            let current = map.draw(ip, estimate);
+            let change = change(ip, current, estimate);
+            if change.end > current || change.peak > current {
+                pending.push((ip, current, change));
+            }
+        }
+        if pending.is_empty() {
+            continue;
+        }
+
+        let mut settled = map.circuit_load(circuit, estimate).watts;
+        let end = settled + pending.iter().map(|(_, current, change)| change.end - current).sum::<f64>();
+        if end > circuit.capacity {
+            over.push(format!("{} would carry {:.0} W of its {:.0} W", circuit.name, end, circuit.capacity));
+            continue;
+        }
+        // Fill each stage with what fits on top of the miners already done
+        let mut stage = 0;
+        while !pending.is_empty() {
+            let mut load = settled;
+            let mut next = vec![];
+            let waiting = pending.len();
+            for (ip, current, change) in pending {
+                let extra = change.peak.max(change.end) - current;
+                if load + extra <= circuit.capacity {
+                    load += extra;
+                    settled += change.end - current;
+                    stages.insert(ip.to_string(), stage);
+                } else {
+                    next.push((ip, current, change));
+                }
+            }
+            if next.len() == waiting {
+                over.push(format!("{} can't start another miner without going over {:.0} W", circuit.name, circuit.capacity));
+                break;
+            }
+            pending = next;
+            stage += 1;
+        }
+    }
+    if !over.is_empty() {
+        return Err(anyhow::anyhow!("Refusing to {}:\n{}", action, over.join("\n")));
+    }
+    if let Some(last) = stages.values().max().filter(|s| **s > 0) {
+        tracing::info!("Staging {} in {} stages to stay within circuit budgets", action, last + 1);
+    }
+    Ok(Stages {
+        stages,
+        delay: Duration::from_secs(config.stage_delay),
+    })
+}
+
+/// Run a task once its stage comes up
+pub(super) async fn staged(delay: Duration, task: impl Future<Output = Result<()>>) -> Result<()> {
+    if !delay.is_zero() {
+        tokio::time::sleep(delay).await;
+    }
+    task.await
+}
@@ -0,0 +1,176 @@
+use async_trait::async_trait;
+use sqlx::sqlite::SqlitePool;
+use tauri::AppHandle;
+use libminer::Client;
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use std::collections::HashMap;
+use std::pin::Pin;
+use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Mutex;
+
+use crate::curtail::{self, Action, Curtailment, CurtailmentAction};
+use crate::db::Node;
+use crate::models::Profile;
+
+use super::{JobDef, Outcome};
+use super::Miner;
+use super::budget::{self, Change, staged};
+
+async fn apply(miner: Miner, action: Action, db: SqlitePool, action_id: i64) -> Result<()> {
+    let res = match action {
+        Action::Sleep => miner.set_sleep(true).await,
+        Action::Profile { profile, .. } => miner.set_profile(profile.into()).await,
+    };
+    Curtailment::action_applied(&db, action_id, res.as_ref().err().map(|e| e.to_string())).await?;
+    res
+}
+
+async fn verify(mut miner: Miner, db: SqlitePool, action_id: i64) -> Result<()> {
+    let res = miner.load().await;
+    // Sleeping miners often don't report power at all
+    let after = res.as_ref().ok().and(miner.power.or(if miner.sleep { Some(0.0) } else { None }));
+    Curtailment::action_after(&db, action_id, after).await?;
+    res?;
+    miner.emit()
+}
+
+async fn reverse(miner: Miner, action: Action, db: SqlitePool, action_id: i64) -> Result<()> {
+    match action {
+        Action::Sleep => miner.set_sleep(false).await?,
+        Action::Profile { previous, .. } => miner.set_profile(previous.unwrap_or(Profile::Default).into()).await?,
+    }
+    Curtailment::action_reversed(&db, action_id).await
+}
+
+/// Curtail a site or part of it to a target, then verify or reverse it by id
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(tag = "step", rename_all = "snake_case")]
+pub enum CurtailJob {
+    Apply {
+        /// kW to bring the scope down to
+        target: f64,
+        scope: Vec<Node>,
+        /// Stored curtailment, marked applied once every miner is done
+        #[serde(skip)]
+        id: Arc<Mutex<Option<i64>>>,
+    },
+    Verify { id: i64 },
+    Reverse { id: i64 },
+}
+
+/// Load a curtailment and the miners of the actions picked by `keep`
+async fn load(
+    db: &SqlitePool,
+    id: i64,
+    client: Client,
+    app: AppHandle,
+    keep: impl Fn(&CurtailmentAction) -> bool,
+) -> Result<(Curtailment, Vec<Miner>)> {
+    let mut curtailment = Curtailment::get(db, id).await?;
+    curtailment.actions.retain(|a| keep(a));
+    let ips = curtailment.actions.iter().map(|a| a.ip.clone()).collect::<Vec<_>>();
+    let miners = Miner::from_ips(&ips, db, client, app).await?;
+    Ok((curtailment, miners))
+}
+
+#[async_trait]
+impl JobDef for CurtailJob {
+    async fn prepare(
+        &self,
+        db: &SqlitePool,
+        app: AppHandle,
+        client: Client,
+    ) -> Result<Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>> {
+        let mut futures = Vec::new();
+        match self {
+            CurtailJob::Apply { target, scope, id } => {
+                let plan = curtail::plan(db, *target, scope).await?;
+                if plan.actions.is_empty() {
+                    return Err(anyhow::anyhow!("Already at or below {} kW", target));
+                }
+                let (curtailment, action_ids) = Curtailment::create(db, &plan, scope).await?;
+                *id.lock().await = Some(curtailment);
+                let ips = plan.actions.iter().map(|a| a.ip.clone()).collect::<Vec<_>>();
+                let mut actions = plan.actions.into_iter()
+                    .zip(action_ids)
+                    .map(|(a, id)| (a.ip, (a.action, id)))
+                    .collect::<HashMap<_, _>>();
+                for miner in Miner::from_ips(&ips, db, client, app).await? {
+                    if let Some((action, action_id)) = actions.remove(&miner.ip) {
+                        futures.push(
+                            Box::pin(apply(miner, action, db.clone(), action_id))
+                            as Pin<Box<dyn Future<Output = Result<()>> + Send>>
+                        );
+                    }
+                }
+            },
+            CurtailJob::Verify { id } => {
+                let state = Curtailment::get(db, *id).await?.state;
+                if state != curtail::APPLIED && state != curtail::VERIFIED {
+                    return Err(anyhow::anyhow!("Curtailment {} is {}", id, state));
+                }
+                let (curtailment, miners) = load(db, *id, client, app, |a| a.applied).await?;
+                let actions = curtailment.actions.into_iter()
+                    .map(|a| (a.ip, a.id))
+                    .collect::<HashMap<_, _>>();
+                for miner in miners {
+                    let action_id = actions[&miner.ip];
+                    futures.push(
+                        Box::pin(verify(miner, db.clone(), action_id))
+                        as Pin<Box<dyn Future<Output = Result<()>> + Send>>
+                    );
+                }
+            },
+            CurtailJob::Reverse { id } => {
+                // Whatever a cancelled or partly failed reverse left behind is retried
+                let (curtailment, miners) = load(db, *id, client, app, |a| a.reversible()).await?;
+                if curtailment.state == curtail::REVERSED {
+                    return Err(anyhow::anyhow!("Curtailment {} is already reversed", id));
+                }
+                let mut actions = curtailment.actions.into_iter()
+                    .map(|a| (a.ip.clone(), a))
+                    .collect::<HashMap<_, _>>();
+                // Miners go back to what they drew before the curtailment
+                let ips = miners.iter().map(|m| m.ip.clone()).collect::<Vec<_>>();
+                let stages = budget::plan(db, "reverse the curtailment", &ips, |ip, current, _| {
+                    let end = actions.get(ip).map_or(current, |a| a.before);
+                    Change { end, peak: current.max(end) }
+                }).await?;
+                for miner in miners {
+                    if let Some(action) = actions.remove(&miner.ip) {
+                        futures.push(
+                            Box::pin(staged(stages.delay(&miner.ip), reverse(miner, action.action, db.clone(), action.id)))
+                            as Pin<Box<dyn Future<Output = Result<()>> + Send>>
+                        );
+                    }
+                }
+            },
+        }
+        Ok(futures)
+    }
+
+    async fn complete(&self, db: &SqlitePool, _app: AppHandle, outcome: Outcome) -> Result<()> {
+        match self {
+            // A cancelled apply stays applying, it can still be reversed
+            CurtailJob::Apply { id, .. } => {
+                if let (Some(id), 0) = (*id.lock().await, outcome.cancelled) {
+                    Curtailment::set_state(db, id, curtail::APPLIED).await?;
+                }
+            },
+            CurtailJob::Verify { id } => {
+                if outcome.cancelled == 0 {
+                    Curtailment::verified(db, *id).await?;
+                }
+            },
+            CurtailJob::Reverse { id } => {
+                let curtailment = Curtailment::get(db, *id).await?;
+                if !curtailment.actions.iter().any(|a| a.reversible()) {
+                    Curtailment::set_state(db, *id, curtail::REVERSED).await?;
+                }
+            },
+        }
+        Ok(())
+    }
+}
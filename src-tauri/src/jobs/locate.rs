@@ -30,13 +30,11 @@ impl JobDef for LocateJob {
         client: Client,
     ) -> Result<Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>> {
         let mut futures = Vec::new();
-        for ip in &self.ips {
-            if let Ok(miner) = Miner::new(ip.clone(), db, client.clone(), app.clone()).await {
-                futures.push(
-                    Box::pin(set_locate(miner, self.locate))
-                    as Pin<Box<dyn Future<Output = Result<()>> + Send>>
-                );
-            }
+        for miner in Miner::from_ips(&self.ips, db, client, app).await? {
+            futures.push(
+                Box::pin(set_locate(miner, self.locate))
+                as Pin<Box<dyn Future<Output = Result<()>> + Send>>
+            );
         }
         Ok(futures)
     }
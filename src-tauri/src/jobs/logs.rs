@@ -19,7 +19,7 @@ async fn log(ip: String, client: Client, auths: db::MinerAuth, folder: String) -
         let auths = auths.get(&make);
         let mut authed = false;
         for auth in auths {
-            if let Ok(_) = miner.auth(&auth.username, &auth.password).await {
+            if miner.auth(&auth.username, &auth.password).await.is_ok() {
                 authed = true;
                 break;
             }
@@ -51,10 +51,10 @@ impl JobDef for LogJob {
     async fn prepare(
         &self,
         db: &SqlitePool,
-        _app: AppHandle,
+        app: AppHandle,
         client: Client,
     ) -> Result<Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>> {
-        let auths = db::MinerAuth::load(db).await?;
+        let auths = super::miner_auths(db, &app).await?;
         let mut futures = vec![];
         for ip in &self.ips {
             futures.push(
@@ -1,36 +1,82 @@
+use std::cmp::Ordering;
+use std::sync::Arc;
+
 use tauri::{AppHandle, Manager};
 use sqlx::sqlite::SqlitePool;
 use anyhow::Result;
 
-use crate::models::{MinerEvent, self};
+use crate::models::{MinerEvent, Hashboard, Condition, PoolStatus, self};
 use libminer::{Client, Profile};
-use crate::db;
+use crate::{cgminer, db};
 
-pub static HASH_MAP: phf::Map<&'static str, &'static str> = phf::phf_map! {
-    // T19
-    "NBT1903" => "240-Ca",
+/// Models fitted with other than three chains, matched against a word of the model name
+const MODEL_BOARDS: &[(&str, u32)] = &[("L3+", 4), ("L3++", 4), ("L7", 4)];
+/// Rejected and stale shares above this fraction of all shares are flagged
+const MAX_REJECT_RATE: f64 = 0.05;
+/// Don't judge the reject rate until a pool has seen this many shares
+const MIN_SHARES: u64 = 20;
 
-    // S19
-    "NBS1902" => "240-Ca",
-    "BHB42801" => "240-Ch",
-    "BHB42831" => "240-Ch",
+/// Number of chains a model runs
+fn expected_boards(model: Option<&str>) -> u32 {
+    model.and_then(|model| {
+        model.split_whitespace()
+            .find_map(|word| MODEL_BOARDS.iter().find(|(name, _)| name.eq_ignore_ascii_case(word)))
+            .map(|(_, boards)| *boards)
+    }).unwrap_or(3)
+}
 
-    // S19a
-    "BHB28611" => "240-Ce",
+/// Connections and lookups shared by every miner of a job
+#[derive(Clone)]
+pub struct MinerContext {
+    pub app: AppHandle,
+    pub client: Client,
+    pub auths: db::MinerAuth,
+    /// The vault is locked, miners are read without logging in
+    pub locked: bool,
+    pub catalog: Arc<db::HashboardCatalog>,
+}
 
-    // S19 Pro
-    "BHB42601" => "240-C",
-    "BHB42651" => "j1-11",
+impl MinerContext {
+    /// For jobs that change miners, fails while the vault is locked
+    pub async fn load(db: &SqlitePool, app: AppHandle, client: Client) -> Result<Self> {
+        Ok(Self {
+            auths: super::miner_auths(db, &app).await?,
+            locked: false,
+            catalog: Arc::new(db::HashboardCatalog::load(db).await?),
+            app,
+            client,
+        })
+    }
 
-    // S19j Pro
-    "BHB42603" => "240-Cb",
-    "BHB42631" => "j1-11",
-};
+    /// For jobs that only read miners, a locked vault falls back to reading without credentials
+    pub async fn load_read(db: &SqlitePool, app: AppHandle, client: Client) -> Result<Self> {
+        let vault = super::vault(&app).await;
+        let locked = vault.locked(db).await?;
+        let auths = if locked {
+            tracing::info!("Credentials are locked, reading miners without logging in");
+            db::MinerAuth::default()
+        } else {
+            db::MinerAuth::load(db, &vault).await?
+        };
+        Ok(Self {
+            auths,
+            locked,
+            catalog: Arc::new(db::HashboardCatalog::load(db).await?),
+            app,
+            client,
+        })
+    }
+}
 
 pub struct Miner {
     pub ip: String,
+    /// cgminer API port, None uses `cgminer::API_PORT`
+    pub port: Option<u16>,
+    /// Reference to the miner in the tool the layout was imported from
+    pub external_id: Option<String>,
     pub make: Option<String>,
     pub model: Option<String>,
+    pub submodel: Option<String>,
     pub mac: Option<String>,
     pub hashrate: Option<f64>,
     pub temp: Option<f64>,
@@ -38,17 +84,22 @@ pub struct Miner {
     pub uptime: Option<f64>,
     pub errors: Vec<String>,
     pub pools: Vec<libminer::Pool>,
+    pub pool_stats: Vec<PoolStatus>,
     pub nameplate: Option<f64>,
     pub power: Option<f64>,
     pub efficiency: Option<f64>,
     pub profile: Option<Profile>,
     pub profiles: Option<Vec<Profile>>,
     pub hashboard: Option<String>,
+    pub boards: Vec<Hashboard>,
+    pub conditions: Vec<Condition>,
     pub sleep: bool,
     pub locate: bool,
     pub client: Client,
     pub app: AppHandle,
     pub auths: db::MinerAuth,
+    pub locked: bool,
+    pub catalog: Arc<db::HashboardCatalog>,
     pub can: i64,
     pub rack: i64,
     pub row: i64,
@@ -62,14 +113,15 @@ impl Miner {
         row: i64,
         index: i64,
         can: i64,
-        app: AppHandle,
-        client: Client,
-        auths: db::MinerAuth,
+        context: MinerContext,
     ) -> Self {
         Self {
             ip,
+            port: None,
+            external_id: None,
             make: None,
             model: None,
+            submodel: None,
             mac: None,
             hashrate: None,
             temp: None,
@@ -77,17 +129,22 @@ impl Miner {
             uptime: None,
             errors: Vec::new(),
             pools: Vec::new(),
+            pool_stats: Vec::new(),
             power: None,
             efficiency: None,
             profile: None,
             profiles: None,
             hashboard: None,
+            boards: Vec::new(),
+            conditions: Vec::new(),
             sleep: false,
             locate: false,
             nameplate: None,
-            client,
-            app,
-            auths,
+            client: context.client,
+            app: context.app,
+            auths: context.auths,
+            locked: context.locked,
+            catalog: context.catalog,
             can,
             rack,
             row,
@@ -95,51 +152,41 @@ impl Miner {
         }
     }
 
-    pub async fn new(ip: String, db: &SqlitePool, client: Client, app: AppHandle) -> Result<Self> {
-        let miner = db::DbMiner::find_ip(db, &ip).await?;
-        let rack = miner.get_rack(db).await?;
-        let can = db::DbCan::get(db, rack.can_id).await?;
-        let auths = db::MinerAuth::load(db).await?;
+    /// Build miners for a list of IPs with a fixed number of queries
+    /// IPs that aren't in the layout are skipped
+    pub async fn from_ips(ips: &[String], db: &SqlitePool, client: Client, app: AppHandle) -> Result<Vec<Self>> {
+        let locations = db::DbMiner::locations(db).await?;
+        let context = MinerContext::load(db, app, client).await?;
 
-        Ok(Self {
-            ip,
-            make: None,
-            model: None,
-            mac: None,
-            hashrate: None,
-            temp: None,
-            fan: None,
-            uptime: None,
-            errors: Vec::new(),
-            pools: Vec::new(),
-            power: None,
-            efficiency: None,
-            profile: None,
-            profiles: None,
-            hashboard: None,
-            sleep: false,
-            locate: false,
-            nameplate: None,
-            client,
-            app,
-            auths,
-            can: can.num,
-            rack: rack.index,
-            row: miner.row,
-            index: miner.index,
-        })
+        let mut miners = vec![];
+        for ip in ips {
+            match locations.get(ip) {
+                Some(loc) => miners.push(Self {
+                    port: loc.port,
+                    external_id: loc.external_id.clone(),
+                    ..Self::default(
+                        ip.clone(),
+                        loc.rack, loc.row, loc.index, loc.can,
+                        context.clone(),
+                    )
+                }),
+                None => tracing::warn!("{} is not in the layout", ip),
+            }
+        }
+        Ok(miners)
     }
 
-    pub fn emit(&self) -> Result<()> {
-        let event = MinerEvent {
+    pub fn event(&self) -> MinerEvent {
+        MinerEvent {
             rack: self.rack,
             row: self.row,
             index: self.index,
             miner: models::Miner {
                 ip: self.ip.clone(),
+                external_id: self.external_id.clone(),
                 make: self.make.clone(),
                 model: self.model.clone(),
-                submodel: self.hashboard.clone().map(|x| HASH_MAP.get(x.as_str()).map(|s| s.to_string())).unwrap_or(None),
+                submodel: self.submodel.clone(),
                 mac: self.mac.clone(),
                 hashrate: self.hashrate,
                 temp: self.temp,
@@ -149,31 +196,40 @@ impl Miner {
                 uptime: self.uptime,
                 errors: self.errors.clone(),
                 pools: self.pools.clone(),
+                pool_stats: self.pool_stats.clone(),
                 profile: self.profile.clone().map(|x| x.into()),
                 profiles: self.profiles.clone().map(|x| x.into_iter().map(|x| x.into()).collect()),
                 hashboard: self.hashboard.clone(),
+                boards: self.boards.clone(),
+                conditions: self.conditions.clone(),
+                anomalies: Vec::new(),
                 sleep: self.sleep,
                 locate: self.locate,
                 nameplate: self.nameplate,
             },
-        };
-        self.app.emit_all("miner", event)?;
+        }
+    }
+
+    pub fn emit(&self) -> Result<()> {
+        self.app.emit_all("miner", self.event())?;
         Ok(())
     }
 
     pub async fn get_miner(&mut self) -> Result<Box<dyn libminer::Miner + Send + Sync>> {
+        // `port` is only the cgminer API port, libminer reaches the web interface on its default
         if let Ok(mut miner) = self.client.get_miner(&self.ip, None).await {
             self.make = Some(miner.get_type().to_string());
             
             let auths = self.auths.get(self.make.as_ref().unwrap());
             let mut authed = false;
             for auth in auths {
-                if let Ok(_) = miner.auth(&auth.username, &auth.password).await {
+                if miner.auth(&auth.username, &auth.password).await.is_ok() {
                     authed = true;
                     break;
                 }
             }
-            if !authed {
+            // Without the vault there was nothing to try, that's not the miner's fault
+            if !authed && !self.locked {
                 self.errors.push("Failed to auth miner".to_string());
             }
             Ok(miner)
@@ -183,7 +239,7 @@ impl Miner {
     }
 
     pub async fn load(&mut self) -> Result<()> {
-        if let Ok(mut miner) = self.get_miner().await {
+        if let Ok(miner) = self.get_miner().await {
             self.model = Some(miner.get_model().await.unwrap_or("Unknown".to_string()));
             self.hashrate = Some(miner.get_hashrate().await.unwrap_or(0.0));
             self.temp = miner.get_temperature().await.ok();
@@ -197,6 +253,16 @@ impl Miner {
             self.profile = miner.get_profile().await.ok();
             self.profiles = miner.get_profiles().await.ok();
             self.hashboard = miner.get_hashboard().await.ok();
+            let status = cgminer::get_status(&self.ip, self.port, self.make.as_deref()).await.unwrap_or_default();
+            self.boards = status.boards;
+            self.pool_stats = status.pools;
+            let mut chips = None;
+            if let Some(hashboard) = &self.hashboard {
+                if let Some(entry) = self.catalog.lookup(hashboard, self.model.as_deref(), &self.ip).await {
+                    self.submodel = Some(entry.submodel.clone());
+                    chips = entry.chips.map(|c| c as u32);
+                }
+            }
             // query errors if we're less than 80% of the nameplate rate
             // or if we're not hashing at all
             
@@ -223,6 +289,8 @@ impl Miner {
             if let Ok(sleep) = miner.get_sleep().await {
                 self.sleep = sleep;
             }
+            self.check_boards(chips);
+            self.check_pools();
 
             Ok(())
         } else {
@@ -230,31 +298,111 @@ impl Miner {
         }
     }
 
+    /// Derive board conditions from the per chain data
+    /// `chips` is the catalog's chip count for this miner's hashboard
+    fn check_boards(&mut self, chips: Option<u32>) {
+        // Fall back to the best board on this miner
+        let expected = chips.or_else(|| self.boards.iter().filter_map(|b| b.chips).max());
+        for board in &mut self.boards {
+            board.expected_chips = expected;
+        }
+
+        // A sleeping miner reports idle chains, and some firmwares don't expose chains at all
+        if self.boards.is_empty() || self.sleep {
+            return;
+        }
+
+        let found = self.boards.len() as u32;
+        let expected = expected_boards(self.model.as_deref());
+        if found < expected {
+            self.conditions.push(Condition::MissingBoard { found, expected });
+        }
+
+        let mut rates = self.boards.iter()
+            .filter_map(|b| b.hashrate)
+            .filter(|r| *r > 0.0)
+            .collect::<Vec<f64>>();
+        rates.sort_by(|a, b| a.partial_cmp(b).unwrap_or(Ordering::Equal));
+        let median = rates.get(rates.len() / 2).copied();
+
+        for board in &self.boards {
+            if board.chips == Some(0) || board.hashrate == Some(0.0) {
+                self.conditions.push(Condition::DeadBoard { board: board.index });
+                continue;
+            }
+            if let (Some(chips), Some(expected)) = (board.chips, board.expected_chips) {
+                if chips < expected {
+                    self.conditions.push(Condition::UnderperformingBoard {
+                        board: board.index,
+                        reason: format!("{}/{} chips", chips, expected),
+                    });
+                    continue;
+                }
+            }
+            // Flag boards running under 80% of their siblings
+            if let (Some(rate), Some(median)) = (board.hashrate, median) {
+                if rate < median * 0.8 {
+                    self.conditions.push(Condition::UnderperformingBoard {
+                        board: board.index,
+                        reason: format!("{:.2} TH/s vs {:.2} TH/s", rate, median),
+                    });
+                }
+            }
+        }
+    }
+
+    /// Derive pool conditions from the reported share counters
+    fn check_pools(&mut self) {
+        if self.sleep {
+            return;
+        }
+
+        // Hashing to a dead pool, or every pool is down
+        let active = self.pool_stats.iter().find(|p| p.active);
+        match active {
+            Some(pool) if pool.status != "Alive" => {
+                self.conditions.push(Condition::DeadPool { url: pool.url.clone() });
+            },
+            None if !self.pool_stats.is_empty() && self.pool_stats.iter().all(|p| p.status != "Alive") => {
+                for pool in &self.pool_stats {
+                    self.conditions.push(Condition::DeadPool { url: pool.url.clone() });
+                }
+            },
+            _ => {},
+        }
+
+        for pool in &self.pool_stats {
+            let bad = pool.rejected + pool.stale;
+            let total = pool.accepted + bad;
+            if total < MIN_SHARES {
+                continue;
+            }
+            let rate = bad as f64 / total as f64;
+            if rate > MAX_REJECT_RATE {
+                self.conditions.push(Condition::HighRejectRate { url: pool.url.clone(), rate });
+            }
+        }
+    }
+
     pub async fn scan(mut self) -> Result<()> {
         self.load().await?;
-        Ok(self.emit()?)
+        self.emit()
     }
 
-    pub async fn set_pool(mut self, pools: db::Pool) -> Result<()> {
-        let mut miner = self.get_miner().await?;
-        let mut worker = pools.username.clone();
+    /// Model name as used in worker names
+    pub async fn worker_model(miner: &(dyn libminer::Miner + Send + Sync)) -> Result<String> {
         let mut model = miner.get_model().await?.to_lowercase();
         // Special case for Vnish, s19-88 becomes s19
         if model.contains("-") {
             model = model.split("-").collect::<Vec<&str>>()[0].to_string();
         }
-        if worker.contains("{can}") {
-            worker = worker.replace("{can}", self.can.to_string().as_str());
-        }
-        if worker.contains("{model}") {
-            worker = worker.replace("{model}", &model);
-        }
-        if worker.contains("{ip}") {
-            // Take the last 2 octets of the IP address
-            let ip = self.ip.split('.').collect::<Vec<&str>>();
-            let ip = format!("{}x{}", ip[ip.len() - 2], ip[ip.len() - 1]);
-            worker = worker.replace("{ip}", &ip);
-        }
+        Ok(model)
+    }
+
+    pub async fn set_pool(mut self, pools: db::Pool) -> Result<()> {
+        let mut miner = self.get_miner().await?;
+        let model = Miner::worker_model(miner.as_ref()).await?;
+        let worker = pools.worker(self.can, &model, &self.ip);
 
         let pools = vec![
             libminer::Pool {
@@ -276,30 +424,44 @@ impl Miner {
     
         miner.set_pools(pools).await?;
         self.pools = miner.get_pools().await?;
-        Ok(self.scan().await?)
+        self.scan().await
     }
 
     pub async fn set_blink(mut self, blink: bool) -> Result<()> {
         let mut miner = self.get_miner().await?;
         miner.set_blink(blink).await?;
-        Ok(self.scan().await?)
+        self.scan().await
     }
 
     pub async fn set_sleep(mut self, sleep: bool) -> Result<()> {
         let mut miner = self.get_miner().await?;
         miner.set_sleep(sleep).await?;
-        Ok(self.scan().await?)
+        self.scan().await
     }
 
     pub async fn reboot(mut self) -> Result<()> {
         let mut miner = self.get_miner().await?;
         miner.reboot().await?;
-        Ok(self.scan().await?)
+        self.scan().await
     }
 
     pub async fn set_profile(mut self, profile: Profile) -> Result<()> {
         let mut miner = self.get_miner().await?;
-        miner.set_profile(profile.into()).await?;
-        Ok(self.scan().await?)
+        miner.set_profile(profile).await?;
+        self.scan().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn boards_by_model() {
+        assert_eq!(expected_boards(Some("Antminer S19j Pro")), 3);
+        assert_eq!(expected_boards(Some("Antminer L7")), 4);
+        assert_eq!(expected_boards(Some("Antminer L3++")), 4);
+        assert_eq!(expected_boards(Some("Antminer L70")), 3);
+        assert_eq!(expected_boards(None), 3);
     }
 }
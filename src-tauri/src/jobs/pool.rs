@@ -8,7 +8,8 @@ use std::pin::Pin;
 use std::future::Future;
 
 use db::Pool;
-use crate::db;
+use crate::{db, stratum};
+use tauri::Manager;
 use super::JobDef;
 use super::Miner;
 
@@ -21,6 +22,9 @@ async fn set_pool(miner: Miner, pool: Pool) -> Result<()> {
 pub struct PoolJob {
     ips: Vec<String>,
     pool: Pool,
+    /// Probe the pool with the first miner's worker name before touching any miners
+    #[serde(default)]
+    preflight: bool,
 }
 
 #[async_trait]
@@ -31,15 +35,40 @@ impl JobDef for PoolJob {
         app: AppHandle,
         client: Client,
     ) -> Result<Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>> {
-        let mut futures = Vec::new();
-        for ip in &self.ips {
-            if let Ok(miner) = Miner::new(ip.clone(), db, client.clone(), app.clone()).await {
-                futures.push(
-                    Box::pin(set_pool(miner, self.pool.clone()))
-                    as Pin<Box<dyn Future<Output = Result<()>> + Send>>
-                );
+        let mut miners = Miner::from_ips(&self.ips, db, client, app.clone()).await?;
+        // The UI only has the masked password, fill in the stored one
+        let mut pool = self.pool.clone();
+        if pool.password.as_deref() == Some(db::MASK) {
+            pool.unmask(&db::Pools::load(db, &super::vault(&app).await).await?);
+        }
+
+        if self.preflight {
+            if let Some(miner) = miners.first_mut() {
+                let model = match miner.get_miner().await {
+                    Ok(m) => Miner::worker_model(m.as_ref()).await.unwrap_or_default(),
+                    Err(_) => String::new(),
+                };
+                // Don't carry auth errors from the probe connection into the job
+                miner.errors.clear();
+                let worker = pool.worker(miner.can, &model, &miner.ip);
+                let results = stratum::probe_pool(&pool, &worker).await;
+                app.emit_all("pool_probe", &results)?;
+                if !results.iter().any(|r| r.ok) {
+                    return Err(anyhow::anyhow!("No pool in {} accepted worker {}", pool.name, worker));
+                }
+                for result in results.iter().filter(|r| !r.ok) {
+                    tracing::warn!("Pool {} failed preflight: {}", result.url, result.error.as_deref().unwrap_or(""));
+                }
             }
         }
+
+        let mut futures = Vec::new();
+        for miner in miners {
+            futures.push(
+                Box::pin(set_pool(miner, pool.clone()))
+                as Pin<Box<dyn Future<Output = Result<()>> + Send>>
+            );
+        }
         Ok(futures)
     }
 }
@@ -0,0 +1,81 @@
+use async_trait::async_trait;
+use sqlx::sqlite::SqlitePool;
+use tauri::AppHandle;
+use libminer::Client;
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap};
+use std::pin::Pin;
+use std::future::Future;
+use std::sync::Arc;
+
+use crate::db::{MinerSample, ProfilePreset};
+use crate::models::Profile;
+
+use super::Miner;
+use super::budget::{self, Change, staged};
+use super::JobDef;
+
+/// Switch a miner to the preset's profile for the model it reports now
+async fn apply_preset(mut miner: Miner, preset: Arc<ProfilePreset>) -> Result<()> {
+    let model = miner.get_miner().await?.get_model().await?;
+    let profile = preset.profile(&model)
+        .ok_or_else(|| anyhow::anyhow!("Preset {} has no profile for {}", preset.name, model))?
+        .clone();
+    miner.set_profile(profile.into()).await
+}
+
+/// Apply a named preset, each miner gets the profile for its model
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PresetJob {
+    ips: Vec<String>,
+    preset: String,
+}
+
+#[async_trait]
+impl JobDef for PresetJob {
+    async fn prepare(
+        &self,
+        db: &SqlitePool,
+        app: AppHandle,
+        client: Client,
+    ) -> Result<Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>> {
+        let preset = Arc::new(ProfilePreset::get(db, &self.preset).await?);
+        // Budgets are checked with the model from the last scan
+        let models = MinerSample::all(db).await?
+            .into_iter()
+            .filter_map(|s| s.model.map(|m| (s.ip, m)))
+            .collect::<HashMap<_, _>>();
+        // Refuse up front rather than failing those miners one by one after the others were switched
+        let mut missing: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+        for ip in &self.ips {
+            if let Some(model) = models.get(ip).filter(|m| preset.profile(m).is_none()) {
+                missing.entry(model.as_str()).or_default().push(ip.as_str());
+            }
+        }
+        if !missing.is_empty() {
+            let missing = missing.into_iter()
+                .map(|(model, ips)| format!("{} ({})", model, ips.join(", ")))
+                .collect::<Vec<_>>();
+            return Err(anyhow::anyhow!("Preset {} has no profile for {}", preset.name, missing.join(", ")));
+        }
+        let stages = budget::plan(db, "apply this preset", &self.ips, |ip, current, estimate| {
+            let profile = models.get(ip).and_then(|m| preset.profile(m));
+            let end = match profile {
+                None => current,
+                Some(Profile::LowPower) => current,
+                Some(Profile::Preset { power, .. }) => *power,
+                Some(Profile::Default | Profile::Manual { .. }) => current.max(estimate),
+            };
+            Change { end, peak: current.max(end) }
+        }).await?;
+        let mut futures = Vec::new();
+        for miner in Miner::from_ips(&self.ips, db, client, app).await? {
+            futures.push(
+                Box::pin(staged(stages.delay(&miner.ip), apply_preset(miner, preset.clone())))
+                as Pin<Box<dyn Future<Output = Result<()>> + Send>>
+            );
+        }
+        Ok(futures)
+    }
+}
@@ -10,6 +10,7 @@ use std::future::Future;
 use crate::models::Profile;
 
 use super::Miner;
+use super::budget::{self, Change, staged};
 use super::JobDef;
 
 async fn set_profile(miner: Miner, profile: Profile) -> Result<()> {
@@ -31,14 +32,20 @@ impl JobDef for ProfileJob {
         app: AppHandle,
         client: Client,
     ) -> Result<Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>> {
+        let stages = budget::plan(db, "change the profile of these miners", &self.ips, |_, current, estimate| {
+            let end = match &self.profile {
+                Profile::LowPower => current,
+                Profile::Preset { power, .. } => *power,
+                Profile::Default | Profile::Manual { .. } => current.max(estimate),
+            };
+            Change { end, peak: current.max(end) }
+        }).await?;
         let mut futures = Vec::new();
-        for ip in &self.ips {
-            if let Ok(miner) = Miner::new(ip.clone(), db, client.clone(), app.clone()).await {
-                futures.push(
-                    Box::pin(set_profile(miner, self.profile.clone()))
-                    as Pin<Box<dyn Future<Output = Result<()>> + Send>>
-                );
-            }
+        for miner in Miner::from_ips(&self.ips, db, client, app).await? {
+            futures.push(
+                Box::pin(staged(stages.delay(&miner.ip), set_profile(miner, self.profile.clone())))
+                as Pin<Box<dyn Future<Output = Result<()>> + Send>>
+            );
         }
         Ok(futures)
     }
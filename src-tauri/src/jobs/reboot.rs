@@ -9,6 +9,7 @@ use std::future::Future;
 
 use super::JobDef;
 use super::Miner;
+use super::budget::{self, Change, staged};
 
 async fn reboot(miner: Miner) -> Result<()> {
     miner.reboot().await?;
@@ -28,14 +29,16 @@ impl JobDef for RebootJob {
         app: AppHandle,
         client: Client,
     ) -> Result<Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>> {
+        // Miners come back at the same draw but can pull full power while they start
+        let stages = budget::plan(db, "reboot these miners", &self.ips, |_, current, estimate| {
+            Change { end: current, peak: current.max(estimate) }
+        }).await?;
         let mut futures = Vec::new();
-        for ip in &self.ips {
-            if let Ok(miner) = Miner::new(ip.clone(), db, client.clone(), app.clone()).await {
-                futures.push(
-                    Box::pin(reboot(miner))
-                    as Pin<Box<dyn Future<Output = Result<()>> + Send>>
-                );
-            }
+        for miner in Miner::from_ips(&self.ips, db, client, app).await? {
+            futures.push(
+                Box::pin(staged(stages.delay(&miner.ip), reboot(miner)))
+                as Pin<Box<dyn Future<Output = Result<()>> + Send>>
+            );
         }
         Ok(futures)
     }
@@ -6,19 +6,35 @@ use serde::{Serialize, Deserialize};
 use anyhow::Result;
 use std::pin::Pin;
 use std::future::Future;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+use tauri::Manager;
+use tokio::sync::Mutex;
 
-use crate::db;
-use super::JobDef;
-use super::Miner;
+use crate::{analysis, db};
+use crate::models::MinerEvent;
+use super::{JobDef, Outcome};
+use super::{Miner, MinerContext};
 
-async fn scan(miner: Miner) -> Result<()> {
-    miner.scan().await?;
+async fn scan(mut miner: Miner, results: Arc<Mutex<Vec<MinerEvent>>>, offline: Arc<Mutex<Vec<String>>>) -> Result<()> {
+    if let Err(e) = miner.load().await {
+        offline.lock().await.push(miner.ip.clone());
+        return Err(e);
+    }
+    miner.emit()?;
+    results.lock().await.push(miner.event());
     Ok(())
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct ScanJob {
     pub can: i64,
+    /// Scanned miners, kept for the peer analysis once the scan completes
+    #[serde(skip)]
+    results: Arc<Mutex<Vec<MinerEvent>>>,
+    /// Miners that didn't answer, recorded as offline samples
+    #[serde(skip)]
+    offline: Arc<Mutex<Vec<String>>>,
 }
 
 #[async_trait]
@@ -29,20 +45,23 @@ impl JobDef for ScanJob {
         app: AppHandle,
         client: Client,
     ) -> Result<Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>> {
-        let mut can = db::DbCan::get(db, self.can).await?;
-        can.load_racks(db).await?;
-        let auths = db::MinerAuth::load(db).await?;
+        let can = db::DbCan::get(db, self.can).await?;
+        let context = MinerContext::load_read(db, app, client).await?;
         let mut futures = vec![];
         for rack in &can.racks {
             for row in &rack.miners {
-                for miner in row {
-                    let miner = Miner::default(
-                        miner.ip.clone(),
-                        rack.index, miner.row, miner.index, can.num,
-                        app.clone(), client.clone(), auths.clone(),
-                    );
+                for miner in row.iter().filter(|m| !m.ip.is_empty()) {
+                    let miner = Miner {
+                        port: miner.port,
+                        external_id: miner.external_id.clone(),
+                        ..Miner::default(
+                            miner.ip.clone(),
+                            rack.index, miner.row, miner.index, can.num,
+                            context.clone(),
+                        )
+                    };
                     futures.push(
-                        Box::pin(scan(miner))
+                        Box::pin(scan(miner, self.results.clone(), self.offline.clone()))
                         as Pin<Box<dyn Future<Output = Result<()>> + Send>>
                     );
                 }
@@ -50,4 +69,53 @@ impl JobDef for ScanJob {
         }
         Ok(futures)
     }
+
+    async fn complete(&self, db: &SqlitePool, app: AppHandle, outcome: Outcome) -> Result<()> {
+        let mut results = std::mem::take(&mut *self.results.lock().await);
+        let offline = std::mem::take(&mut *self.offline.lock().await);
+        let sampled = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs() as i64;
+        let samples = results.iter()
+            .map(|e| db::MinerSample {
+                ip: e.miner.ip.clone(),
+                online: true,
+                healthy: e.miner.errors.is_empty() && e.miner.conditions.is_empty(),
+                hashrate: e.miner.hashrate,
+                power: e.miner.power,
+                efficiency: e.miner.efficiency,
+                model: e.miner.model.clone(),
+                sleep: e.miner.sleep,
+                profile: e.miner.profile.clone(),
+                profiles: e.miner.profiles.clone(),
+                sampled,
+            })
+            .chain(offline.into_iter().map(|ip| db::MinerSample {
+                ip,
+                online: false,
+                healthy: false,
+                hashrate: None,
+                power: None,
+                efficiency: None,
+                model: None,
+                sleep: false,
+                profile: None,
+                profiles: None,
+                sampled,
+            }))
+            .collect::<Vec<_>>();
+        // Totals going stale shouldn't stop the anomaly pass
+        if let Err(e) = db::MinerSample::record(db, &samples).await {
+            tracing::warn!("Failed to record scan samples: {}", e);
+        }
+
+        // A cancelled scan has already been emitted, comparing a partial site would mislead
+        if outcome.cancelled > 0 {
+            return Ok(());
+        }
+        analysis::annotate(&mut results);
+        // Re-emit only the miners that picked up anomalies
+        for event in results.into_iter().filter(|e| !e.miner.anomalies.is_empty()) {
+            app.emit_all("miner", event)?;
+        }
+        Ok(())
+    }
 }
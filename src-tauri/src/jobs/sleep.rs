@@ -4,21 +4,43 @@ use tauri::AppHandle;
 use libminer::Client;
 use serde::{Serialize, Deserialize};
 use anyhow::Result;
+use std::collections::HashMap;
 use std::pin::Pin;
 use std::future::Future;
+use std::sync::Arc;
+use tokio::sync::Mutex;
 
 use super::JobDef;
 use super::Miner;
+use super::budget::{self, Change, Stages, staged};
 
-async fn set_sleep(miner: Miner, sleep: bool) -> Result<()> {
-    miner.set_sleep(sleep).await?;
-    Ok(())
+/// The error of every miner whose task finished, None when it succeeded
+pub type Results = Arc<Mutex<HashMap<String, Option<String>>>>;
+
+async fn set_sleep(miner: Miner, sleep: bool, results: Results) -> Result<()> {
+    let ip = miner.ip.clone();
+    let res = miner.set_sleep(sleep).await;
+    results.lock().await.insert(ip, res.as_ref().err().map(|e| e.to_string()));
+    res
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct SleepJob {
     ips: Vec<String>,
     sleep: bool,
+    #[serde(skip)]
+    results: Results,
+}
+
+impl SleepJob {
+    pub fn new(ips: Vec<String>, sleep: bool) -> Self {
+        Self { ips, sleep, results: Results::default() }
+    }
+
+    /// Filled in as each miner's task finishes, cancelled ones are missing
+    pub fn results(&self) -> Results {
+        self.results.clone()
+    }
 }
 
 #[async_trait]
@@ -29,14 +51,21 @@ impl JobDef for SleepJob {
         app: AppHandle,
         client: Client,
     ) -> Result<Vec<Pin<Box<dyn Future<Output = Result<()>> + Send>>>> {
+        let stages = if self.sleep {
+            Stages::none()
+        } else {
+            // A sleeping miner wakes to full power
+            budget::plan(db, "wake these miners", &self.ips, |_, current, estimate| {
+                let end = current.max(estimate);
+                Change { end, peak: end }
+            }).await?
+        };
         let mut futures = Vec::new();
-        for ip in &self.ips {
-            if let Ok(miner) = Miner::new(ip.clone(), db, client.clone(), app.clone()).await {
-                futures.push(
-                    Box::pin(set_sleep(miner, self.sleep))
-                    as Pin<Box<dyn Future<Output = Result<()>> + Send>>
-                );
-            }
+        for miner in Miner::from_ips(&self.ips, db, client, app).await? {
+            futures.push(
+                Box::pin(staged(stages.delay(&miner.ip), set_sleep(miner, self.sleep, self.results.clone())))
+                as Pin<Box<dyn Future<Output = Result<()>> + Send>>
+            );
         }
         Ok(futures)
     }
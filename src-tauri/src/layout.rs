@@ -0,0 +1,1068 @@
+//! Tool neutral site layout files
+//!
+//! A layout is a site's cans, the racks in each can and the miner IPs in each rack slot.
+//! It can be written as JSON, YAML or CSV, picked by the file extension.
+//!
+//! JSON and YAML nest the layout:
+//!
+//! ```yaml
+//! version: 1
+//! site: North
+//! areas:
+//!   - { id: 1, name: Building A, kind: building }
+//!   - { id: 2, parent: 1, name: Pod 1, kind: pod }
+//! cans:
+//!   - name: C01
+//!     num: 1
+//!     area: 2
+//!     racks:
+//!       - name: C01-R1
+//!         width: 4
+//!         height: 8
+//!         slots:
+//!           - { row: 0, index: 0, ip: 10.1.0.10 }
+//! ```
+//!
+//! Area ids only link areas and cans within the file, an area is listed after the one it's in.
+//! A layout naming a site other than the open one isn't imported.
+//! Racks can set `numbering`, how their slots are labelled, such as
+//! `{ start: bottom_left, serpentine: true }`. Slots can also set `port` for miners whose cgminer
+//! API is forwarded to another port and `external_id`, the miner's id in the tool the layout came from.
+//!
+//! CSV flattens it to one row per slot with the columns
+//! `can,can_num,rack,width,height,row,index,ip,port,external_id,numbering,area,area_kind`,
+//! the last five are optional. A rack without miners is a single row with `row`, `index` and
+//! `ip` left empty, a can without racks leaves everything after `can_num` empty. CSV writes
+//! numbering as a single value such as `bottom_left_serpentine`, and a can's areas as their
+//! names and kinds from the top joined by `/`, such as `Building A/Pod 1` and `building/pod`.
+//! Areas without cans aren't kept in CSV.
+//!
+//! Rows and indexes start at 0 from the top left of the rack. Racks are numbered within
+//! their can in the order they're listed. Rack names are unique within their can, IPs
+//! across the site.
+
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use sqlx::{Sqlite, Transaction};
+use sqlx::sqlite::SqlitePool;
+use tracing::info;
+
+use crate::db::{DbArea, Node, Numbering};
+
+/// Newest layout file version this build reads and the one it writes
+const VERSION: u32 = 1;
+
+/// Something that stops an import, lines are 1-based and include the header
+/// Line 0 means the file has no lines to point at, the reason says where instead
+#[derive(Serialize, Debug, Clone)]
+pub struct ImportProblem {
+    pub file: String,
+    pub line: u64,
+    pub reason: String,
+}
+
+pub fn format_problems(problems: &[ImportProblem]) -> String {
+    problems.iter()
+        .map(|p| match p.line {
+            0 => format!("{}: {}", p.file, p.reason),
+            line => format!("{}:{}: {}", p.file, line, p.reason),
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Just the file name of a path, for problems
+pub(crate) fn file_name(path: &str) -> String {
+    Path::new(path)
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Read every row of a CSV, recording rows that don't parse instead of stopping
+pub(crate) fn read_rows<T: serde::de::DeserializeOwned>(path: &str, file: &str, builder: &mut Builder) -> Result<Vec<(u64, T)>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .from_path(path)?;
+    let headers = rdr.headers()?.clone();
+    let mut rows = vec![];
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(e) => {
+                let line = e.position().map(|p| p.line()).unwrap_or(0);
+                builder.problem(ImportProblem { file: file.to_string(), line, reason: e.to_string() });
+                continue;
+            },
+        };
+        let line = record.position().map(|p| p.line()).unwrap_or(0);
+        match record.deserialize::<T>(Some(&headers)) {
+            Ok(row) => rows.push((line, row)),
+            Err(e) => builder.problem(ImportProblem { file: file.to_string(), line, reason: e.to_string() }),
+        }
+    }
+    Ok(rows)
+}
+
+/// A slot identified by can and rack name
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SlotRef {
+    pub can: String,
+    pub rack: String,
+    pub row: i64,
+    pub index: i64,
+}
+
+/// An area above cans, `key` only identifies it within the file
+pub(crate) struct SiteArea {
+    pub key: i64,
+    pub parent: Option<i64>,
+    pub name: String,
+    pub kind: String,
+}
+
+pub(crate) struct SiteCan {
+    pub name: String,
+    pub num: i64,
+    /// Key of the area the can is in
+    pub area: Option<i64>,
+}
+
+pub(crate) struct SiteRack {
+    pub can: String,
+    pub name: String,
+    pub index: i64,
+    pub width: i64,
+    pub height: i64,
+    pub numbering: Numbering,
+}
+
+/// A rack as a file describes it, checked by `Builder::rack`
+pub(crate) struct NewRack<'a> {
+    pub can: &'a str,
+    pub name: &'a str,
+    /// Position within the can
+    pub index: i64,
+    pub width: Option<i64>,
+    pub height: Option<i64>,
+    pub numbering: Numbering,
+}
+
+pub(crate) struct SiteMiner {
+    pub ip: String,
+    pub slot: SlotRef,
+    pub port: Option<u16>,
+    pub external_id: Option<String>,
+}
+
+/// A miner as a file describes it, checked by `Builder::miner`
+pub(crate) struct NewMiner<'a> {
+    pub ip: &'a str,
+    /// Can of the rack, None for formats that only name the rack
+    pub can: Option<&'a str>,
+    pub rack: &'a str,
+    pub row: i64,
+    pub index: i64,
+    pub port: Option<u16>,
+    pub external_id: Option<&'a str>,
+}
+
+/// A validated layout read from any import format
+pub(crate) struct Sitemap {
+    pub areas: Vec<SiteArea>,
+    pub cans: Vec<SiteCan>,
+    pub racks: Vec<SiteRack>,
+    pub miners: Vec<SiteMiner>,
+}
+
+/// Where a row came from, for problems
+#[derive(Clone)]
+pub(crate) struct Source<'a> {
+    pub file: &'a str,
+    pub line: u64,
+    /// Describes the row when there's no line to point at
+    pub path: Option<String>,
+}
+
+impl Source<'_> {
+    fn problem(&self, reason: String) -> ImportProblem {
+        let reason = match &self.path {
+            Some(path) => format!("{}: {}", path, reason),
+            None => reason,
+        };
+        ImportProblem { file: self.file.to_string(), line: self.line, reason }
+    }
+
+    fn at(&self) -> String {
+        match &self.path {
+            Some(path) => path.clone(),
+            None => format!("line {}", self.line),
+        }
+    }
+}
+
+/// Builds a sitemap row by row, keeping the rows that are valid and a problem for each one that isn't
+/// Cans must be added before their racks and racks before their miners
+pub(crate) struct Builder {
+    map: Sitemap,
+    problems: Vec<ImportProblem>,
+    ips: HashMap<String, String>,
+    slots: HashMap<SlotRef, String>,
+}
+
+impl Builder {
+    pub fn new() -> Self {
+        Self {
+            map: Sitemap { areas: vec![], cans: vec![], racks: vec![], miners: vec![] },
+            problems: vec![],
+            ips: HashMap::new(),
+            slots: HashMap::new(),
+        }
+    }
+
+    pub fn problem(&mut self, problem: ImportProblem) {
+        self.problems.push(problem);
+    }
+
+    pub fn has_can(&self, name: &str) -> bool {
+        self.map.cans.iter().any(|c| c.name == name)
+    }
+
+    /// Areas must be added after their parent, names only need to be unique among siblings
+    pub fn area(&mut self, src: &Source, key: i64, parent: Option<i64>, name: &str, kind: &str) {
+        let name = name.trim();
+        if name.is_empty() {
+            self.problems.push(src.problem("Area has no name".to_string()));
+        } else if self.map.areas.iter().any(|a| a.key == key) {
+            self.problems.push(src.problem(format!("Duplicate area id {}", key)));
+        } else if parent.map_or(false, |p| !self.map.areas.iter().any(|a| a.key == p)) {
+            self.problems.push(src.problem(format!("Area {} is in unknown area {}", name, parent.unwrap_or_default())));
+        } else if self.map.areas.iter().any(|a| a.parent == parent && a.name == name) {
+            self.problems.push(src.problem(format!("Duplicate area {}", name)));
+        } else {
+            self.map.areas.push(SiteArea { key, parent, name: name.to_string(), kind: kind.trim().to_string() });
+        }
+    }
+
+    pub fn can(&mut self, src: &Source, name: &str, num: i64, area: Option<i64>) {
+        let name = name.trim();
+        if name.is_empty() {
+            self.problems.push(src.problem("Group has no name".to_string()));
+        } else if self.has_can(name) {
+            self.problems.push(src.problem(format!("Duplicate group {}", name)));
+        } else if area.map_or(false, |a| !self.map.areas.iter().any(|area| area.key == a)) {
+            self.problems.push(src.problem(format!("Group {} is in unknown area {}", name, area.unwrap_or_default())));
+        } else {
+            self.map.cans.push(SiteCan { name: name.to_string(), num, area });
+        }
+    }
+
+    pub fn rack(&mut self, src: &Source, rack: NewRack) {
+        let NewRack { can, name, index, width, height, numbering } = rack;
+        let name = name.trim();
+        if name.is_empty() {
+            self.problems.push(src.problem("Rack has no name".to_string()));
+            return;
+        }
+        if !self.has_can(can) {
+            self.problems.push(src.problem(format!("Rack {} is in unknown group {}", name, can)));
+            return;
+        }
+        let (width, height) = match (width, height) {
+            (Some(w), Some(h)) if w > 0 && h > 0 => (w, h),
+            _ => {
+                self.problems.push(src.problem(format!("Rack {} needs a width and height of at least 1", name)));
+                return;
+            },
+        };
+        if self.map.racks.iter().any(|r| r.can == can && r.name == name) {
+            self.problems.push(src.problem(format!("Duplicate rack {} in group {}", name, can)));
+            return;
+        }
+        self.map.racks.push(SiteRack { can: can.to_string(), name: name.to_string(), index, width, height, numbering });
+    }
+
+    pub fn miner(&mut self, src: &Source, miner: NewMiner) {
+        let NewMiner { ip, can, rack, row, index, port, external_id } = miner;
+        let ip = ip.trim();
+        if ip.parse::<std::net::Ipv4Addr>().is_err() {
+            self.problems.push(src.problem(format!("{} is not a valid IP address", ip)));
+            return;
+        }
+        if port == Some(0) {
+            self.problems.push(src.problem(format!("{} has port 0", ip)));
+            return;
+        }
+        // Without a can the rack name has to be unique across the site
+        let mut found = self.map.racks.iter().filter(|r| r.name == rack && can.map_or(true, |c| r.can == c));
+        let site_rack = match (found.next(), found.next()) {
+            (Some(rack), None) => rack,
+            (Some(_), Some(_)) => {
+                self.problems.push(src.problem(format!("Rack {} is in more than one group, {} can't be placed", rack, ip)));
+                return;
+            },
+            (None, _) => {
+                let reason = match can {
+                    Some(can) => format!("Unknown rack {} in group {}", rack, can),
+                    None => format!("Unknown rack {}", rack),
+                };
+                self.problems.push(src.problem(reason));
+                return;
+            },
+        };
+        if row < 0 || row >= site_rack.height || index < 0 || index >= site_rack.width {
+            self.problems.push(src.problem(format!(
+                "Slot {}-{} is outside rack {} ({}x{})",
+                row, index, site_rack.name, site_rack.width, site_rack.height
+            )));
+            return;
+        }
+        if let Some(first) = self.ips.get(ip) {
+            self.problems.push(src.problem(format!("Duplicate IP {}, first on {}", ip, first)));
+            return;
+        }
+        let slot = SlotRef { can: site_rack.can.clone(), rack: rack.to_string(), row, index };
+        if let Some(first) = self.slots.get(&slot) {
+            self.problems.push(src.problem(format!(
+                "Duplicate slot {} {}-{}, first on {}", slot.rack, slot.row, slot.index, first
+            )));
+            return;
+        }
+        self.ips.insert(ip.to_string(), src.at());
+        self.slots.insert(slot.clone(), src.at());
+        self.map.miners.push(SiteMiner {
+            ip: ip.to_string(),
+            slot,
+            port,
+            external_id: external_id.map(str::trim).filter(|id| !id.is_empty()).map(String::from),
+        });
+    }
+
+    /// The sitemap and every problem found, problems are sorted by file order then line
+    pub fn finish(mut self, files: &[&str]) -> (Sitemap, Vec<ImportProblem>) {
+        self.problems.sort_by_key(|p| (files.iter().position(|f| *f == p.file), p.line));
+        (self.map, self.problems)
+    }
+}
+
+/// Whether an import's format can describe areas
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Areas {
+    /// The sitemap's areas replace the current ones
+    Replace,
+    /// The format has no areas, keep the current ones and put cans back in the area of the
+    /// can with the same name
+    Keep,
+}
+
+/// Replace the whole layout in one transaction
+/// Circuit feeds move to the rack with the same can and name. Nothing is written if a feed's
+/// rack is gone, or a rack fed by slot range changes size or numbering, those feeds are
+/// returned as problems of `file` instead.
+pub(crate) async fn replace(db: &SqlitePool, map: &Sitemap, areas: Areas, file: &str) -> Result<Vec<ImportProblem>> {
+    let mut tx = db.begin().await?;
+    let feeds = sqlx::query!(r#"
+        SELECT f.circuit_id, f.first, f.last, ci.name AS circuit,
+            r.name AS rack, r.width, r.height, r.numbering, c.name AS can
+        FROM circuit_feeds f
+        JOIN circuits ci ON f.circuit_id = ci.id
+        JOIN racks r ON f.rack_id = r.id
+        JOIN cans c ON r.can_id = c.id
+        "#
+    ).fetch_all(&mut tx).await?;
+    let mut problems = vec![];
+    for feed in &feeds {
+        let problem = |reason| ImportProblem { file: file.to_string(), line: 0, reason };
+        match map.racks.iter().find(|r| r.can == feed.can && r.name == feed.rack) {
+            None => problems.push(problem(format!(
+                "Circuit {} feeds rack {} in can {}, which isn't in the new layout, remove the feed first",
+                feed.circuit, feed.rack, feed.can
+            ))),
+            Some(rack) if feed.first.is_some()
+                && (rack.width != feed.width || rack.height != feed.height || rack.numbering.to_db() != feed.numbering) =>
+            {
+                problems.push(problem(format!(
+                    "Circuit {} feeds a slot range of rack {} in can {}, whose size or numbering the new layout changes",
+                    feed.circuit, feed.rack, feed.can
+                )));
+            },
+            Some(_) => {},
+        }
+    }
+    if !problems.is_empty() {
+        return Ok(problems);
+    }
+
+    let can_areas = sqlx::query!("SELECT name, area_id FROM cans WHERE area_id IS NOT NULL")
+        .fetch_all(&mut tx).await?;
+
+    // Deleting racks deletes their feeds, they're added back below
+    sqlx::query("DELETE FROM miners").execute(&mut tx).await?;
+    sqlx::query("DELETE FROM racks").execute(&mut tx).await?;
+    sqlx::query("DELETE FROM cans").execute(&mut tx).await?;
+    if areas == Areas::Replace {
+        sqlx::query("DELETE FROM areas").execute(&mut tx).await?;
+    }
+    let rack_ids = insert(&mut tx, map).await?;
+    if areas == Areas::Keep {
+        for can in &can_areas {
+            sqlx::query!("UPDATE cans SET area_id = ? WHERE name = ?", can.area_id, can.name)
+                .execute(&mut tx).await?;
+        }
+    }
+    for feed in &feeds {
+        let rack_id = rack_ids[&(feed.can.as_str(), feed.rack.as_str())];
+        sqlx::query!(
+            "INSERT INTO circuit_feeds (circuit_id, rack_id, first, last) VALUES (?, ?, ?, ?)",
+            feed.circuit_id, rack_id, feed.first, feed.last
+        ).execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    info!(
+        "Imported {} areas, {} cans, {} racks and {} miners, kept {} circuit feeds",
+        map.areas.len(), map.cans.len(), map.racks.len(), map.miners.len(), feeds.len()
+    );
+    Ok(vec![])
+}
+
+/// Add cans, racks and miners next to the existing layout in one transaction
+/// Callers check the names and IPs don't collide with what's already there
+pub(crate) async fn append(db: &SqlitePool, map: &Sitemap) -> Result<()> {
+    let mut tx = db.begin().await?;
+    insert(&mut tx, map).await?;
+    tx.commit().await?;
+    info!("Added {} cans, {} racks and {} miners", map.cans.len(), map.racks.len(), map.miners.len());
+    Ok(())
+}
+
+/// Returns the id of each rack by can and name
+async fn insert<'m>(tx: &mut Transaction<'_, Sqlite>, map: &'m Sitemap) -> Result<HashMap<(&'m str, &'m str), i64>> {
+    // Areas come after their parents, so each parent already has its id
+    let mut area_ids = HashMap::new();
+    for area in &map.areas {
+        let parent_id = area.parent.map(|p| area_ids[&p]);
+        let id = sqlx::query!(
+            "INSERT INTO areas (parent_id, name, kind) VALUES (?, ?, ?)",
+            parent_id, area.name, area.kind
+        ).execute(&mut *tx).await?.last_insert_rowid();
+        area_ids.insert(area.key, id);
+    }
+    let mut can_ids = HashMap::new();
+    for can in &map.cans {
+        let area_id = can.area.map(|a| area_ids[&a]);
+        let id = sqlx::query!("INSERT INTO cans (name, num, area_id) VALUES (?, ?, ?)", can.name, can.num, area_id)
+            .execute(&mut *tx).await?
+            .last_insert_rowid();
+        can_ids.insert(can.name.as_str(), id);
+    }
+    let mut rack_ids = HashMap::new();
+    for rack in &map.racks {
+        let can_id = can_ids[rack.can.as_str()];
+        let numbering = rack.numbering.to_db();
+        let id = sqlx::query!(
+            "INSERT INTO racks (name, index_, width, height, numbering, can_id) VALUES (?, ?, ?, ?, ?, ?)",
+            rack.name, rack.index, rack.width, rack.height, numbering, can_id
+        ).execute(&mut *tx).await?.last_insert_rowid();
+        rack_ids.insert((rack.can.as_str(), rack.name.as_str()), id);
+    }
+    for miner in &map.miners {
+        let rack_id = rack_ids[&(miner.slot.can.as_str(), miner.slot.rack.as_str())];
+        sqlx::query!(
+            "INSERT INTO miners (ip, rack_id, row, index_, port, external_id) VALUES (?, ?, ?, ?, ?, ?)",
+            miner.ip, rack_id, miner.slot.row, miner.slot.index, miner.port, miner.external_id
+        ).execute(&mut *tx).await?;
+    }
+    Ok(rack_ids)
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Layout {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub site: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub areas: Vec<LayoutArea>,
+    #[serde(default)]
+    pub cans: Vec<LayoutCan>,
+}
+
+fn default_version() -> u32 {
+    VERSION
+}
+
+/// An area above cans, listed after the area it's in
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LayoutArea {
+    /// Only identifies the area within the file
+    pub id: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<i64>,
+    pub name: String,
+    #[serde(default)]
+    pub kind: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LayoutCan {
+    pub name: String,
+    pub num: i64,
+    /// Id of the area the can is in
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub area: Option<i64>,
+    #[serde(default)]
+    pub racks: Vec<LayoutRack>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LayoutRack {
+    pub name: String,
+    pub width: i64,
+    pub height: i64,
+    /// How slots are labelled, top left and not serpentine when left out
+    #[serde(default)]
+    pub numbering: Numbering,
+    #[serde(default)]
+    pub slots: Vec<LayoutSlot>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LayoutSlot {
+    pub row: i64,
+    pub index: i64,
+    pub ip: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub external_id: Option<String>,
+}
+
+impl From<&Sitemap> for Layout {
+    fn from(map: &Sitemap) -> Self {
+        let areas = map.areas.iter().map(|area| LayoutArea {
+            id: area.key,
+            parent: area.parent,
+            name: area.name.clone(),
+            kind: area.kind.clone(),
+        }).collect();
+        let cans = map.cans.iter().map(|can| LayoutCan {
+            name: can.name.clone(),
+            num: can.num,
+            area: can.area,
+            racks: map.racks.iter().filter(|r| r.can == can.name).map(|rack| LayoutRack {
+                name: rack.name.clone(),
+                width: rack.width,
+                height: rack.height,
+                numbering: rack.numbering,
+                slots: map.miners.iter().filter(|m| m.slot.can == rack.can && m.slot.rack == rack.name).map(|m| LayoutSlot {
+                    row: m.slot.row,
+                    index: m.slot.index,
+                    ip: m.ip.clone(),
+                    port: m.port,
+                    external_id: m.external_id.clone(),
+                }).collect(),
+            }).collect(),
+        }).collect();
+        Layout { version: VERSION, site: None, areas, cans }
+    }
+}
+
+/// One CSV row, see the module docs
+#[derive(Serialize, Deserialize)]
+struct LayoutRecord {
+    can: String,
+    can_num: i64,
+    rack: Option<String>,
+    width: Option<i64>,
+    height: Option<i64>,
+    row: Option<i64>,
+    index: Option<i64>,
+    ip: Option<String>,
+    #[serde(default)]
+    port: Option<u16>,
+    #[serde(default)]
+    external_id: Option<String>,
+    /// Slot numbering as stored in the racks table, such as `bottom_left_serpentine`
+    #[serde(default)]
+    numbering: Option<String>,
+    /// Names of the can's areas from the top, and their kinds, each joined by `/`
+    #[serde(default)]
+    area: Option<String>,
+    #[serde(default)]
+    area_kind: Option<String>,
+}
+
+enum Format {
+    Csv,
+    Json,
+    Yaml,
+}
+
+impl Format {
+    fn of(path: &str) -> Result<Self> {
+        let ext = Path::new(path)
+            .extension()
+            .map(|e| e.to_string_lossy().to_ascii_lowercase())
+            .unwrap_or_default();
+        match ext.as_str() {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "yaml" | "yml" => Ok(Format::Yaml),
+            _ => Err(anyhow::anyhow!("Unknown layout format .{}, use .csv, .json or .yaml", ext)),
+        }
+    }
+}
+
+fn read_structured(path: &str, file: &str, format: Format, site: Option<&str>, builder: &mut Builder) -> Result<()> {
+    let text = std::fs::read_to_string(path)?;
+    let parsed = match format {
+        Format::Json => serde_json::from_str::<Layout>(&text)
+            .map_err(|e| ImportProblem { file: file.to_string(), line: e.line() as u64, reason: e.to_string() }),
+        _ => serde_yaml::from_str::<Layout>(&text)
+            .map_err(|e| ImportProblem {
+                file: file.to_string(),
+                line: e.location().map(|l| l.line() as u64).unwrap_or(0),
+                reason: e.to_string(),
+            }),
+    };
+    let layout = match parsed {
+        Ok(layout) => layout,
+        Err(problem) => {
+            builder.problem(problem);
+            return Ok(());
+        },
+    };
+    let src = |path: String| Source { file, line: 0, path: Some(path) };
+    if layout.version > VERSION {
+        builder.problem(src("version".to_string())
+            .problem(format!("Layout version {} is newer than this application supports", layout.version)));
+        return Ok(());
+    }
+    if let (Some(from), Some(site)) = (layout.site.as_deref(), site) {
+        if from != site {
+            builder.problem(src("site".to_string())
+                .problem(format!("Layout is of site {}, the open site is {}", from, site)));
+            return Ok(());
+        }
+    }
+
+    for area in &layout.areas {
+        builder.area(&src(format!("area {}", area.name)), area.id, area.parent, &area.name, &area.kind);
+    }
+    for can in &layout.cans {
+        builder.can(&src(format!("can {}", can.name)), &can.name, can.num, can.area);
+    }
+    for can in &layout.cans {
+        for (i, rack) in can.racks.iter().enumerate() {
+            let at = src(format!("can {} rack {}", can.name, rack.name));
+            builder.rack(&at, NewRack {
+                can: &can.name,
+                name: &rack.name,
+                index: i as i64,
+                width: Some(rack.width),
+                height: Some(rack.height),
+                numbering: rack.numbering,
+            });
+        }
+    }
+    for can in &layout.cans {
+        for rack in &can.racks {
+            for slot in &rack.slots {
+                let at = src(format!("can {} rack {} slot {}-{}", can.name, rack.name, slot.row, slot.index));
+                builder.miner(&at, NewMiner {
+                    ip: &slot.ip,
+                    can: Some(&can.name),
+                    rack: &rack.name,
+                    row: slot.row,
+                    index: slot.index,
+                    port: slot.port,
+                    external_id: slot.external_id.as_deref(),
+                });
+            }
+        }
+    }
+    Ok(())
+}
+
+fn read_csv(path: &str, file: &str, builder: &mut Builder) -> Result<()> {
+    let records = read_rows::<LayoutRecord>(path, file, builder)?;
+    let src = |line: u64| Source { file, line, path: None };
+
+    // Each pass only looks at rows that add something new, a can or rack repeats
+    // on every slot row after its first
+    let mut areas = HashMap::<(Option<i64>, &str), i64>::new();
+    for (line, record) in &records {
+        if builder.has_can(&record.can) {
+            continue;
+        }
+        let names = record.area.as_deref().map(|a| a.split('/').map(str::trim).collect::<Vec<_>>()).unwrap_or_default();
+        let kinds = record.area_kind.as_deref().map(|k| k.split('/').map(str::trim).collect::<Vec<_>>()).unwrap_or_default();
+        let mut area = None;
+        for (depth, name) in names.iter().enumerate().filter(|(_, name)| !name.is_empty()) {
+            area = Some(match areas.get(&(area, *name)) {
+                Some(key) => *key,
+                None => {
+                    let key = areas.len() as i64 + 1;
+                    builder.area(&src(*line), key, area, name, kinds.get(depth).copied().unwrap_or_default());
+                    areas.insert((area, *name), key);
+                    key
+                },
+            });
+        }
+        builder.can(&src(*line), &record.can, record.can_num, area);
+    }
+    let mut racks = HashMap::<(&str, &str), (u64, Option<i64>, Option<i64>)>::new();
+    let mut counts = HashMap::<&str, i64>::new();
+    for (line, record) in &records {
+        let rack = match record.rack.as_deref() {
+            Some(rack) if !rack.trim().is_empty() => rack.trim(),
+            _ => continue,
+        };
+        if let Some((first, width, height)) = racks.get(&(record.can.as_str(), rack)) {
+            let differs = |size: Option<i64>, first: Option<i64>| size.is_some() && first.is_some() && size != first;
+            if differs(record.width, *width) || differs(record.height, *height) {
+                builder.problem(src(*line).problem(format!(
+                    "Rack {} is {}x{} here but {}x{} on line {}",
+                    rack,
+                    record.width.or(*width).unwrap_or_default(), record.height.or(*height).unwrap_or_default(),
+                    width.unwrap_or_default(), height.unwrap_or_default(), first
+                )));
+            }
+            continue;
+        }
+        racks.insert((record.can.as_str(), rack), (*line, record.width, record.height));
+        let numbering = match record.numbering.as_deref().map(str::trim).filter(|n| !n.is_empty()) {
+            Some(value) => match Numbering::parse(value) {
+                Ok(numbering) => numbering,
+                Err(e) => {
+                    builder.problem(src(*line).problem(e.to_string()));
+                    continue;
+                },
+            },
+            None => Numbering::default(),
+        };
+        let index = counts.entry(record.can.as_str()).or_insert(0);
+        builder.rack(&src(*line), NewRack {
+            can: &record.can,
+            name: rack,
+            index: *index,
+            width: record.width,
+            height: record.height,
+            numbering,
+        });
+        *index += 1;
+    }
+    for (line, record) in &records {
+        let ip = match record.ip.as_deref() {
+            Some(ip) if !ip.trim().is_empty() => ip,
+            _ => continue,
+        };
+        match (record.rack.as_deref(), record.row, record.index) {
+            (Some(rack), Some(row), Some(index)) => builder.miner(&src(*line), NewMiner {
+                ip,
+                can: Some(&record.can),
+                rack: rack.trim(),
+                row,
+                index,
+                port: record.port,
+                external_id: record.external_id.as_deref(),
+            }),
+            _ => builder.problem(src(*line).problem(format!("{} needs a rack, row and index", ip))),
+        }
+    }
+    Ok(())
+}
+
+/// Read and validate a layout file, returning every problem found rather than the first
+/// A JSON or YAML layout of a site other than `site` is refused
+pub(crate) fn parse(path: &str, site: Option<&str>) -> Result<(Sitemap, Vec<ImportProblem>)> {
+    let format = Format::of(path)?;
+    let file = file_name(path);
+    let mut builder = Builder::new();
+    match format {
+        Format::Csv => read_csv(path, &file, &mut builder)?,
+        format => read_structured(path, &file, format, site, &mut builder)?,
+    }
+    Ok(builder.finish(&[&file]))
+}
+
+/// Replace the whole layout with a layout file
+/// Nothing is written if anything in the file has a problem, or circuit feeds can't be kept,
+/// the problems are returned instead
+pub async fn import(db: &SqlitePool, path: &str, site: Option<&str>) -> Result<Vec<ImportProblem>> {
+    let (map, problems) = parse(path, site)?;
+    if !problems.is_empty() {
+        return Ok(problems);
+    }
+    info!("Importing layout from {}", path);
+    replace(db, &map, Areas::Replace, &file_name(path)).await
+}
+
+/// Read the current layout from the database
+/// With a node only its racks are read, along with the cans they're in
+pub async fn current(db: &SqlitePool, site: Option<String>, node: Option<Node>) -> Result<Layout> {
+    let mut cans = sqlx::query!("SELECT id, name, num, area_id FROM cans ORDER BY num, name")
+        .fetch_all(db).await?
+        .into_iter()
+        .map(|c| (c.id, LayoutCan { name: c.name, num: c.num, area: c.area_id, racks: vec![] }))
+        .collect::<Vec<_>>();
+    let mut racks = sqlx::query!("SELECT id, can_id, name, width, height, numbering FROM racks ORDER BY can_id, index_, id")
+        .fetch_all(db).await?;
+    if let Some(node) = node {
+        let keep = node.racks(db).await?.into_iter().collect::<HashSet<_>>();
+        racks.retain(|r| keep.contains(&r.id));
+        cans.retain(|(id, _)| racks.iter().any(|r| r.can_id == *id));
+    }
+    let mut miners = sqlx::query!("SELECT rack_id, ip, row, index_, port, external_id FROM miners WHERE ip != '' ORDER BY rack_id, row, index_")
+        .fetch_all(db).await?;
+
+    for rack in racks {
+        let slots = miners.iter()
+            .filter(|m| m.rack_id == rack.id)
+            .map(|m| LayoutSlot {
+                row: m.row,
+                index: m.index_,
+                ip: m.ip.clone(),
+                port: m.port.map(|p| p as u16),
+                external_id: m.external_id.clone(),
+            })
+            .collect();
+        miners.retain(|m| m.rack_id != rack.id);
+        if let Some((_, can)) = cans.iter_mut().find(|(id, _)| *id == rack.can_id) {
+            can.racks.push(LayoutRack {
+                name: rack.name,
+                width: rack.width,
+                height: rack.height,
+                numbering: Numbering::parse(&rack.numbering)?,
+                slots,
+            });
+        }
+    }
+
+    // Only the areas holding the exported cans when exporting part of the site
+    let mut areas = DbArea::all(db).await?;
+    if node.is_some() {
+        let parents = areas.iter().map(|a| (a.id, a.parent_id)).collect::<HashMap<_, _>>();
+        let mut keep = HashSet::new();
+        for (_, can) in &cans {
+            let mut next = can.area;
+            while let Some(id) = next {
+                if !keep.insert(id) {
+                    break;
+                }
+                next = parents.get(&id).copied().flatten();
+            }
+        }
+        areas.retain(|a| keep.contains(&a.id));
+    }
+    // Parents before their children so an import can create them in order
+    let mut ordered: Vec<LayoutArea> = vec![];
+    while !areas.is_empty() {
+        let (ready, rest): (Vec<_>, Vec<_>) = areas.into_iter()
+            .partition(|a| a.parent_id.map_or(true, |p| ordered.iter().any(|o| o.id == p)));
+        // Whatever is left has a parent that's missing, put it at the top
+        let stuck = ready.is_empty();
+        areas = rest;
+        for area in ready {
+            ordered.push(LayoutArea { id: area.id, parent: area.parent_id, name: area.name, kind: area.kind });
+        }
+        if stuck {
+            ordered.extend(areas.drain(..).map(|a| LayoutArea { id: a.id, parent: None, name: a.name, kind: a.kind }));
+        }
+    }
+
+    Ok(Layout {
+        version: VERSION,
+        site,
+        areas: ordered,
+        cans: cans.into_iter().map(|(_, can)| can).collect(),
+    })
+}
+
+/// Names and kinds of an area and those above it, from the top, each joined by `/`
+fn area_path(areas: &[LayoutArea], id: Option<i64>) -> Result<(Option<String>, Option<String>)> {
+    let mut names = vec![];
+    let mut kinds = vec![];
+    let mut next = id;
+    while let Some(area) = next.and_then(|id| areas.iter().find(|a| a.id == id)) {
+        if area.name.contains('/') || area.kind.contains('/') {
+            return Err(anyhow::anyhow!("Area {} has a / in its name or kind, export as JSON or YAML instead", area.name));
+        }
+        names.push(area.name.as_str());
+        kinds.push(area.kind.as_str());
+        next = area.parent;
+    }
+    if names.is_empty() {
+        return Ok((None, None));
+    }
+    names.reverse();
+    kinds.reverse();
+    Ok((Some(names.join("/")), Some(kinds.join("/"))))
+}
+
+/// Write the current layout, or the part of it under a node, to a file
+/// The format is picked by the extension
+pub async fn export(db: &SqlitePool, path: &str, site: Option<String>, node: Option<Node>) -> Result<()> {
+    let format = Format::of(path)?;
+    let layout = current(db, site, node).await?;
+    match format {
+        Format::Json => std::fs::write(path, serde_json::to_string_pretty(&layout)?)?,
+        Format::Yaml => std::fs::write(path, serde_yaml::to_string(&layout)?)?,
+        Format::Csv => {
+            let mut wtr = csv::Writer::from_path(path)?;
+            for can in &layout.cans {
+                let (area, area_kind) = area_path(&layout.areas, can.area)?;
+                let record = |rack: Option<&LayoutRack>, slot: Option<&LayoutSlot>| LayoutRecord {
+                    can: can.name.clone(),
+                    can_num: can.num,
+                    rack: rack.map(|r| r.name.clone()),
+                    width: rack.map(|r| r.width),
+                    height: rack.map(|r| r.height),
+                    row: slot.map(|s| s.row),
+                    index: slot.map(|s| s.index),
+                    ip: slot.map(|s| s.ip.clone()),
+                    port: slot.and_then(|s| s.port),
+                    external_id: slot.and_then(|s| s.external_id.clone()),
+                    numbering: rack.map(|r| r.numbering.to_db()),
+                    area: area.clone(),
+                    area_kind: area_kind.clone(),
+                };
+                if can.racks.is_empty() {
+                    wtr.serialize(record(None, None))?;
+                }
+                for rack in &can.racks {
+                    if rack.slots.is_empty() {
+                        wtr.serialize(record(Some(rack), None))?;
+                    }
+                    for slot in &rack.slots {
+                        wtr.serialize(record(Some(rack), Some(slot)))?;
+                    }
+                }
+            }
+            wtr.flush()?;
+        },
+    }
+    info!("Exported layout to {}", path);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db;
+
+    /// Cans C1 and C2, each with a rack named R1
+    async fn shared_names() -> SqlitePool {
+        let db = db::test_db().await;
+        for (can, num) in [("C1", 1), ("C2", 2)] {
+            let can_id = sqlx::query("INSERT INTO cans (name, num) VALUES (?, ?)")
+                .bind(can).bind(num)
+                .execute(&db).await.unwrap()
+                .last_insert_rowid();
+            let rack_id = sqlx::query("INSERT INTO racks (can_id, name, index_, width, height) VALUES (?, 'R1', 0, 2, 2)")
+                .bind(can_id)
+                .execute(&db).await.unwrap()
+                .last_insert_rowid();
+            sqlx::query("INSERT INTO miners (rack_id, ip, row, index_) VALUES (?, ?, 1, 1)")
+                .bind(rack_id).bind(format!("10.0.{}.1", num))
+                .execute(&db).await.unwrap();
+        }
+        db
+    }
+
+    #[tokio::test]
+    async fn round_trip_shared_rack_names() {
+        let db = shared_names().await;
+        let before = serde_json::to_value(current(&db, None, None).await.unwrap()).unwrap();
+        for ext in ["json", "yaml", "csv"] {
+            let path = db::test_path(&format!("layout.{}", ext));
+            export(&db, &path, None, None).await.unwrap();
+            let problems = import(&db, &path, None).await.unwrap();
+            assert!(problems.is_empty(), "{}: {}", ext, format_problems(&problems));
+            let after = serde_json::to_value(current(&db, None, None).await.unwrap()).unwrap();
+            assert_eq!(before, after, "{}", ext);
+            let _ = std::fs::remove_file(&path);
+        }
+    }
+
+    #[test]
+    fn rack_without_can() {
+        let src = Source { file: "sitemap", line: 1, path: None };
+        let mut builder = Builder::new();
+        for can in ["C1", "C2"] {
+            builder.can(&src, can, 1, None);
+            builder.rack(&src, NewRack { can, name: "R1", index: 0, width: Some(2), height: Some(2), numbering: Numbering::default() });
+        }
+        builder.rack(&src, NewRack { can: "C1", name: "R1", index: 1, width: Some(2), height: Some(2), numbering: Numbering::default() });
+        builder.rack(&src, NewRack { can: "C2", name: "R2", index: 1, width: Some(2), height: Some(2), numbering: Numbering::default() });
+        let miner = |ip, can, rack| NewMiner { ip, can, rack, row: 0, index: 0, port: None, external_id: None };
+        builder.miner(&src, miner("10.0.0.1", Some("C2"), "R1"));
+        builder.miner(&src, miner("10.0.0.2", None, "R2"));
+        builder.miner(&src, miner("10.0.0.3", None, "R1"));
+        builder.miner(&src, miner("10.0.0.4", Some("C1"), "R2"));
+        let (map, problems) = builder.finish(&["sitemap"]);
+        let reasons = problems.iter().map(|p| p.reason.as_str()).collect::<Vec<_>>();
+        assert_eq!(reasons, [
+            "Duplicate rack R1 in group C1",
+            "Rack R1 is in more than one group, 10.0.0.3 can't be placed",
+            "Unknown rack R2 in group C1",
+        ]);
+        let slots = map.miners.iter().map(|m| (m.slot.can.as_str(), m.slot.rack.as_str())).collect::<Vec<_>>();
+        assert_eq!(slots, [("C2", "R1"), ("C2", "R2")]);
+    }
+
+    /// Circuit A feeding slots 1-2 of R1 in C1 and circuit B feeding all of R1 in C2
+    async fn feed_racks(db: &SqlitePool) {
+        sqlx::query("INSERT INTO pdus (id, name) VALUES (1, 'P1')").execute(db).await.unwrap();
+        sqlx::query("INSERT INTO circuits (id, pdu_id, name, capacity) VALUES (1, 1, 'A', 10000), (2, 1, 'B', 10000)")
+            .execute(db).await.unwrap();
+        sqlx::query(r#"
+            INSERT INTO circuit_feeds (circuit_id, rack_id, first, last)
+            SELECT 1, r.id, 1, 2 FROM racks r JOIN cans c ON r.can_id = c.id WHERE c.name = 'C1'
+            UNION ALL
+            SELECT 2, r.id, NULL, NULL FROM racks r JOIN cans c ON r.can_id = c.id WHERE c.name = 'C2'
+            "#
+        ).execute(db).await.unwrap();
+    }
+
+    async fn feeds(db: &SqlitePool) -> Vec<(i64, String, Option<i64>)> {
+        sqlx::query_as(r#"
+            SELECT f.circuit_id, c.name, f.first
+            FROM circuit_feeds f
+            JOIN racks r ON f.rack_id = r.id
+            JOIN cans c ON r.can_id = c.id
+            ORDER BY f.circuit_id
+            "#
+        ).fetch_all(db).await.unwrap()
+    }
+
+    #[tokio::test]
+    async fn replace_keeps_feeds() {
+        let db = shared_names().await;
+        feed_racks(&db).await;
+        let before = feeds(&db).await;
+        assert_eq!(before.len(), 2);
+        let path = db::test_path("layout.json");
+        export(&db, &path, None, None).await.unwrap();
+        assert!(import(&db, &path, None).await.unwrap().is_empty());
+        assert_eq!(feeds(&db).await, before);
+
+        // Dropping C2 and resizing C1's rack would lose both feeds
+        let text = std::fs::read_to_string(&path).unwrap();
+        let mut layout: Layout = serde_json::from_str(&text).unwrap();
+        layout.cans.truncate(1);
+        layout.cans[0].racks[0].width = 3;
+        std::fs::write(&path, serde_json::to_string(&layout).unwrap()).unwrap();
+        let problems = import(&db, &path, None).await.unwrap();
+        let reasons = problems.iter().map(|p| p.reason.as_str()).collect::<Vec<_>>();
+        assert_eq!(reasons, [
+            "Circuit A feeds a slot range of rack R1 in can C1, whose size or numbering the new layout changes",
+            "Circuit B feeds rack R1 in can C2, which isn't in the new layout, remove the feed first",
+        ]);
+        assert_eq!(feeds(&db).await, before);
+        let _ = std::fs::remove_file(&path);
+    }
+}
@@ -3,21 +3,33 @@
     windows_subsystem = "windows"
 )]
 
-use db::DbCan;
+use db::{DbArea, DbCan, DbCircuit, DbFeed, DbPdu, DbRack, DbMiner, Node, NodeSummary, Numbering, Slot};
 use jobs::Job;
 use libminer::{ClientBuilder, Client};
 use sqlx::sqlite::SqlitePool;
-use tauri::State;
+use tauri::{Manager, State};
 use anyhow::Result;
 use tokio::sync::Mutex;
 use tokio::sync::broadcast;
 
+mod analysis;
+mod cgminer;
+mod curtail;
 mod db;
 mod frontier;
 mod jobs;
+mod layout;
 mod models;
-use db::{Config, Pools, MinerAuth, Auth};
+mod pricing;
+mod report;
+mod sites;
+mod stratum;
+mod template;
+use db::{Config, Pools, Pool, MinerAuth, Auth, Pricing, DbHashboard, DbUnknownHashboard, Vault, VaultStatus, Settings};
+use db::settings::{self, Setting};
+use db::backup::{Backup, BackupFile};
 use models::Can;
+use sites::{Site, Sites};
 
 struct JobState {
     working: bool,
@@ -51,71 +63,425 @@ impl JobState {
 }
 
 #[tauri::command]
-async fn get_cans(db: State<'_, SqlitePool>) -> Result<Vec<Can>, String> {
+async fn get_cans(db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<Can>, String> {
+    let db = db.lock().await.clone();
     let cans = db::DbCan::all(&db).await.map_err(|e| e.to_string())?;
     Ok(cans.into_iter().map(|can| can.into()).collect())
 }
 
 #[tauri::command]
-async fn gen_empty_can(can: i64, db: State<'_, SqlitePool>) -> Result<DbCan, String> {
-    let mut can = db::DbCan::get(&db, can).await.map_err(|e| e.to_string())?;
-    can.load_racks(&db).await.map_err(|e| e.to_string())?;
-    Ok(can)
+async fn gen_empty_can(can: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<DbCan, String> {
+    let db = db.lock().await.clone();
+    db::DbCan::get(&db, can).await.map_err(|e| e.to_string())
 }
 
-/// Import Frontier Locations export
 #[tauri::command]
-async fn import_frontier_locations(layout: String, sitemap: String, db: State<'_, SqlitePool>) -> Result<(), ()> {
-    if let Err(e) = frontier::import_sitemap(&db, layout, sitemap).await {
-        tracing::error!("Error importing sitemap: {}", e);
-    }
-    Ok(())
+async fn create_can(name: String, num: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<i64, String> {
+    let db = db.lock().await.clone();
+    DbCan::create(&db, &name, num).await.map_err(|e| e.to_string())
 }
 
+/// Rename a can or change its number
 #[tauri::command]
-async fn save_settings(settings: Config, client: State<'_, Mutex<Client>>, db: State<'_, SqlitePool>) -> Result<(), String> {
-    settings.save(&db).await.map_err(|e| e.to_string())?;
-    let new_client = ClientBuilder::new()
-        .max_connections(settings.maxConnections)
-        .connect_timeout(tokio::time::Duration::from_secs(settings.connectionTimeout))
-        .request_timeout(tokio::time::Duration::from_secs(settings.readTimeout))
-        .build()
-        .map_err(|e| e.to_string())?;
-    let mut client = client.lock().await;
-    *client = new_client;
-    Ok(())
+async fn update_can(id: i64, name: String, num: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbCan::update(&db, id, &name, num).await.map_err(|e| e.to_string())
 }
 
 #[tauri::command]
-async fn run_job(
-    job: Job,
-    jobstate: State<'_, Mutex<JobState>>,
-    client: State<'_, Mutex<Client>>,
-    db: State<'_, SqlitePool>,
-    app: tauri::AppHandle
+async fn delete_can(id: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbCan::delete(&db, id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_areas(db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<DbArea>, String> {
+    let db = db.lock().await.clone();
+    DbArea::all(&db).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_area(parent_id: Option<i64>, name: String, kind: String, db: State<'_, Mutex<SqlitePool>>) -> Result<i64, String> {
+    let db = db.lock().await.clone();
+    DbArea::create(&db, parent_id, &name, &kind).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_area(id: i64, parent_id: Option<i64>, name: String, kind: String, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbArea::update(&db, id, parent_id, &name, &kind).await.map_err(|e| e.to_string())
+}
+
+/// Delete an area, what was in it moves up a level
+#[tauri::command]
+async fn delete_area(id: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbArea::delete(&db, id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn set_can_area(can_id: i64, area_id: Option<i64>, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbArea::assign_can(&db, can_id, area_id).await.map_err(|e| e.to_string())
+}
+
+/// The site tree with hashrate, power and health totals from the latest scans
+#[tauri::command]
+async fn site_summary(sites: State<'_, Mutex<Sites>>, db: State<'_, Mutex<SqlitePool>>) -> Result<NodeSummary, String> {
+    let name = sites.lock().await.active().map(|s| s.name.clone()).unwrap_or_default();
+    let db = db.lock().await.clone();
+    NodeSummary::site(&db, name).await.map_err(|e| e.to_string())
+}
+
+/// IPs under a node, for running a job on any part of the site
+#[tauri::command]
+async fn node_ips(node: Node, db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<String>, String> {
+    let db = db.lock().await.clone();
+    node.ips(&db).await.map_err(|e| e.to_string())
+}
+
+/// What curtailing the scope to `target` kW would do, from the last scan
+#[tauri::command]
+async fn plan_curtailment(target: f64, scope: Vec<Node>, db: State<'_, Mutex<SqlitePool>>) -> Result<curtail::CurtailmentPlan, String> {
+    let db = db.lock().await.clone();
+    curtail::plan(&db, target, &scope).await.map_err(|e| e.to_string())
+}
+
+/// Applied curtailments with their actions, newest first
+#[tauri::command]
+async fn get_curtailments(db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<curtail::Curtailment>, String> {
+    let db = db.lock().await.clone();
+    curtail::Curtailment::all(&db).await.map_err(|e| e.to_string())
+}
+
+/// PDUs with their circuits, what each circuit feeds and its current load
+#[tauri::command]
+async fn get_pdus(db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<DbPdu>, String> {
+    let db = db.lock().await.clone();
+    let config: Config = settings::load(&db).await.map_err(|e| e.to_string())?;
+    DbPdu::all(&db, config.miner_power).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_pdu(name: String, db: State<'_, Mutex<SqlitePool>>) -> Result<i64, String> {
+    let db = db.lock().await.clone();
+    DbPdu::create(&db, &name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn rename_pdu(id: i64, name: String, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbPdu::rename(&db, id, &name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_pdu(id: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbPdu::delete(&db, id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_circuit(pdu_id: i64, name: String, capacity: f64, db: State<'_, Mutex<SqlitePool>>) -> Result<i64, String> {
+    let db = db.lock().await.clone();
+    DbCircuit::create(&db, pdu_id, &name, capacity).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_circuit(id: i64, name: String, capacity: f64, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbCircuit::update(&db, id, &name, capacity).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_circuit(id: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbCircuit::delete(&db, id).await.map_err(|e| e.to_string())
+}
+
+/// Feed a whole rack from a circuit, or the slots labelled `first` to `last`
+#[tauri::command]
+async fn add_circuit_feed(circuit_id: i64, rack_id: i64, first: Option<i64>, last: Option<i64>, db: State<'_, Mutex<SqlitePool>>) -> Result<i64, String> {
+    let db = db.lock().await.clone();
+    DbFeed::create(&db, circuit_id, rack_id, first, last).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_circuit_feed(id: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbFeed::delete(&db, id).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_rack(can: i64, name: String, width: i64, height: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<i64, String> {
+    let db = db.lock().await.clone();
+    DbRack::create(&db, can, &name, width, height).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn rename_rack(id: i64, name: String, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbRack::rename(&db, id, &name).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn resize_rack(id: i64, width: i64, height: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbRack::resize(&db, id, width, height).await.map_err(|e| e.to_string())
+}
+
+/// Set the display order of a can's racks
+#[tauri::command]
+async fn reorder_racks(can: i64, racks: Vec<i64>, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbRack::reorder(&db, can, &racks).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_rack(id: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbRack::delete(&db, id).await.map_err(|e| e.to_string())
+}
+
+/// Put an IP in a slot, e.g. after swapping a miner
+#[tauri::command]
+async fn assign_slot(slot: Slot, ip: String, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbMiner::assign(&db, slot, &ip).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn move_slot(from: Slot, to: Slot, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbMiner::move_slot(&db, from, to).await.map_err(|e| e.to_string())
+}
+
+/// Change how a rack's slots are labelled, returns the new labels laid out like the rack's miners
+#[tauri::command]
+async fn set_rack_numbering(rack_id: i64, numbering: Numbering, db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<Vec<i64>>, String> {
+    let db = db.lock().await.clone();
+    DbRack::set_numbering(&db, rack_id, numbering).await.map_err(|e| e.to_string())
+}
+
+/// Label people use for a slot
+#[tauri::command]
+async fn slot_label(slot: Slot, db: State<'_, Mutex<SqlitePool>>) -> Result<i64, String> {
+    let db = db.lock().await.clone();
+    let rack = DbRack::get(&db, slot.rack_id).await.map_err(|e| e.to_string())?;
+    rack.label(slot.row, slot.index).map_err(|e| e.to_string())
+}
+
+/// Slot people call `label` in a rack
+#[tauri::command]
+async fn slot_at(rack_id: i64, label: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<Slot, String> {
+    let db = db.lock().await.clone();
+    let rack = DbRack::get(&db, rack_id).await.map_err(|e| e.to_string())?;
+    let (row, index) = rack.position(label).map_err(|e| e.to_string())?;
+    Ok(Slot { rack_id, row, index })
+}
+
+#[tauri::command]
+async fn clear_slot(slot: Slot, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbMiner::clear(&db, slot).await.map_err(|e| e.to_string())
+}
+
+/// Import a CSV, JSON or YAML layout file, replacing the whole layout
+/// Returns the problems that stopped the import, empty if it was imported. A layout of
+/// another site is refused
+#[tauri::command]
+async fn import_layout(
+    path: String,
+    sites: State<'_, Mutex<Sites>>,
+    db: State<'_, Mutex<SqlitePool>>,
+) -> Result<Vec<layout::ImportProblem>, String> {
+    let site = sites.lock().await.active().ok().map(|s| s.name.clone());
+    let db = db.lock().await.clone();
+    layout::import(&db, &path, site.as_deref()).await.map_err(|e| e.to_string())
+}
+
+/// Export the layout as CSV, JSON or YAML depending on the file extension
+/// A node limits the export to the racks under it
+#[tauri::command]
+async fn export_layout(
+    path: String,
+    node: Option<Node>,
+    sites: State<'_, Mutex<Sites>>,
+    db: State<'_, Mutex<SqlitePool>>,
 ) -> Result<(), String> {
+    let site = sites.lock().await.active().ok().map(|s| s.name.clone());
+    let db = db.lock().await.clone();
+    layout::export(&db, &path, site, node).await.map_err(|e| e.to_string())
+}
+
+/// Cans, racks and IPs a layout template would add, without adding them
+#[tauri::command]
+async fn preview_template(template: template::Template, db: State<'_, Mutex<SqlitePool>>) -> Result<template::TemplatePreview, String> {
+    let db = db.lock().await.clone();
+    template.preview(&db).await.map_err(|e| e.to_string())
+}
+
+/// Add the cans, racks and IPs of a layout template to the layout
+#[tauri::command]
+async fn generate_template(template: template::Template, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    template.generate(&db).await.map_err(|e| e.to_string())
+}
+
+/// Import Frontier Locations export, replacing the whole layout
+/// Returns the problems that stopped the import, empty if it was imported
+#[tauri::command]
+async fn import_frontier_locations(layout: String, sitemap: String, db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<layout::ImportProblem>, String> {
+    let db = db.lock().await.clone();
+    frontier::import_sitemap(&db, &layout, &sitemap).await.map_err(|e| e.to_string())
+}
+
+/// Changes a merge import of a Frontier export would make, without applying them
+#[tauri::command]
+async fn preview_frontier_merge(layout: String, sitemap: String, db: State<'_, Mutex<SqlitePool>>) -> Result<frontier::MergePreview, String> {
+    let db = db.lock().await.clone();
+    frontier::preview_merge(&db, &layout, &sitemap).await.map_err(|e| e.to_string())
+}
+
+/// Merge a Frontier export into the existing layout, keeping local edits to matched rows
+#[tauri::command]
+async fn merge_frontier_locations(
+    layout: String,
+    sitemap: String,
+    expected: String,
+    db: State<'_, Mutex<SqlitePool>>,
+) -> Result<Vec<frontier::LayoutChange>, String> {
+    let db = db.lock().await.clone();
+    frontier::merge_sitemap(&db, &layout, &sitemap, &expected).await.map_err(|e| e.to_string())
+}
+
+/// Export the layout as Frontier Locations layout and sitemap CSVs
+#[tauri::command]
+async fn export_frontier_locations(layout: String, sitemap: String, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    frontier::export_sitemap(&db, &layout, &sitemap).await.map_err(|e| e.to_string())
+}
+
+/// Save settings, subsystems that depend on them pick up the change from the registry
+#[tauri::command]
+async fn save_settings(settings: Config, registry: State<'_, Settings>, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    registry.save(&db, &settings).await.map_err(|e| e.to_string())
+}
+
+const BUSY: &str = "Already working";
+
+/// Prepare a job and mark it as running, only one job runs at a time
+async fn start_job(app: &tauri::AppHandle, job: Job) -> Result<jobs::JobRunner, String> {
     // Check if we're already working
+    let jobstate = app.state::<Mutex<JobState>>();
     let mut job_guard = jobstate.lock().await;
     if job_guard.working {
-        return Err("Already working".to_string());
+        return Err(BUSY.to_string());
     }
 
     // Set up our runner and cancel channel
-    let client = client.lock().await.clone();
-    let (job, cancel) = jobs::JobRunner::new(job, &db, app, client).await.map_err(|e| e.to_string())?;
+    let client = app.state::<Mutex<Client>>().lock().await.clone();
+    let db = app.state::<Mutex<SqlitePool>>().lock().await.clone();
+    let (job, cancel) = jobs::JobRunner::new(job, &db, app.clone(), client).await.map_err(|e| e.to_string())?;
     job_guard.start(cancel);
-    drop(job_guard);
+    Ok(job)
+}
 
-    // Run our job
+/// Run a started job to the end so the next one can start
+async fn finish_job(app: &tauri::AppHandle, job: jobs::JobRunner) {
     if let Err(e) = job.run().await {
         tracing::error!("Error running job: {}", e);
     }
+    app.state::<Mutex<JobState>>().lock().await.done();
+}
 
-    let mut job_guard = jobstate.lock().await;
-    job_guard.done();
+#[tauri::command]
+async fn run_job(job: Job, app: tauri::AppHandle) -> Result<(), String> {
+    let job = start_job(&app, job).await?;
+    finish_job(&app, job).await;
     Ok(())
 }
 
+#[tauri::command]
+async fn get_pricing(db: State<'_, Mutex<SqlitePool>>) -> Result<Pricing, String> {
+    let db = db.lock().await.clone();
+    settings::load(&db).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn save_pricing(pricing: Pricing, registry: State<'_, Settings>, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    registry.save(&db, &pricing).await.map_err(|e| e.to_string())
+}
+
+/// Add a price schedule CSV to the stored schedule, returns the number of intervals read
+#[tauri::command]
+async fn import_prices(path: String, db: State<'_, Mutex<SqlitePool>>) -> Result<usize, String> {
+    let db = db.lock().await.clone();
+    pricing::import(&db, &path).await.map_err(|e| e.to_string())
+}
+
+/// Fetch the schedule from the configured endpoint now rather than on the next refresh
+#[tauri::command]
+async fn fetch_prices(db: State<'_, Mutex<SqlitePool>>) -> Result<usize, String> {
+    let db = db.lock().await.clone();
+    let config: Pricing = settings::load(&db).await.map_err(|e| e.to_string())?;
+    if config.url.trim().is_empty() {
+        return Err("No price endpoint configured".to_string());
+    }
+    pricing::fetch(&db, &config.url).await.map_err(|e| e.to_string())
+}
+
+/// The current and upcoming price intervals
+#[tauri::command]
+async fn get_prices(db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<pricing::PriceInterval>, String> {
+    let db = db.lock().await.clone();
+    pricing::schedule(&db, pricing::now()).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_price_decisions(limit: Option<i64>, db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<pricing::PriceDecision>, String> {
+    let db = db.lock().await.clone();
+    pricing::decisions(&db, limit.unwrap_or(500)).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_profile_presets(db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<db::ProfilePreset>, String> {
+    let db = db.lock().await.clone();
+    db::ProfilePreset::all(&db).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn create_profile_preset(name: String, profiles: Vec<db::ModelProfile>, db: State<'_, Mutex<SqlitePool>>) -> Result<i64, String> {
+    let db = db.lock().await.clone();
+    db::ProfilePreset::create(&db, &name, &profiles).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn update_profile_preset(id: i64, name: String, profiles: Vec<db::ModelProfile>, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    db::ProfilePreset::update(&db, id, &name, &profiles).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_profile_preset(id: i64, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    db::ProfilePreset::delete(&db, id).await.map_err(|e| e.to_string())
+}
+
+/// Load curves and response of the miners over a curtailment event
+#[tauri::command]
+async fn compliance_report(request: report::ReportRequest, db: State<'_, Mutex<SqlitePool>>) -> Result<report::Report, String> {
+    let db = db.lock().await.clone();
+    report::generate(&db, &request).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_compliance_report(request: report::ReportRequest, path: String, db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<String>, String> {
+    let db = db.lock().await.clone();
+    report::export(&db, &request, &path).await.map_err(|e| e.to_string())
+}
+
 #[tauri::command]
 async fn cancel_job(jobstate: State<'_, Mutex<JobState>>) -> Result<(), String> {
     let mut job_guard = jobstate.lock().await;
@@ -124,65 +490,457 @@ async fn cancel_job(jobstate: State<'_, Mutex<JobState>>) -> Result<(), String>
 }
 
 #[tauri::command]
-async fn get_settings(db: State<'_, SqlitePool>) -> Result<Config, String> {
-    Config::load(&db).await.map_err(|e| e.to_string())
+async fn get_settings(db: State<'_, Mutex<SqlitePool>>) -> Result<Config, String> {
+    let db = db.lock().await.clone();
+    settings::load(&db).await.map_err(|e| e.to_string())
 }
 
+/// Pools with their passwords masked unless `reveal` is set, revealing needs the vault unlocked
 #[tauri::command]
-async fn get_pools(db: State<'_, SqlitePool>) -> Result<Pools, String> {
-    Pools::load(&db).await.map_err(|e| e.to_string())
+async fn get_pools(reveal: Option<bool>, vault: State<'_, Mutex<Vault>>, db: State<'_, Mutex<SqlitePool>>) -> Result<Pools, String> {
+    let db = db.lock().await.clone();
+    if reveal.unwrap_or(false) {
+        Pools::load(&db, &*vault.lock().await).await.map_err(|e| e.to_string())
+    } else {
+        Pools::load_masked(&db).await.map_err(|e| e.to_string())
+    }
 }
 
 #[tauri::command]
-async fn save_pools(pools: Pools, db: State<'_, SqlitePool>) -> Result<(), String> {
-    pools.save(&db).await.map_err(|e| e.to_string())
+async fn save_pools(
+    pools: Pools,
+    registry: State<'_, Settings>,
+    vault: State<'_, Mutex<Vault>>,
+    db: State<'_, Mutex<SqlitePool>>,
+) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    pools.save(&db, &*vault.lock().await).await.map_err(|e| e.to_string())?;
+    registry.notify(Pools::KEY);
+    Ok(())
 }
 
+/// Check every url of a pool accepts the worker
+/// Without a worker the template is rendered with placeholder values
 #[tauri::command]
-async fn get_miner_auth(db: State<'_, SqlitePool>) -> Result<Vec<Auth>, String> {
-    Ok(MinerAuth::load(&db).await.map_err(|e| e.to_string())?.auths)
+async fn probe_pool(
+    mut pool: Pool,
+    worker: Option<String>,
+    vault: State<'_, Mutex<Vault>>,
+    db: State<'_, Mutex<SqlitePool>>,
+) -> Result<Vec<stratum::ProbeResult>, String> {
+    if pool.password.as_deref() == Some(db::MASK) {
+        let db = db.lock().await.clone();
+        let stored = Pools::load(&db, &*vault.lock().await).await.map_err(|e| e.to_string())?;
+        pool.unmask(&stored);
+    }
+    let worker = worker.unwrap_or_else(|| pool.worker(0, "test", "0.0.0.0"));
+    Ok(stratum::probe_pool(&pool, &worker).await)
 }
 
+/// Miner credentials with their passwords masked unless `reveal` is set, revealing needs the vault unlocked
 #[tauri::command]
-async fn save_miner_auth(auths: Vec<Auth>, db: State<'_, SqlitePool>) -> Result<(), String> {
-    MinerAuth { auths }.save(&db).await.map_err(|e| e.to_string())
+async fn get_miner_auth(reveal: Option<bool>, vault: State<'_, Mutex<Vault>>, db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<Auth>, String> {
+    let db = db.lock().await.clone();
+    let auths = if reveal.unwrap_or(false) {
+        MinerAuth::load(&db, &*vault.lock().await).await
+    } else {
+        MinerAuth::load_masked(&db).await
+    };
+    Ok(auths.map_err(|e| e.to_string())?.auths)
+}
+
+#[tauri::command]
+async fn save_miner_auth(
+    auths: Vec<Auth>,
+    registry: State<'_, Settings>,
+    vault: State<'_, Mutex<Vault>>,
+    db: State<'_, Mutex<SqlitePool>>,
+) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    MinerAuth { auths }.save(&db, &*vault.lock().await).await.map_err(|e| e.to_string())?;
+    registry.notify(MinerAuth::KEY);
+    Ok(())
+}
+
+#[tauri::command]
+async fn vault_status(vault: State<'_, Mutex<Vault>>, db: State<'_, Mutex<SqlitePool>>) -> Result<VaultStatus, String> {
+    let db = db.lock().await.clone();
+    vault.lock().await.status(&db).await.map_err(|e| e.to_string())
+}
+
+/// Unlock stored credentials for this session, the first unlock sets the passphrase
+#[tauri::command]
+async fn unlock_vault(passphrase: String, vault: State<'_, Mutex<Vault>>, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    vault.lock().await.unlock(&db, &passphrase).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn lock_vault(vault: State<'_, Mutex<Vault>>) -> Result<(), String> {
+    vault.lock().await.lock();
+    Ok(())
+}
+
+#[tauri::command]
+async fn change_passphrase(passphrase: String, vault: State<'_, Mutex<Vault>>, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    vault.lock().await.change_passphrase(&db, &passphrase).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_hashboards(db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<DbHashboard>, String> {
+    let db = db.lock().await.clone();
+    DbHashboard::all(&db).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn save_hashboard(hashboard: DbHashboard, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    hashboard.save(&db).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_hashboard(hashboard: String, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbHashboard::delete(&db, &hashboard).await.map_err(|e| e.to_string())
+}
+
+/// Import hashboard catalog entries from a CSV, returns the number imported
+#[tauri::command]
+async fn import_hashboards(path: String, db: State<'_, Mutex<SqlitePool>>) -> Result<usize, String> {
+    let db = db.lock().await.clone();
+    DbHashboard::import_csv(&db, &path).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn export_hashboards(path: String, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    DbHashboard::export_csv(&db, &path).await.map_err(|e| e.to_string())
+}
+
+/// Hashboard IDs seen while scanning that aren't in the catalog
+#[tauri::command]
+async fn get_unknown_hashboards(db: State<'_, Mutex<SqlitePool>>) -> Result<Vec<DbUnknownHashboard>, String> {
+    let db = db.lock().await.clone();
+    DbUnknownHashboard::all(&db).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn get_sites(sites: State<'_, Mutex<Sites>>) -> Result<Sites, String> {
+    Ok(sites.lock().await.clone())
+}
+
+#[tauri::command]
+async fn save_site(site: Site, sites: State<'_, Mutex<Sites>>) -> Result<(), String> {
+    sites.lock().await.upsert(site).map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn delete_site(name: String, sites: State<'_, Mutex<Sites>>) -> Result<(), String> {
+    sites.lock().await.remove(&name).map_err(|e| e.to_string())
+}
+
+/// Switch to another site's database and settings without restarting
+#[tauri::command]
+async fn switch_site(
+    name: String,
+    sites: State<'_, Mutex<Sites>>,
+    jobstate: State<'_, Mutex<JobState>>,
+    client: State<'_, Mutex<Client>>,
+    vault: State<'_, Mutex<Vault>>,
+    db: State<'_, Mutex<SqlitePool>>,
+) -> Result<(), String> {
+    // Hold the job lock so nothing starts against the old database mid switch
+    let job_guard = jobstate.lock().await;
+    if job_guard.working {
+        return Err("Can't switch sites while a job is running".to_string());
+    }
+
+    let mut sites = sites.lock().await;
+    let site = sites.get(&name).map_err(|e| e.to_string())?.clone();
+    let new_db = db::connect(&site.path).await.map_err(|e| e.to_string())?;
+    let config: Config = settings::load(&new_db).await.map_err(|e| e.to_string())?;
+    let new_client = build_client(&config).map_err(|e| e.to_string())?;
+
+    let old_db = std::mem::replace(&mut *db.lock().await, new_db);
+    *client.lock().await = new_client;
+    // Each site has its own passphrase
+    vault.lock().await.lock();
+    old_db.close().await;
+
+    sites.active = site.name;
+    sites.save().map_err(|e| e.to_string())?;
+    Ok(())
+}
+
+/// Export the site to a single archive, history is optional as it can be large
+#[tauri::command]
+async fn export_backup(path: String, history: bool, db: State<'_, Mutex<SqlitePool>>) -> Result<(), String> {
+    let db = db.lock().await.clone();
+    Backup::export(&db, &path, history).await.map_err(|e| e.to_string())
+}
+
+/// Replace the active site's layout and settings with an archive
+#[tauri::command]
+async fn import_backup(
+    path: String,
+    history: bool,
+    jobstate: State<'_, Mutex<JobState>>,
+    registry: State<'_, Settings>,
+    vault: State<'_, Mutex<Vault>>,
+    db: State<'_, Mutex<SqlitePool>>,
+) -> Result<(), String> {
+    let job_guard = jobstate.lock().await;
+    if job_guard.working {
+        return Err("Can't restore a backup while a job is running".to_string());
+    }
+    let db = db.lock().await.clone();
+    Backup::import(&db, &path, history).await.map_err(|e| e.to_string())?;
+    // The archive's secrets are sealed with the passphrase of the site it came from
+    vault.lock().await.lock();
+
+    for key in [Config::KEY, Pools::KEY, MinerAuth::KEY] {
+        registry.notify(key);
+    }
+    Ok(())
+}
+
+#[tauri::command]
+async fn backup_now(sites: State<'_, Mutex<Sites>>, db: State<'_, Mutex<SqlitePool>>) -> Result<String, String> {
+    let path = sites.lock().await.active().map_err(|e| e.to_string())?.path.clone();
+    let db = db.lock().await.clone();
+    let config: Config = settings::load(&db).await.map_err(|e| e.to_string())?;
+    db::backup::snapshot(&db, &path, config.backup_generations).await.map_err(|e| e.to_string())
+}
+
+#[tauri::command]
+async fn list_backups(sites: State<'_, Mutex<Sites>>) -> Result<Vec<BackupFile>, String> {
+    let sites = sites.lock().await;
+    let site = sites.active().map_err(|e| e.to_string())?;
+    db::backup::list(&site.path).map_err(|e| e.to_string())
+}
+
+/// Periodically back up whichever site is active
+async fn backup_scheduler(app: tauri::AppHandle) {
+    loop {
+        let path = app.state::<Mutex<Sites>>().lock().await.active().map(|s| s.path.clone());
+        let db = app.state::<Mutex<SqlitePool>>().lock().await.clone();
+        match path {
+            Ok(path) => match db::backup::scheduled(&db, &path).await {
+                Ok(Some(file)) => tracing::info!("Backed up {} to {}", path, file),
+                Ok(None) => {},
+                Err(e) => tracing::error!("Error backing up {}: {}", path, e),
+            },
+            Err(e) => tracing::error!("Error backing up: {}", e),
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(600)).await;
+    }
+}
+
+/// Sleep and wake miners as the electricity price crosses their breakeven
+async fn price_scheduler(app: tauri::AppHandle) {
+    let mut fetched = 0;
+    loop {
+        let db = app.state::<Mutex<SqlitePool>>().lock().await.clone();
+        if let Err(e) = apply_prices(&app, &db, &mut fetched).await {
+            tracing::error!("Error applying prices: {}", e);
+        }
+        tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
+    }
+}
+
+async fn apply_prices(app: &tauri::AppHandle, db: &SqlitePool, fetched: &mut i64) -> Result<()> {
+    let config: Pricing = settings::load(db).await?;
+    if !config.enabled {
+        return Ok(());
+    }
+    let now = pricing::now();
+    if !config.url.trim().is_empty() && now - *fetched >= config.refresh as i64 * 60 {
+        *fetched = now;
+        match pricing::fetch(db, &config.url).await {
+            Ok(count) => tracing::info!("Fetched {} prices from {}", count, config.url),
+            Err(e) => tracing::warn!("Failed to fetch prices from {}: {}", config.url, e),
+        }
+    }
+
+    // Sleeping and waking log into miners, wait for the vault rather than fail every decision
+    if jobs::vault(app).await.locked(db).await? {
+        tracing::debug!("Credentials are locked, not applying prices");
+        return Ok(());
+    }
+    let decisions = pricing::decide(db, &config, now).await?;
+    for sleep in [true, false] {
+        let group = decisions.iter().filter(|d| d.sleep == sleep).cloned().collect::<Vec<_>>();
+        if group.is_empty() {
+            continue;
+        }
+        let ips = group.iter().map(|d| d.ip.clone()).collect();
+        let job = jobs::SleepJob::new(ips, sleep);
+        let results = job.results();
+        match start_job(app, Job::Sleep(job)).await {
+            // Another job is running, try again on the next pass
+            Err(e) if e == BUSY => return Ok(()),
+            Err(e) => {
+                let failed = group.into_iter().map(|d| (d, Some(e.clone()))).collect::<Vec<_>>();
+                pricing::record(db, &failed).await?;
+            },
+            Ok(job) => {
+                finish_job(app, job).await;
+                // Each miner is recorded with how its own task ended, so failures are retried
+                let mut results = results.lock().await;
+                let outcomes = group.into_iter()
+                    .map(|d| {
+                        let error = results.remove(&d.ip).unwrap_or_else(|| Some("Cancelled before it finished".to_string()));
+                        (d, error)
+                    })
+                    .collect::<Vec<_>>();
+                pricing::record(db, &outcomes).await?;
+            },
+        }
+    }
+    Ok(())
+}
+
+/// Rebuild the miner client whenever the connection settings change
+async fn watch_settings(app: tauri::AppHandle, mut changes: broadcast::Receiver<&'static str>) {
+    loop {
+        match changes.recv().await {
+            Ok(key) if key != Config::KEY => continue,
+            Err(broadcast::error::RecvError::Closed) => break,
+            // A lagged receiver may have missed a config change
+            Ok(_) | Err(broadcast::error::RecvError::Lagged(_)) => {},
+        }
+        let db = app.state::<Mutex<SqlitePool>>().lock().await.clone();
+        let client = settings::load::<Config>(&db).await.and_then(|config| build_client(&config));
+        match client {
+            Ok(client) => *app.state::<Mutex<Client>>().lock().await = client,
+            Err(e) => tracing::error!("Error rebuilding client: {}", e),
+        }
+    }
+}
+
+fn build_client(config: &Config) -> Result<Client> {
+    ClientBuilder::new()
+        .connect_timeout(tokio::time::Duration::from_secs(config.connection_timeout))
+        .request_timeout(tokio::time::Duration::from_secs(config.read_timeout))
+        .max_connections(config.max_connections)
+        .cache_token(true)
+        .build()
+        .map_err(|e| anyhow::anyhow!("{}", e))
 }
 
 async fn main_async() {
     tracing_subscriber::fmt::init();
 
-    let db = db::connect().await.unwrap();
-    let config = Config::load(&db).await.unwrap();
+    let context = tauri::generate_context!();
+    let sites = Sites::load(context.config()).unwrap();
+    let db = db::connect(&sites.active().unwrap().path).await.unwrap();
+    let config: Config = settings::load(&db).await.unwrap();
+    let registry = Settings::new();
+    let changes = registry.subscribe();
 
     let jobstate = Mutex::new(JobState::new());
 
-    let client = ClientBuilder::new()
-        .connect_timeout(tokio::time::Duration::from_secs(config.connectionTimeout))
-        .request_timeout(tokio::time::Duration::from_secs(config.readTimeout))
-        .max_connections(config.maxConnections)
-        .cache_token(true)
-        .build()
-        .unwrap();
+    let client = build_client(&config).unwrap();
 
     tauri::async_runtime::set(tokio::runtime::Handle::current());
     tauri::Builder::default()
         .manage(Mutex::new(client))
-        .manage(db)
+        .manage(Mutex::new(db))
+        .manage(Mutex::new(sites))
+        .manage(Mutex::new(Vault::new()))
+        .manage(registry)
         .manage(jobstate)
+        .setup(|app| {
+            tokio::spawn(backup_scheduler(app.handle()));
+            tokio::spawn(watch_settings(app.handle(), changes));
+            tokio::spawn(price_scheduler(app.handle()));
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             get_cans,
             gen_empty_can,
+            create_can,
+            update_can,
+            delete_can,
+            get_areas,
+            create_area,
+            update_area,
+            delete_area,
+            set_can_area,
+            site_summary,
+            node_ips,
+            plan_curtailment,
+            get_curtailments,
+            get_pricing,
+            save_pricing,
+            import_prices,
+            fetch_prices,
+            get_prices,
+            get_price_decisions,
+            compliance_report,
+            export_compliance_report,
+            get_profile_presets,
+            create_profile_preset,
+            update_profile_preset,
+            delete_profile_preset,
+            get_pdus,
+            create_pdu,
+            rename_pdu,
+            delete_pdu,
+            create_circuit,
+            update_circuit,
+            delete_circuit,
+            add_circuit_feed,
+            delete_circuit_feed,
+            create_rack,
+            rename_rack,
+            resize_rack,
+            reorder_racks,
+            delete_rack,
+            assign_slot,
+            move_slot,
+            clear_slot,
+            set_rack_numbering,
+            slot_label,
+            slot_at,
             run_job,
             cancel_job,
+            preview_template,
+            generate_template,
+            import_layout,
+            export_layout,
             import_frontier_locations,
+            preview_frontier_merge,
+            merge_frontier_locations,
+            export_frontier_locations,
             save_settings,
             get_settings,
             get_pools,
             save_pools,
+            probe_pool,
             get_miner_auth,
-            save_miner_auth
+            save_miner_auth,
+            vault_status,
+            unlock_vault,
+            lock_vault,
+            change_passphrase,
+            get_hashboards,
+            save_hashboard,
+            delete_hashboard,
+            import_hashboards,
+            export_hashboards,
+            get_unknown_hashboards,
+            get_sites,
+            save_site,
+            delete_site,
+            switch_site,
+            export_backup,
+            import_backup,
+            backup_now,
+            list_backups
             ])
-        .run(tauri::generate_context!())
+        .run(context)
         .expect("error while running tauri application");
 }
 
@@ -21,13 +21,13 @@ impl From<libminer::Profile> for Profile {
     }
 }
 
-impl Into<libminer::Profile> for Profile {
-    fn into(self) -> libminer::Profile {
-        match self {
-            Self::Default => libminer::Profile::Default,
-            Self::LowPower => libminer::Profile::LowPower,
-            Self::Preset { name, power, ths } => libminer::Profile::Preset { name, power, ths },
-            Self::Manual { volt, freq, min_freq, max_freq, min_volt, max_volt, def_freq, def_volt } => libminer::Profile::Manual { volt, freq, min_freq, max_freq, min_volt, max_volt, def_freq, def_volt },
+impl From<Profile> for libminer::Profile {
+    fn from(profile: Profile) -> Self {
+        match profile {
+            Profile::Default => Self::Default,
+            Profile::LowPower => Self::LowPower,
+            Profile::Preset { name, power, ths } => Self::Preset { name, power, ths },
+            Profile::Manual { volt, freq, min_freq, max_freq, min_volt, max_volt, def_freq, def_volt } => Self::Manual { volt, freq, min_freq, max_freq, min_volt, max_volt, def_freq, def_volt },
         }
     }
 }
@@ -49,17 +49,77 @@ impl From<DbCan> for Can {
     }
 }
 
-#[derive(Serialize, Debug)]
-pub struct Rack {
-    pub name: String,
-    pub width: i64,
-    pub height: i64,
-    pub miners: Vec<Vec<Miner>>,
+/// Per chain data reported by the miner's API
+#[derive(Serialize, Debug, Clone)]
+pub struct Hashboard {
+    pub index: u32,
+    pub hashrate: Option<f64>,
+    pub chips: Option<u32>,
+    pub expected_chips: Option<u32>,
+    pub board_temp: Option<f64>,
+    pub chip_temp: Option<f64>,
+    pub freq: Option<f64>,
+    pub serial: Option<String>,
+}
+
+/// Pool state and share counters reported by the miner's API
+#[derive(Serialize, Debug, Clone)]
+pub struct PoolStatus {
+    pub url: String,
+    pub user: String,
+    /// Alive, Dead, etc. as reported by the firmware
+    pub status: String,
+    pub active: bool,
+    pub accepted: u64,
+    pub rejected: u64,
+    pub stale: u64,
+    /// Unix timestamp of the last share, if one was submitted
+    pub last_share_time: Option<i64>,
+}
+
+/// Problems derived from scan data rather than reported by the miner
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "type")]
+pub enum Condition {
+    MissingBoard { found: u32, expected: u32 },
+    DeadBoard { board: u32 },
+    UnderperformingBoard { board: u32, reason: String },
+    DeadPool { url: String },
+    HighRejectRate { url: String, rate: f64 },
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Metric {
+    Temp,
+    Fan,
+    Hashrate,
+    Efficiency,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum AnomalyScope {
+    Rack,
+    Can,
+}
+
+/// A reading that stands out from the miner's same model peers
+#[derive(Serialize, Debug, Clone)]
+pub struct Anomaly {
+    pub metric: Metric,
+    pub scope: AnomalyScope,
+    pub value: f64,
+    pub median: f64,
+    /// Robust z-score against the peer group, 0 when the peers were identical
+    pub score: f64,
 }
 
 #[derive(Serialize, Debug, Clone)]
 pub struct Miner {
     pub ip: String,
+    /// Reference to the miner in the tool the layout was imported from
+    pub external_id: Option<String>,
     pub make: Option<String>,
     pub model: Option<String>,
     pub submodel: Option<String>,
@@ -70,11 +130,15 @@ pub struct Miner {
     pub uptime: Option<f64>,
     pub errors: Vec<String>,
     pub pools: Vec<libminer::Pool>,
+    pub pool_stats: Vec<PoolStatus>,
     pub power: Option<f64>,
     pub efficiency: Option<f64>,
     pub profile: Option<Profile>,
     pub profiles: Option<Vec<Profile>>,
     pub hashboard: Option<String>,
+    pub boards: Vec<Hashboard>,
+    pub conditions: Vec<Condition>,
+    pub anomalies: Vec<Anomaly>,
     pub sleep: bool,
     pub locate: bool,
     pub nameplate: Option<f64>,
@@ -0,0 +1,416 @@
+//! Electricity price schedules and the automatic sleep and wake decisions made from them
+//!
+//! Schedules are CSV with a header of `start,price` and optionally `end`, prices in $/MWh.
+//! Times are unix seconds or `YYYY-MM-DD HH:MM[:SS]` with an optional `T`, `Z` or `+HH:MM`
+//! offset, UTC when no offset is given. Intervals without an end run until the next one starts.
+//! The HTTP endpoint serves the same CSV, or a JSON list of `{start, end, price}` objects.
+
+use std::collections::{HashMap, HashSet};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use sqlx::sqlite::SqlitePool;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio::time::{timeout, Duration};
+
+use crate::db::{MinerSample, Node, Pricing};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Length of the last interval of a schedule with a single row and no end
+const DEFAULT_INTERVAL: i64 = 3600;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PriceInterval {
+    pub start: i64,
+    pub end: i64,
+    /// $/MWh
+    pub price: f64,
+}
+
+/// An automatic sleep or wake of a miner
+#[derive(Serialize, Debug, Clone)]
+pub struct Decision {
+    pub ip: String,
+    pub model: String,
+    pub sleep: bool,
+    pub price: f64,
+    pub breakeven: f64,
+    /// Start of the price interval that triggered it
+    pub interval: i64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct PriceDecision {
+    pub id: i64,
+    pub decided: i64,
+    #[serde(flatten)]
+    pub decision: Decision,
+    pub error: Option<String>,
+}
+
+pub fn now() -> i64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs() as i64).unwrap_or(0)
+}
+
+/// Days since 1970-01-01 of a civil date
+fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year - era * 400;
+    let mp = (month + 9) % 12;
+    let doy = (153 * mp + 2) / 5 + day - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+/// Days in a month of the proleptic Gregorian calendar
+fn days_in_month(year: i64, month: i64) -> i64 {
+    match month {
+        2 if year % 4 == 0 && (year % 100 != 0 || year % 400 == 0) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+fn parse_time(value: &str) -> Result<i64> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<i64>() {
+        return Ok(seconds);
+    }
+    let invalid = || anyhow::anyhow!("Invalid time {}", value);
+    let (date, rest) = value.split_at(value.find(['T', ' ']).ok_or_else(invalid)?);
+    let rest = &rest[1..];
+    let (time, offset) = match rest.find(['Z', '+', '-']) {
+        Some(i) => rest.split_at(i),
+        None => (rest, ""),
+    };
+
+    let date = date.split('-').map(|p| p.parse::<i64>()).collect::<Result<Vec<_>, _>>().map_err(|_| invalid())?;
+    let time = time.split(':').map(|p| p.parse::<i64>()).collect::<Result<Vec<_>, _>>().map_err(|_| invalid())?;
+    let (year, month, day) = match date[..] {
+        [year, month, day] if (1..=12).contains(&month) && (1..=days_in_month(year, month)).contains(&day) => (year, month, day),
+        _ => return Err(invalid()),
+    };
+    let (hour, minute, second) = match time[..] {
+        [hour, minute] => (hour, minute, 0),
+        [hour, minute, second] => (hour, minute, second),
+        _ => return Err(invalid()),
+    };
+    if hour > 23 || minute > 59 || second > 59 {
+        return Err(invalid());
+    }
+    let offset = match offset {
+        "" | "Z" => 0,
+        _ => {
+            let sign = if offset.starts_with('-') { -1 } else { 1 };
+            let parts = offset[1..].split(':').map(|p| p.parse::<i64>()).collect::<Result<Vec<_>, _>>().map_err(|_| invalid())?;
+            match parts[..] {
+                [hours, minutes] if (0..=23).contains(&hours) && (0..=59).contains(&minutes) => sign * (hours * 3600 + minutes * 60),
+                _ => return Err(invalid()),
+            }
+        },
+    };
+    Ok(days_from_civil(year, month, day) * 86400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+#[derive(Deserialize)]
+struct PriceRecord {
+    start: String,
+    end: Option<String>,
+    price: f64,
+}
+
+/// Sort intervals and run open ended ones until the next starts
+fn finish(mut rows: Vec<(i64, Option<i64>, f64)>) -> Result<Vec<PriceInterval>> {
+    rows.sort_by_key(|r| r.0);
+    let mut intervals: Vec<PriceInterval> = vec![];
+    for i in 0..rows.len() {
+        let (start, end, price) = rows[i];
+        if i > 0 && rows[i - 1].0 == start {
+            return Err(anyhow::anyhow!("Two prices start at {}", start));
+        }
+        let end = match (end, rows.get(i + 1)) {
+            (Some(end), _) => end,
+            (None, Some(next)) => next.0,
+            (None, None) => start + intervals.last().map_or(DEFAULT_INTERVAL, |l| l.end - l.start),
+        };
+        if end <= start {
+            return Err(anyhow::anyhow!("Price starting at {} ends before it starts", start));
+        }
+        intervals.push(PriceInterval { start, end, price });
+    }
+    Ok(intervals)
+}
+
+/// Read a schedule from CSV, or JSON when it's a list
+pub fn parse(text: &str) -> Result<Vec<PriceInterval>> {
+    if text.trim_start().starts_with('[') {
+        let rows: Vec<PriceRecord> = serde_json::from_str(text)?;
+        let rows = rows.into_iter()
+            .map(|r| Ok((parse_time(&r.start)?, r.end.as_deref().map(parse_time).transpose()?, r.price)))
+            .collect::<Result<Vec<_>>>()?;
+        return finish(rows);
+    }
+    let mut reader = csv::ReaderBuilder::new().trim(csv::Trim::All).from_reader(text.as_bytes());
+    let mut rows = vec![];
+    for (i, record) in reader.deserialize::<PriceRecord>().enumerate() {
+        // Line 1 is the header
+        let line = i + 2;
+        let record = record.map_err(|e| anyhow::anyhow!("Line {}: {}", line, e))?;
+        let start = parse_time(&record.start).map_err(|e| anyhow::anyhow!("Line {}: {}", line, e))?;
+        let end = record.end.as_deref()
+            .filter(|e| !e.is_empty())
+            .map(parse_time)
+            .transpose()
+            .map_err(|e| anyhow::anyhow!("Line {}: {}", line, e))?;
+        rows.push((start, end, record.price));
+    }
+    finish(rows)
+}
+
+/// Add intervals to the schedule, replacing any starting at the same time
+pub async fn store(db: &SqlitePool, intervals: &[PriceInterval]) -> Result<()> {
+    let mut tx = db.begin().await?;
+    for interval in intervals {
+        sqlx::query!(r#"
+            INSERT INTO prices (start, end, price) VALUES (?, ?, ?)
+            ON CONFLICT (start) DO UPDATE SET end = excluded.end, price = excluded.price
+            "#,
+            interval.start, interval.end, interval.price
+        ).execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+pub async fn import(db: &SqlitePool, path: &str) -> Result<usize> {
+    let intervals = parse(&std::fs::read_to_string(path)?)?;
+    store(db, &intervals).await?;
+    Ok(intervals.len())
+}
+
+/// GET a plain http url, the endpoint is expected to be on the local network
+async fn get(url: &str) -> Result<String> {
+    let rest = url.trim().strip_prefix("http://")
+        .ok_or_else(|| anyhow::anyhow!("Price endpoint must be an http:// url"))?;
+    let (host, path) = match rest.find('/') {
+        Some(i) => rest.split_at(i),
+        None => (rest, "/"),
+    };
+    let (name, port) = match host.rsplit_once(':') {
+        Some((name, port)) => (name, port.parse::<u16>()?),
+        None => (host, 80),
+    };
+    let request = format!("GET {} HTTP/1.0\r\nHost: {}\r\nConnection: close\r\n\r\n", path, host);
+    let response = timeout(TIMEOUT, async {
+        let mut stream = TcpStream::connect((name, port)).await?;
+        stream.write_all(request.as_bytes()).await?;
+        let mut buf = Vec::new();
+        stream.read_to_end(&mut buf).await?;
+        Ok::<_, std::io::Error>(buf)
+    }).await??;
+
+    let response = String::from_utf8_lossy(&response);
+    let (head, body) = response.split_once("\r\n\r\n")
+        .ok_or_else(|| anyhow::anyhow!("Invalid response from {}", url))?;
+    let status = head.lines().next().unwrap_or_default();
+    if status.split_whitespace().nth(1) != Some("200") {
+        return Err(anyhow::anyhow!("{} answered {}", url, status));
+    }
+    Ok(body.to_string())
+}
+
+/// Fetch the schedule from the endpoint and store it
+pub async fn fetch(db: &SqlitePool, url: &str) -> Result<usize> {
+    let intervals = parse(&get(url).await?)?;
+    store(db, &intervals).await?;
+    Ok(intervals.len())
+}
+
+/// Intervals ending after `from`, in order
+pub async fn schedule(db: &SqlitePool, from: i64) -> Result<Vec<PriceInterval>> {
+    let rows = sqlx::query!("SELECT start, end, price FROM prices WHERE end > ? ORDER BY start", from)
+        .fetch_all(db).await?;
+    Ok(rows.into_iter().map(|r| PriceInterval { start: r.start, end: r.end, price: r.price }).collect())
+}
+
+pub async fn current(db: &SqlitePool, at: i64) -> Result<Option<PriceInterval>> {
+    let row = sqlx::query!("SELECT start, end, price FROM prices WHERE start <= ? AND end > ? ORDER BY start DESC", at, at)
+        .fetch_optional(db).await?;
+    Ok(row.map(|r| PriceInterval { start: r.start, end: r.end, price: r.price }))
+}
+
+/// Miners whose state doesn't match what the price at `at` calls for
+/// Miners sleep while the price is above their model's breakeven and wake once it drops back.
+/// Only miners the scheduler put to sleep are woken, and miners cut by a curtailment that
+/// hasn't been reversed are left alone.
+pub async fn decide(db: &SqlitePool, pricing: &Pricing, at: i64) -> Result<Vec<Decision>> {
+    let interval = match current(db, at).await? {
+        Some(interval) => interval,
+        None => return Ok(vec![]),
+    };
+    let placed = Node::Site.ips(db).await?.into_iter().collect::<HashSet<_>>();
+    let curtailed = crate::curtail::curtailed(db).await?;
+    let last = sqlx::query!(r#"
+        SELECT ip, sleep AS "sleep: bool", interval, error
+        FROM price_decisions
+        WHERE id IN (SELECT MAX(id) FROM price_decisions GROUP BY ip)
+        "#
+    )
+        .fetch_all(db).await?
+        .into_iter()
+        .map(|d| (d.ip.clone(), d))
+        .collect::<HashMap<_, _>>();
+    // Miners whose last successful decision put them to sleep
+    let slept = sqlx::query!(r#"
+        SELECT ip, sleep AS "sleep: bool"
+        FROM price_decisions
+        WHERE id IN (SELECT MAX(id) FROM price_decisions WHERE error IS NULL GROUP BY ip)
+        "#
+    )
+        .fetch_all(db).await?
+        .into_iter()
+        .filter(|d| d.sleep)
+        .map(|d| d.ip)
+        .collect::<HashSet<_>>();
+
+    let mut decisions = vec![];
+    for sample in MinerSample::all(db).await? {
+        if !sample.online || !placed.contains(&sample.ip) || curtailed.contains_key(&sample.ip) {
+            continue;
+        }
+        let (model, breakeven) = match sample.model.as_deref().and_then(|m| Some((m, pricing.breakeven(m)?))) {
+            Some(found) => found,
+            None => continue,
+        };
+        let sleep = interval.price > breakeven;
+        // A failed decision is tried again in the next interval
+        if let Some(d) = last.get(&sample.ip) {
+            if d.error.is_some() && d.interval == interval.start && d.sleep == sleep {
+                continue;
+            }
+        }
+        let owned = slept.contains(&sample.ip);
+        let act = if sleep { !owned && !sample.sleep } else { owned };
+        if act {
+            decisions.push(Decision {
+                ip: sample.ip.clone(),
+                model: model.to_string(),
+                sleep,
+                price: interval.price,
+                breakeven,
+                interval: interval.start,
+            });
+        }
+    }
+    Ok(decisions)
+}
+
+/// Record decisions once they've been carried out, with the error of each that failed
+pub async fn record(db: &SqlitePool, decisions: &[(Decision, Option<String>)]) -> Result<()> {
+    let decided = now();
+    let mut tx = db.begin().await?;
+    for (d, error) in decisions {
+        sqlx::query!(r#"
+            INSERT INTO price_decisions (decided, ip, model, sleep, price, breakeven, interval, error)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            "#,
+            decided, d.ip, d.model, d.sleep, d.price, d.breakeven, d.interval, error
+        ).execute(&mut tx).await?;
+    }
+    tx.commit().await?;
+    Ok(())
+}
+
+/// The latest decisions, newest first
+pub async fn decisions(db: &SqlitePool, limit: i64) -> Result<Vec<PriceDecision>> {
+    let rows = sqlx::query!(r#"
+        SELECT id, decided, ip, model, sleep AS "sleep: bool", price, breakeven, interval, error
+        FROM price_decisions
+        ORDER BY id DESC
+        LIMIT ?
+        "#,
+        limit
+    ).fetch_all(db).await?;
+    Ok(rows.into_iter().map(|r| PriceDecision {
+        id: r.id,
+        decided: r.decided,
+        decision: Decision {
+            ip: r.ip,
+            model: r.model,
+            sleep: r.sleep,
+            price: r.price,
+            breakeven: r.breakeven,
+            interval: r.interval,
+        },
+        error: r.error,
+    }).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn civil_days() {
+        assert_eq!(days_from_civil(1970, 1, 1), 0);
+        assert_eq!(days_from_civil(2000, 3, 1), 11017);
+        assert_eq!(days_from_civil(1969, 12, 31), -1);
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(parse_time("1700000000").unwrap(), 1700000000);
+        assert_eq!(parse_time(" 2023-11-14 22:13:20 ").unwrap(), 1700000000);
+        assert_eq!(parse_time("2023-11-14T22:13:20Z").unwrap(), 1700000000);
+        assert_eq!(parse_time("2023-11-14T22:13").unwrap(), 1699999980);
+        assert_eq!(parse_time("2023-11-14T17:13:20-05:00").unwrap(), 1700000000);
+        assert_eq!(parse_time("2023-11-15T03:43:20+05:30").unwrap(), 1700000000);
+        assert_eq!(parse_time("2024-02-29 00:00").unwrap(), 1709164800);
+        assert_eq!(parse_time("2000-02-29 00:00").unwrap(), 951782400);
+    }
+
+    #[test]
+    fn bad_times() {
+        for value in [
+            "", "yesterday", "2023-11-14", "2023-11-14 22", "2023-11-14 24:00", "2023-11-14 22:60",
+            "2023-13-01 00:00", "2023-00-01 00:00", "2023-11-00 00:00", "2023-11-31 00:00",
+            "2023-02-29 00:00", "1900-02-29 00:00", "2023-02-31 00:00",
+            "2023-11-14T22:13+05", "2023-11-14T22:13+24:00", "2023-11-14T22:13+05:60",
+        ] {
+            assert!(parse_time(value).is_err(), "{}", value);
+        }
+    }
+
+    #[test]
+    fn interval_ends() {
+        let intervals = parse("start,price\n7200,30\n0,10\n3600,20\n").unwrap();
+        let ends = intervals.iter().map(|i| (i.start, i.end, i.price)).collect::<Vec<_>>();
+        // Open ended intervals run to the next, the last as long as the one before it
+        assert_eq!(ends, vec![(0, 3600, 10.0), (3600, 7200, 20.0), (7200, 10800, 30.0)]);
+
+        let single = parse("start,end,price\n0,,10\n").unwrap();
+        assert_eq!((single[0].start, single[0].end), (0, DEFAULT_INTERVAL));
+
+        let explicit = parse("start,end,price\n0,900,10\n3600,,20\n").unwrap();
+        assert_eq!((explicit[0].end, explicit[1].end), (900, 3600 + 900));
+
+        assert!(parse("start,end,price\n3600,0,10\n").is_err());
+        assert!(parse("start,price\n0,10\n0,20\n").is_err());
+        let err = parse("start,price\n0,10\n2023-02-31 00:00,20\n").unwrap_err();
+        assert!(err.to_string().starts_with("Line 3:"), "{}", err);
+    }
+
+    #[test]
+    fn json() {
+        let intervals = parse(r#"[
+            {"start": "2023-11-14T22:13:20Z", "price": 10},
+            {"start": "1700003600", "end": "1700007200", "price": 20}
+        ]"#).unwrap();
+        let ends = intervals.iter().map(|i| (i.start, i.end)).collect::<Vec<_>>();
+        assert_eq!(ends, vec![(1700000000, 1700003600), (1700003600, 1700007200)]);
+    }
+}
@@ -0,0 +1,606 @@
+//! Compliance reports for curtailment events
+//!
+//! Load curves are rebuilt from the power history, which holds a reading whenever a miner's
+//! scanned power changes, so every curve is a step function. A miner that doesn't answer a
+//! scan keeps its last known draw, and one still unreachable at the end of the event is
+//! reported since whether it curtailed is unknown. Miners are checked against the
+//! curtailments and price decisions made during the event, or every miner drawing power at
+//! the start when there were none.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write;
+use std::path::Path;
+
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use sqlx::sqlite::SqlitePool;
+
+use crate::db::JobRun;
+
+/// A commanded miner still drawing this fraction of its starting power failed to respond
+const RESPONSE: f64 = 0.5;
+/// Without a target, load is curtailed once this fraction of the event's largest reduction is reached
+const REACHED: f64 = 0.9;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct ReportRequest {
+    /// Unix times of the event window
+    pub start: i64,
+    pub end: i64,
+    /// kW the site had to get down to, taken from a curtailment in the window when not given
+    pub target: Option<f64>,
+}
+
+#[derive(Serialize, Debug, Clone, Copy)]
+pub struct LoadPoint {
+    pub time: i64,
+    /// kW from this time until the next point
+    pub load: f64,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct CanCurve {
+    pub id: i64,
+    pub name: String,
+    pub baseline: f64,
+    pub minimum: f64,
+    pub points: Vec<LoadPoint>,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct FailedMiner {
+    pub ip: String,
+    pub can: String,
+    pub rack: String,
+    /// Watts at the start and end of the event
+    pub baseline: f64,
+    pub last: f64,
+    pub reason: String,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Report {
+    pub start: i64,
+    pub end: i64,
+    pub target: Option<f64>,
+    /// Site kW at the start and the lowest during the event
+    pub baseline: f64,
+    pub minimum: f64,
+    /// When load was curtailed and seconds it took from the start
+    pub reached: Option<i64>,
+    pub time_to_curtail: Option<i64>,
+    /// kW reduction held from `reached` to the end, and its time weighted average
+    pub sustained: Option<f64>,
+    pub average: Option<f64>,
+    /// Miners expected to curtail
+    pub commanded: usize,
+    /// Commanded miners that didn't answer the last scan of the event
+    pub unreachable: usize,
+    pub site: Vec<LoadPoint>,
+    pub cans: Vec<CanCurve>,
+    pub failed: Vec<FailedMiner>,
+    pub jobs: Vec<JobRun>,
+}
+
+/// `2026-10-19 14:00:00 UTC`
+fn format_time(time: i64) -> String {
+    let days = time.div_euclid(86400);
+    let secs = time.rem_euclid(86400);
+    // Civil date from days since 1970-01-01
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z - era * 146097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as i64;
+    format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC", year, month, day, secs / 3600, secs % 3600 / 60, secs % 60)
+}
+
+/// Watts a reading shows, None when the miner didn't answer so its draw is unknown
+fn draw(online: bool, power: Option<f64>) -> Option<f64> {
+    if online { Some(power.unwrap_or(0.0)) } else { None }
+}
+
+/// Load at `time` from a step curve
+fn load_at(points: &[LoadPoint], time: i64) -> f64 {
+    points.iter().take_while(|p| p.time <= time).last().map_or(0.0, |p| p.load)
+}
+
+/// A miner's draw from the power history
+struct Reading {
+    ip: String,
+    time: i64,
+    /// Watts, None when the miner didn't answer
+    watts: Option<f64>,
+}
+
+/// Load curves of an event and where every miner ended up
+struct Curves {
+    site: Vec<LoadPoint>,
+    /// One curve per can, in the order of the can indexes
+    cans: Vec<Vec<LoadPoint>>,
+    /// Watts of every miner that answered before the start
+    starting: HashMap<String, f64>,
+    /// Last known watts of every miner
+    draws: HashMap<String, f64>,
+    /// Miners whose latest reading is unreachable
+    unreachable: HashSet<String>,
+}
+
+/// Step curves from the latest readings at `start` and the readings during the event in order
+/// `can_of` is the index of each miner's can, readings of other miners are ignored
+fn curves(start: i64, can_of: &HashMap<&str, usize>, cans: usize, baseline: Vec<Reading>, readings: Vec<Reading>) -> Curves {
+    let mut draws: HashMap<String, f64> = HashMap::new();
+    let mut unreachable: HashSet<String> = HashSet::new();
+    let mut loads = vec![0.0; cans];
+    for reading in baseline {
+        if let Some(&can) = can_of.get(reading.ip.as_str()) {
+            match reading.watts {
+                Some(watts) => {
+                    loads[can] += watts;
+                    draws.insert(reading.ip, watts);
+                },
+                None => {
+                    unreachable.insert(reading.ip);
+                },
+            }
+        }
+    }
+    let starting = draws.clone();
+    let mut site = vec![LoadPoint { time: start, load: loads.iter().sum::<f64>() / 1000.0 }];
+    let mut curves = loads.iter().map(|load| vec![LoadPoint { time: start, load: load / 1000.0 }]).collect::<Vec<_>>();
+
+    let mut by_time: BTreeMap<i64, Vec<Reading>> = BTreeMap::new();
+    for reading in readings {
+        by_time.entry(reading.time).or_default().push(reading);
+    }
+    for (time, readings) in by_time {
+        let mut touched = HashSet::new();
+        for reading in readings {
+            if let Some(&can) = can_of.get(reading.ip.as_str()) {
+                match reading.watts {
+                    Some(watts) => {
+                        unreachable.remove(&reading.ip);
+                        let previous = draws.insert(reading.ip, watts).unwrap_or(0.0);
+                        loads[can] += watts - previous;
+                        touched.insert(can);
+                    },
+                    None => {
+                        unreachable.insert(reading.ip);
+                    },
+                }
+            }
+        }
+        if touched.is_empty() {
+            continue;
+        }
+        site.push(LoadPoint { time, load: loads.iter().sum::<f64>() / 1000.0 });
+        for can in touched {
+            curves[can].push(LoadPoint { time, load: loads[can] / 1000.0 });
+        }
+    }
+    Curves { site, cans: curves, starting, draws, unreachable }
+}
+
+/// When the site load was curtailed, reaching `target` or most of the event's largest reduction
+fn reached(site: &[LoadPoint], target: Option<f64>) -> Option<i64> {
+    let baseline = site.first()?.load;
+    let minimum = site.iter().map(|p| p.load).fold(f64::INFINITY, f64::min);
+    match target {
+        Some(target) => site.iter().find(|p| p.load <= target),
+        None if baseline > minimum => site.iter().find(|p| baseline - p.load >= (baseline - minimum) * REACHED),
+        None => None,
+    }.map(|p| p.time)
+}
+
+/// Highest and time weighted average load from `reached` to `end`
+fn held(site: &[LoadPoint], reached: i64, end: i64) -> (f64, f64) {
+    let highest = site.iter()
+        .filter(|p| p.time >= reached)
+        .map(|p| p.load)
+        .fold(load_at(site, reached), f64::max);
+    if end <= reached {
+        return (highest, load_at(site, reached));
+    }
+    let mut energy = 0.0;
+    let times = site.iter().map(|p| p.time).filter(|t| *t > reached && *t < end).chain([end]);
+    let mut from = reached;
+    for to in times {
+        energy += load_at(site, from) * (to - from) as f64;
+        from = to;
+    }
+    (highest, energy / (end - reached) as f64)
+}
+
+pub async fn generate(db: &SqlitePool, request: &ReportRequest) -> Result<Report> {
+    let (start, end) = (request.start, request.end);
+    if end <= start {
+        return Err(anyhow::anyhow!("The event must end after it starts"));
+    }
+
+    let miners = sqlx::query!(r#"
+        SELECT m.ip, r.name AS rack, c.id AS can_id, c.name AS can
+        FROM miners m
+        JOIN racks r ON m.rack_id = r.id
+        JOIN cans c ON r.can_id = c.id
+        WHERE m.ip != ''
+        ORDER BY c.num, r.index_, m.row, m.index_
+        "#
+    ).fetch_all(db).await?;
+    let mut cans: Vec<CanCurve> = vec![];
+    let mut can_of = HashMap::new();
+    for miner in &miners {
+        if cans.last().map(|c| c.id) != Some(miner.can_id) {
+            cans.push(CanCurve { id: miner.can_id, name: miner.can.clone(), baseline: 0.0, minimum: 0.0, points: vec![] });
+        }
+        can_of.insert(miner.ip.as_str(), cans.len() - 1);
+    }
+
+    // Latest reading of every miner at the start, then every change during the event
+    let baseline = sqlx::query!(r#"
+        SELECT ip, online AS "online: bool", power
+        FROM power_readings
+        WHERE id IN (SELECT MAX(id) FROM power_readings WHERE sampled <= ? GROUP BY ip)
+        "#,
+        start
+    ).fetch_all(db).await?;
+    let readings = sqlx::query!(r#"
+        SELECT ip, sampled, online AS "online: bool", power
+        FROM power_readings
+        WHERE sampled > ? AND sampled <= ?
+        ORDER BY sampled, id
+        "#,
+        start, end
+    ).fetch_all(db).await?;
+
+    let baseline = baseline.into_iter()
+        .map(|r| Reading { watts: draw(r.online, r.power), ip: r.ip, time: start })
+        .collect();
+    let readings = readings.into_iter()
+        .map(|r| Reading { watts: draw(r.online, r.power), ip: r.ip, time: r.sampled })
+        .collect();
+    let Curves { site, cans: points, starting, draws, unreachable } = curves(start, &can_of, cans.len(), baseline, readings);
+    for (curve, points) in cans.iter_mut().zip(points) {
+        curve.baseline = points[0].load;
+        curve.minimum = points.iter().map(|p| p.load).fold(f64::INFINITY, f64::min);
+        curve.points = points;
+    }
+
+    let baseline_kw = site[0].load;
+    let minimum = site.iter().map(|p| p.load).fold(f64::INFINITY, f64::min);
+    let target = match request.target {
+        Some(target) => Some(target),
+        None => sqlx::query!(r#"SELECT target AS "target!" FROM curtailments WHERE created BETWEEN ? AND ? ORDER BY created LIMIT 1"#, start, end)
+            .fetch_optional(db).await?
+            .map(|c| c.target),
+    };
+    let reached = reached(&site, target);
+    let (sustained, average) = match reached {
+        Some(reached) => {
+            let (highest, average) = held(&site, reached, end);
+            (Some(baseline_kw - highest), Some(baseline_kw - average))
+        },
+        None => (None, None),
+    };
+
+    // Miners told to curtail during the event and any errors doing so
+    let mut commanded: HashMap<String, Option<String>> = HashMap::new();
+    let actions = sqlx::query!(r#"
+        SELECT a.ip, a.error
+        FROM curtailment_actions a
+        JOIN curtailments c ON a.curtailment_id = c.id
+        WHERE c.created BETWEEN ? AND ?
+        "#,
+        start, end
+    ).fetch_all(db).await?;
+    let decisions = sqlx::query!(
+        "SELECT ip, error FROM price_decisions WHERE sleep AND decided BETWEEN ? AND ?",
+        start, end
+    ).fetch_all(db).await?;
+    for (ip, error) in actions.into_iter().map(|a| (a.ip, a.error)).chain(decisions.into_iter().map(|d| (d.ip, d.error))) {
+        let entry = commanded.entry(ip).or_default();
+        if entry.is_none() {
+            *entry = error;
+        }
+    }
+    if commanded.is_empty() {
+        commanded = starting.iter()
+            .filter(|(_, watts)| **watts > 0.0)
+            .map(|(ip, _)| (ip.clone(), None))
+            .collect();
+    }
+
+    let mut failed = vec![];
+    for miner in &miners {
+        let error = match commanded.get(&miner.ip) {
+            Some(error) => error,
+            None => continue,
+        };
+        let baseline = starting.get(&miner.ip).copied().unwrap_or(0.0);
+        let last = draws.get(&miner.ip).copied().unwrap_or(0.0);
+        // Readings are only stored on change, a miner without any during the event is
+        // still drawing its baseline
+        let reason = if let Some(error) = error {
+            format!("Command failed: {}", error)
+        } else if unreachable.contains(&miner.ip) {
+            format!("Unreachable, unknown whether it curtailed, last read {:.0} W", last)
+        } else if baseline > 0.0 && last >= baseline * RESPONSE {
+            format!("Still drawing {:.0} W of {:.0} W", last, baseline)
+        } else {
+            continue;
+        };
+        failed.push(FailedMiner {
+            ip: miner.ip.clone(),
+            can: miner.can.clone(),
+            rack: miner.rack.clone(),
+            baseline,
+            last,
+            reason,
+        });
+    }
+
+    Ok(Report {
+        start,
+        end,
+        target,
+        baseline: baseline_kw,
+        minimum,
+        reached,
+        time_to_curtail: reached.map(|r| r - start),
+        sustained,
+        average,
+        commanded: commanded.len(),
+        unreachable: commanded.keys().filter(|ip| unreachable.contains(*ip)).count(),
+        site,
+        cans,
+        failed,
+        jobs: JobRun::between(db, start, end).await?,
+    })
+}
+
+fn optional(value: Option<f64>) -> String {
+    value.map(|v| format!("{:.3}", v)).unwrap_or_default()
+}
+
+fn summary(report: &Report) -> Vec<(&'static str, String)> {
+    vec![
+        ("Event start", format_time(report.start)),
+        ("Event end", format_time(report.end)),
+        ("Target (kW)", optional(report.target)),
+        ("Baseline (kW)", format!("{:.3}", report.baseline)),
+        ("Minimum (kW)", format!("{:.3}", report.minimum)),
+        ("Curtailed at", report.reached.map(format_time).unwrap_or_default()),
+        ("Time to curtail (s)", report.time_to_curtail.map(|t| t.to_string()).unwrap_or_default()),
+        ("Sustained reduction (kW)", optional(report.sustained)),
+        ("Average reduction (kW)", optional(report.average)),
+        ("Miners commanded", report.commanded.to_string()),
+        ("Miners unreachable", report.unreachable.to_string()),
+        ("Miners failed to respond", report.failed.len().to_string()),
+    ]
+}
+
+/// Site and per can load at every change, one row per time
+fn write_curves(report: &Report, path: &Path) -> Result<()> {
+    let mut writer = csv::Writer::from_path(path)?;
+    let mut header = vec!["time".to_string(), "site_kw".to_string()];
+    header.extend(report.cans.iter().map(|c| format!("{}_kw", c.name)));
+    writer.write_record(&header)?;
+    for point in &report.site {
+        let mut row = vec![format_time(point.time), format!("{:.3}", point.load)];
+        row.extend(report.cans.iter().map(|c| format!("{:.3}", load_at(&c.points, point.time))));
+        writer.write_record(&row)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// The load curves to `path`, with the summary and failed miners alongside it
+fn write_csv(report: &Report, path: &Path) -> Result<Vec<String>> {
+    let stem = path.file_stem().map(|s| s.to_string_lossy().to_string()).unwrap_or_default();
+    let sibling = |suffix: &str| path.with_file_name(format!("{}-{}.csv", stem, suffix));
+    write_curves(report, path)?;
+
+    let summary_path = sibling("summary");
+    let mut writer = csv::Writer::from_path(&summary_path)?;
+    writer.write_record(["metric", "value"])?;
+    for (metric, value) in summary(report) {
+        writer.write_record([metric, value.as_str()])?;
+    }
+    writer.flush()?;
+
+    let failed_path = sibling("failed");
+    let mut writer = csv::Writer::from_path(&failed_path)?;
+    writer.write_record(["ip", "can", "rack", "baseline_w", "last_w", "reason"])?;
+    for miner in &report.failed {
+        writer.write_record([
+            miner.ip.as_str(), miner.can.as_str(), miner.rack.as_str(),
+            &format!("{:.0}", miner.baseline), &format!("{:.0}", miner.last), miner.reason.as_str(),
+        ])?;
+    }
+    writer.flush()?;
+
+    Ok([path.to_path_buf(), summary_path, failed_path].iter().map(|p| p.to_string_lossy().to_string()).collect())
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;").replace('"', "&quot;")
+}
+
+/// Step line of the site load, with the target dashed
+fn chart(report: &Report) -> String {
+    const WIDTH: f64 = 800.0;
+    const HEIGHT: f64 = 300.0;
+    let max = report.site.iter().map(|p| p.load).chain(report.target).fold(0.0, f64::max).max(0.001) * 1.1;
+    let x = |t: i64| (t - report.start) as f64 / (report.end - report.start) as f64 * WIDTH;
+    let y = |load: f64| HEIGHT - load / max * HEIGHT;
+
+    let mut path = String::new();
+    for (i, point) in report.site.iter().enumerate() {
+        if i == 0 {
+            write!(path, "M{:.1} {:.1}", x(point.time), y(point.load)).unwrap();
+        } else {
+            write!(path, " H{:.1} V{:.1}", x(point.time), y(point.load)).unwrap();
+        }
+    }
+    write!(path, " H{:.1}", WIDTH).unwrap();
+
+    let mut svg = format!(r#"<svg viewBox="0 0 {} {}" width="{}" height="{}" xmlns="http://www.w3.org/2000/svg">"#, WIDTH, HEIGHT, WIDTH, HEIGHT);
+    svg.push_str(r##"<rect width="100%" height="100%" fill="#fafafa" stroke="#ccc"/>"##);
+    if let Some(target) = report.target {
+        write!(svg, r##"<line x1="0" x2="{}" y1="{:.1}" y2="{:.1}" stroke="#c00" stroke-dasharray="6 4"/>"##, WIDTH, y(target), y(target)).unwrap();
+    }
+    if let Some(reached) = report.reached {
+        write!(svg, r##"<line x1="{:.1}" x2="{:.1}" y1="0" y2="{}" stroke="#090" stroke-dasharray="2 4"/>"##, x(reached), x(reached), HEIGHT).unwrap();
+    }
+    write!(svg, r##"<path d="{}" fill="none" stroke="#036" stroke-width="2"/>"##, path).unwrap();
+    write!(svg, r##"<text x="4" y="14" font-size="12">{:.0} kW</text></svg>"##, max).unwrap();
+    svg
+}
+
+fn table(header: &[&str], rows: impl IntoIterator<Item = Vec<String>>) -> String {
+    let mut html = String::from("<table><tr>");
+    for cell in header {
+        write!(html, "<th>{}</th>", escape(cell)).unwrap();
+    }
+    html.push_str("</tr>");
+    for row in rows {
+        html.push_str("<tr>");
+        for cell in row {
+            write!(html, "<td>{}</td>", escape(&cell)).unwrap();
+        }
+        html.push_str("</tr>");
+    }
+    html.push_str("</table>");
+    html
+}
+
+fn html(report: &Report) -> String {
+    let mut html = String::from(concat!(
+        "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Curtailment report</title><style>",
+        "body{font-family:sans-serif;margin:2em}table{border-collapse:collapse;margin-bottom:2em}",
+        "th,td{border:1px solid #ccc;padding:4px 8px;text-align:left}th{background:#eee}",
+        "</style></head><body>",
+    ));
+    write!(html, "<h1>Curtailment report</h1><p>{} to {}</p>", format_time(report.start), format_time(report.end)).unwrap();
+    html.push_str(&table(&["Metric", "Value"], summary(report).into_iter().map(|(m, v)| vec![m.to_string(), v])));
+    html.push_str("<h2>Site load</h2>");
+    html.push_str(&chart(report));
+    html.push_str(&table(&["Time", "Site (kW)"], report.site.iter().map(|p| vec![format_time(p.time), format!("{:.3}", p.load)])));
+    html.push_str("<h2>Cans</h2>");
+    html.push_str(&table(
+        &["Can", "Baseline (kW)", "Minimum (kW)", "End (kW)", "Reduction (kW)"],
+        report.cans.iter().map(|c| {
+            let last = c.points.last().map_or(0.0, |p| p.load);
+            vec![c.name.clone(), format!("{:.3}", c.baseline), format!("{:.3}", c.minimum), format!("{:.3}", last), format!("{:.3}", c.baseline - last)]
+        }),
+    ));
+    write!(html, "<h2>Miners that failed to respond ({})</h2>", report.failed.len()).unwrap();
+    html.push_str(&table(
+        &["IP", "Can", "Rack", "Baseline (W)", "Last (W)", "Reason"],
+        report.failed.iter().map(|m| vec![
+            m.ip.clone(), m.can.clone(), m.rack.clone(),
+            format!("{:.0}", m.baseline), format!("{:.0}", m.last), m.reason.clone(),
+        ]),
+    ));
+    html.push_str("<h2>Jobs</h2>");
+    html.push_str(&table(
+        &["Job", "Started", "Finished", "Miners", "Failed", "Cancelled"],
+        report.jobs.iter().map(|j| vec![
+            j.job.clone(),
+            format_time(j.started),
+            j.finished.map(format_time).unwrap_or_default(),
+            j.tasks.to_string(),
+            j.failed.map(|f| f.to_string()).unwrap_or_default(),
+            j.cancelled.map(|c| c.to_string()).unwrap_or_default(),
+        ]),
+    ));
+    html.push_str("</body></html>");
+    html
+}
+
+/// Write the report as HTML, or as CSV files when `path` ends in .csv, returns the files written
+pub async fn export(db: &SqlitePool, request: &ReportRequest, path: &str) -> Result<Vec<String>> {
+    let report = generate(db, request).await?;
+    let path = Path::new(path);
+    match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()).as_deref() {
+        Some("csv") => write_csv(&report, path),
+        Some("html") | Some("htm") => {
+            std::fs::write(path, html(&report))?;
+            Ok(vec![path.to_string_lossy().to_string()])
+        },
+        _ => Err(anyhow::anyhow!("Reports are exported as .csv or .html")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(ip: &str, time: i64, watts: Option<f64>) -> Reading {
+        Reading { ip: ip.to_string(), time, watts }
+    }
+
+    fn steps(points: &[LoadPoint]) -> Vec<(i64, f64)> {
+        points.iter().map(|p| (p.time, p.load)).collect()
+    }
+
+    fn site(points: &[(i64, f64)]) -> Vec<LoadPoint> {
+        points.iter().map(|&(time, load)| LoadPoint { time, load }).collect()
+    }
+
+    #[test]
+    fn times() {
+        assert_eq!(format_time(0), "1970-01-01 00:00:00 UTC");
+        assert_eq!(format_time(1700000000), "2023-11-14 22:13:20 UTC");
+        assert_eq!(format_time(951782400), "2000-02-29 00:00:00 UTC");
+        assert_eq!(format_time(1709251199), "2024-02-29 23:59:59 UTC");
+        assert_eq!(format_time(-1), "1969-12-31 23:59:59 UTC");
+    }
+
+    #[test]
+    fn step_curves() {
+        let can_of = HashMap::from([("a", 0), ("b", 0), ("c", 1)]);
+        let baseline = vec![reading("a", 0, Some(1000.0)), reading("b", 0, Some(2000.0)), reading("c", 0, None)];
+        let readings = vec![
+            reading("a", 10, Some(0.0)),
+            // Not in the layout
+            reading("x", 15, Some(5000.0)),
+            reading("b", 20, Some(100.0)),
+            reading("c", 20, Some(500.0)),
+            reading("b", 30, None),
+        ];
+        let curves = curves(0, &can_of, 2, baseline, readings);
+        assert_eq!(steps(&curves.site), vec![(0, 3.0), (10, 2.0), (20, 0.6)]);
+        assert_eq!(steps(&curves.cans[0]), vec![(0, 3.0), (10, 2.0), (20, 0.1)]);
+        assert_eq!(steps(&curves.cans[1]), vec![(0, 0.0), (20, 0.5)]);
+        assert_eq!(curves.starting, HashMap::from([("a".to_string(), 1000.0), ("b".to_string(), 2000.0)]));
+        // An unreachable miner keeps its last known draw
+        assert_eq!(curves.draws["b"], 100.0);
+        assert_eq!(curves.unreachable, HashSet::from(["b".to_string()]));
+    }
+
+    #[test]
+    fn time_to_curtail() {
+        let points = site(&[(0, 10.0), (10, 8.0), (20, 5.0), (30, 1.5), (40, 1.0)]);
+        assert_eq!(reached(&points, Some(5.0)), Some(20));
+        assert_eq!(reached(&points, Some(0.5)), None);
+        // 90% of the 9 kW reduction
+        assert_eq!(reached(&points, None), Some(30));
+        assert_eq!(reached(&site(&[(0, 10.0), (10, 12.0)]), None), None);
+        assert_eq!(reached(&[], None), None);
+    }
+
+    #[test]
+    fn held_load() {
+        let points = site(&[(0, 10.0), (10, 4.0), (20, 6.0), (30, 4.0)]);
+        let (highest, average) = held(&points, 10, 40);
+        assert_eq!(highest, 6.0);
+        assert!((average - 140.0 / 30.0).abs() < 1e-9);
+        // The last step runs to the end
+        assert_eq!(held(&points, 30, 100), (4.0, 4.0));
+        assert_eq!(held(&points, 20, 20), (6.0, 6.0));
+    }
+}
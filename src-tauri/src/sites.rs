@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+
+const SITES_FILE: &str = "sites.json";
+const DB_FILE: &str = "scanner.db";
+
+/// A site profile, each site keeps its layout and settings in its own database
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Site {
+    pub name: String,
+    pub path: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Sites {
+    pub active: String,
+    pub sites: Vec<Site>,
+    #[serde(skip)]
+    file: PathBuf,
+}
+
+impl Sites {
+    /// Load site profiles from the app config directory
+    /// Creates a default site in the app data directory on first run
+    pub fn load(config: &tauri::Config) -> Result<Self> {
+        let config_dir = tauri::api::path::app_config_dir(config)
+            .ok_or_else(|| anyhow::anyhow!("No app config directory"))?;
+        let file = config_dir.join(SITES_FILE);
+        if file.exists() {
+            let mut sites: Sites = serde_json::from_str(&std::fs::read_to_string(&file)?)?;
+            sites.file = file;
+            return Ok(sites);
+        }
+
+        // Older versions kept the database in the working directory, keep using it if it's there
+        let path = match Path::new(DB_FILE).canonicalize() {
+            Ok(path) => path,
+            Err(_) => tauri::api::path::app_data_dir(config)
+                .ok_or_else(|| anyhow::anyhow!("No app data directory"))?
+                .join(DB_FILE),
+        };
+        let sites = Sites {
+            active: "Default".to_string(),
+            sites: vec![Site {
+                name: "Default".to_string(),
+                path: path.to_string_lossy().to_string(),
+            }],
+            file,
+        };
+        sites.save()?;
+        Ok(sites)
+    }
+
+    pub fn save(&self) -> Result<()> {
+        if let Some(dir) = self.file.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(&self.file, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Site> {
+        self.sites.iter()
+            .find(|s| s.name == name)
+            .ok_or_else(|| anyhow::anyhow!("No site named {}", name))
+    }
+
+    pub fn active(&self) -> Result<&Site> {
+        self.get(&self.active)
+    }
+
+    /// Add a site or update the path of an existing one
+    pub fn upsert(&mut self, site: Site) -> Result<()> {
+        if site.name.trim().is_empty() || site.path.trim().is_empty() {
+            return Err(anyhow::anyhow!("Site name and path are required"));
+        }
+        if site.name == self.active {
+            return Err(anyhow::anyhow!("Can't change the path of the active site"));
+        }
+        match self.sites.iter_mut().find(|s| s.name == site.name) {
+            Some(existing) => existing.path = site.path,
+            None => self.sites.push(site),
+        }
+        self.save()
+    }
+
+    pub fn remove(&mut self, name: &str) -> Result<()> {
+        if name == self.active {
+            return Err(anyhow::anyhow!("Can't remove the active site"));
+        }
+        self.sites.retain(|s| s.name != name);
+        self.save()
+    }
+}
@@ -0,0 +1,110 @@
+use anyhow::Result;
+use serde::Serialize;
+use serde_json::{json, Value};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::net::tcp::OwnedReadHalf;
+use tokio::time::{timeout, Duration, Instant};
+
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Serialize, Debug, Clone)]
+pub struct ProbeResult {
+    pub url: String,
+    pub ok: bool,
+    pub subscribed: bool,
+    pub authorized: bool,
+    /// Round trip of the subscribe request in milliseconds
+    pub latency: Option<f64>,
+    pub error: Option<String>,
+}
+
+/// Split a pool url such as stratum+tcp://pool.example.com:3333 into host and port
+fn parse_url(url: &str) -> Result<(String, u16)> {
+    let url = url.trim();
+    let rest = match url.split_once("://") {
+        Some(("stratum+tcp", rest)) | Some(("stratum", rest)) | Some(("tcp", rest)) => rest,
+        Some((scheme, _)) => return Err(anyhow::anyhow!("Unsupported scheme {}", scheme)),
+        None => url,
+    };
+    let rest = rest.trim_end_matches('/');
+    let (host, port) = rest.rsplit_once(':')
+        .ok_or_else(|| anyhow::anyhow!("Missing port in {}", url))?;
+    Ok((host.to_string(), port.parse()?))
+}
+
+/// Read lines until the response to `id`, skipping notifications the pool sends in between
+async fn response(reader: &mut BufReader<OwnedReadHalf>, id: u64) -> Result<Value> {
+    let mut line = String::new();
+    loop {
+        line.clear();
+        if reader.read_line(&mut line).await? == 0 {
+            return Err(anyhow::anyhow!("Connection closed by pool"));
+        }
+        let msg: Value = serde_json::from_str(line.trim())?;
+        if msg["id"].as_u64() == Some(id) {
+            if !msg["error"].is_null() {
+                return Err(anyhow::anyhow!("{}", msg["error"]));
+            }
+            return Ok(msg["result"].clone());
+        }
+    }
+}
+
+async fn run(url: &str, worker: &str, password: &str, result: &mut ProbeResult) -> Result<()> {
+    let (host, port) = parse_url(url)?;
+    let stream = timeout(TIMEOUT, TcpStream::connect((host.as_str(), port))).await??;
+    let (read, mut write) = stream.into_split();
+    let mut reader = BufReader::new(read);
+
+    let start = Instant::now();
+    let subscribe = json!({ "id": 1, "method": "mining.subscribe", "params": ["anttools"] });
+    write.write_all(format!("{}\n", subscribe).as_bytes()).await?;
+    timeout(TIMEOUT, response(&mut reader, 1)).await??;
+    result.latency = Some(start.elapsed().as_secs_f64() * 1000.0);
+    result.subscribed = true;
+
+    let authorize = json!({ "id": 2, "method": "mining.authorize", "params": [worker, password] });
+    write.write_all(format!("{}\n", authorize).as_bytes()).await?;
+    let authed = timeout(TIMEOUT, response(&mut reader, 2)).await??;
+    result.authorized = authed.as_bool().unwrap_or(false);
+    if !result.authorized {
+        return Err(anyhow::anyhow!("Worker {} was not authorized", worker));
+    }
+    Ok(())
+}
+
+/// Open a stratum connection and attempt to subscribe and authorize the worker
+pub async fn probe(url: &str, worker: &str, password: &str) -> ProbeResult {
+    let mut result = ProbeResult {
+        url: url.to_string(),
+        ok: false,
+        subscribed: false,
+        authorized: false,
+        latency: None,
+        error: None,
+    };
+    match run(url, worker, password, &mut result).await {
+        Ok(()) => result.ok = true,
+        Err(e) => result.error = Some(e.to_string()),
+    }
+    result
+}
+
+/// Probe every configured url of a pool concurrently
+pub async fn probe_pool(pool: &crate::db::Pool, worker: &str) -> Vec<ProbeResult> {
+    let password = pool.password.clone().unwrap_or_default();
+    let handles = pool.urls().into_iter()
+        .map(|url| {
+            let (url, worker, password) = (url.to_string(), worker.to_string(), password.clone());
+            tokio::spawn(async move { probe(&url, &worker, &password).await })
+        })
+        .collect::<Vec<_>>();
+    let mut results = vec![];
+    for handle in handles {
+        if let Ok(result) = handle.await {
+            results.push(result);
+        }
+    }
+    results
+}
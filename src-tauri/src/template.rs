@@ -0,0 +1,417 @@
+//! Generate cans, racks and miner IPs from an addressing scheme
+//!
+//! Names and IPs are templates where anything in braces is integer arithmetic, for example
+//! `10.{can}.{rack}.{row * width + col + 10}`. Expressions support `+ - * / %` and parentheses
+//! over these variables:
+//!
+//! - `can` the can number, counting from the template's first can
+//! - `rack` the rack within its can, from 1
+//! - `row` and `col` the slot within its rack, from 0 at the top left
+//! - `slot` the slot within its rack counted left to right then top to bottom, from 0
+//! - `width` and `height` of the racks
+//!
+//! Can names can only use `can`, `width` and `height`, rack names can't use the slot variables.
+
+use std::collections::HashMap;
+
+use serde::{Serialize, Deserialize};
+use anyhow::Result;
+use sqlx::sqlite::SqlitePool;
+
+use crate::db::Numbering;
+use crate::layout::{self, Builder, ImportProblem, Layout, NewMiner, NewRack, Source};
+
+/// Refuse templates bigger than any site so a typo can't hang the app
+const MAX_SLOTS: i64 = 100_000;
+/// Deepest nesting of parentheses and unary minus an expression may use
+const MAX_DEPTH: usize = 32;
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Template {
+    /// Number of the first can generated
+    pub first_can: i64,
+    /// Number of cans to generate
+    pub cans: i64,
+    /// Racks in each can
+    pub racks: i64,
+    pub width: i64,
+    pub height: i64,
+    pub can_name: String,
+    pub rack_name: String,
+    pub ip: String,
+}
+
+/// What generating a template would add, nothing is written
+#[derive(Serialize, Debug)]
+pub struct TemplatePreview {
+    pub layout: Layout,
+    pub miners: usize,
+    /// Generated values that aren't valid, such as an octet over 255
+    pub problems: Vec<ImportProblem>,
+    /// Generated names and IPs already used in the layout
+    pub collisions: Vec<String>,
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    vars: &'a [(&'a str, i64)],
+    depth: usize,
+}
+
+impl Parser<'_> {
+    fn skip_space(&mut self) {
+        while self.chars.peek().map_or(false, |c| c.is_whitespace()) {
+            self.chars.next();
+        }
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_space();
+        self.chars.peek().copied()
+    }
+
+    fn expr(&mut self) -> Result<i64> {
+        let mut value = self.term()?;
+        while let Some(op @ ('+' | '-')) = self.peek() {
+            self.chars.next();
+            let rhs = self.term()?;
+            value = if op == '+' { value.checked_add(rhs) } else { value.checked_sub(rhs) }
+                .ok_or_else(|| anyhow::anyhow!("Number too large"))?;
+        }
+        Ok(value)
+    }
+
+    fn term(&mut self) -> Result<i64> {
+        let mut value = self.factor()?;
+        while let Some(op @ ('*' | '/' | '%')) = self.peek() {
+            self.chars.next();
+            let rhs = self.factor()?;
+            value = match op {
+                _ if op != '*' && rhs == 0 => return Err(anyhow::anyhow!("Division by zero")),
+                '*' => value.checked_mul(rhs),
+                '/' => value.checked_div(rhs),
+                _ => value.checked_rem(rhs),
+            }.ok_or_else(|| anyhow::anyhow!("Number too large"))?;
+        }
+        Ok(value)
+    }
+
+    /// Parse a nested part of the expression, refusing nesting deep enough to overflow the stack
+    fn nested(&mut self, parse: fn(&mut Self) -> Result<i64>) -> Result<i64> {
+        if self.depth >= MAX_DEPTH {
+            return Err(anyhow::anyhow!("Expression nested too deeply"));
+        }
+        self.depth += 1;
+        let value = parse(self);
+        self.depth -= 1;
+        value
+    }
+
+    fn factor(&mut self) -> Result<i64> {
+        match self.peek() {
+            Some('-') => {
+                self.chars.next();
+                self.nested(Self::factor)?
+                    .checked_neg()
+                    .ok_or_else(|| anyhow::anyhow!("Number too large"))
+            },
+            Some('(') => {
+                self.chars.next();
+                let value = self.nested(Self::expr)?;
+                match self.peek() {
+                    Some(')') => {
+                        self.chars.next();
+                        Ok(value)
+                    },
+                    _ => Err(anyhow::anyhow!("Missing )")),
+                }
+            },
+            Some(c) if c.is_ascii_digit() => {
+                let mut digits = String::new();
+                while let Some(c) = self.chars.peek().copied().filter(|c| c.is_ascii_digit()) {
+                    digits.push(c);
+                    self.chars.next();
+                }
+                Ok(digits.parse()?)
+            },
+            Some(c) if c.is_ascii_alphabetic() => {
+                let mut name = String::new();
+                while let Some(c) = self.chars.peek().copied().filter(|c| c.is_ascii_alphanumeric() || *c == '_') {
+                    name.push(c);
+                    self.chars.next();
+                }
+                self.vars.iter()
+                    .find(|(var, _)| *var == name)
+                    .map(|(_, value)| *value)
+                    .ok_or_else(|| anyhow::anyhow!("Unknown variable {}", name))
+            },
+            Some(c) => Err(anyhow::anyhow!("Unexpected {}", c)),
+            None => Err(anyhow::anyhow!("Expression ended early")),
+        }
+    }
+}
+
+fn eval(expr: &str, vars: &[(&str, i64)]) -> Result<i64> {
+    let mut parser = Parser { chars: expr.chars().peekable(), vars, depth: 0 };
+    let value = parser.expr()?;
+    match parser.peek() {
+        None => Ok(value),
+        Some(c) => Err(anyhow::anyhow!("Unexpected {}", c)),
+    }
+}
+
+/// Fill in every `{expression}` in a template
+fn render(template: &str, vars: &[(&str, i64)]) -> Result<String> {
+    let mut out = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        out.push_str(&rest[..start]);
+        let end = rest[start..].find('}')
+            .ok_or_else(|| anyhow::anyhow!("Missing }} in {}", template))?;
+        let expr = &rest[start + 1..start + end];
+        let value = eval(expr, vars)
+            .map_err(|e| anyhow::anyhow!("{} in {{{}}} of {}", e, expr, template))?;
+        out.push_str(&value.to_string());
+        rest = &rest[start + end + 1..];
+    }
+    if rest.contains('}') {
+        return Err(anyhow::anyhow!("Unmatched }} in {}", template));
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+impl Template {
+    fn check(&self) -> Result<()> {
+        if self.cans < 1 || self.racks < 1 || self.width < 1 || self.height < 1 {
+            return Err(anyhow::anyhow!("Cans, racks, width and height must be at least 1"));
+        }
+        let slots = [self.cans, self.racks, self.width, self.height].iter()
+            .try_fold(1i64, |acc, n| acc.checked_mul(*n))
+            .filter(|n| *n <= MAX_SLOTS);
+        if slots.is_none() {
+            return Err(anyhow::anyhow!("Templates are limited to {} slots", MAX_SLOTS));
+        }
+        Ok(())
+    }
+
+    /// Expand the template, invalid IPs and duplicates end up as problems
+    fn expand(&self) -> Result<(layout::Sitemap, Vec<ImportProblem>)> {
+        self.check()?;
+        let mut builder = Builder::new();
+        let file = "template";
+        let size = [("width", self.width), ("height", self.height)];
+
+        let mut cans = vec![];
+        for can in self.first_can..self.first_can + self.cans {
+            let vars = [size[0], size[1], ("can", can)];
+            let name = render(&self.can_name, &vars)?;
+            builder.can(&Source { file, line: 0, path: Some(format!("can {}", can)) }, &name, can, None);
+            cans.push((can, name));
+        }
+        let mut racks = vec![];
+        for (can, can_name) in &cans {
+            for rack in 1..=self.racks {
+                let vars = [size[0], size[1], ("can", *can), ("rack", rack)];
+                let name = render(&self.rack_name, &vars)?;
+                let src = Source { file, line: 0, path: Some(format!("can {} rack {}", can, rack)) };
+                builder.rack(&src, NewRack {
+                    can: can_name,
+                    name: &name,
+                    index: rack - 1,
+                    width: Some(self.width),
+                    height: Some(self.height),
+                    numbering: Numbering::default(),
+                });
+                racks.push((*can, can_name, rack, name));
+            }
+        }
+        for (can, can_name, rack, rack_name) in &racks {
+            for row in 0..self.height {
+                for col in 0..self.width {
+                    let vars = [
+                        size[0], size[1],
+                        ("can", *can), ("rack", *rack),
+                        ("row", row), ("col", col), ("slot", row * self.width + col),
+                    ];
+                    let ip = render(&self.ip, &vars)?;
+                    let src = Source { file, line: 0, path: Some(format!("can {} rack {} slot {}-{}", can_name, rack_name, row, col)) };
+                    builder.miner(&src, NewMiner {
+                        ip: &ip,
+                        can: Some(can_name),
+                        rack: rack_name,
+                        row,
+                        index: col,
+                        port: None,
+                        external_id: None,
+                    });
+                }
+            }
+        }
+        Ok(builder.finish(&[file]))
+    }
+
+    /// Names and IPs the template shares with the existing layout
+    async fn collisions(&self, db: &SqlitePool, map: &layout::Sitemap) -> Result<Vec<String>> {
+        let mut collisions = vec![];
+        let cans = sqlx::query!("SELECT name, num FROM cans")
+            .fetch_all(db).await?;
+        for can in &map.cans {
+            if let Some(existing) = cans.iter().find(|c| c.name == can.name || c.num == can.num) {
+                collisions.push(format!("Can {} #{} clashes with existing can {} #{}", can.name, can.num, existing.name, existing.num));
+            }
+        }
+        let racks = sqlx::query!(r#"
+            SELECT r.name, c.name AS can
+            FROM racks r
+            JOIN cans c ON r.can_id = c.id
+            "#
+        ).fetch_all(db).await?;
+        for rack in map.racks.iter().filter(|rack| racks.iter().any(|r| r.can == rack.can && r.name == rack.name)) {
+            collisions.push(format!("Rack {} already exists in can {}", rack.name, rack.can));
+        }
+        let miners = sqlx::query!(r#"
+            SELECT m.ip, m.row, m.index_, r.name AS rack
+            FROM miners m
+            JOIN racks r ON m.rack_id = r.id
+            WHERE m.ip != ''
+            "#
+        ).fetch_all(db).await?
+            .into_iter()
+            .map(|m| (m.ip.clone(), m))
+            .collect::<HashMap<_, _>>();
+        for miner in &map.miners {
+            if let Some(existing) = miners.get(&miner.ip) {
+                collisions.push(format!(
+                    "{} for rack {} slot {}-{} is already in rack {} slot {}-{}",
+                    miner.ip, miner.slot.rack, miner.slot.row, miner.slot.index,
+                    existing.rack, existing.row, existing.index_
+                ));
+            }
+        }
+        Ok(collisions)
+    }
+
+    pub async fn preview(&self, db: &SqlitePool) -> Result<TemplatePreview> {
+        let (map, problems) = self.expand()?;
+        let collisions = self.collisions(db, &map).await?;
+        Ok(TemplatePreview {
+            layout: Layout::from(&map),
+            miners: map.miners.len(),
+            problems,
+            collisions,
+        })
+    }
+
+    /// Add the generated cans, racks and miners to the layout
+    /// Nothing is written if any value is invalid or collides with the existing layout
+    pub async fn generate(&self, db: &SqlitePool) -> Result<()> {
+        let (map, problems) = self.expand()?;
+        if !problems.is_empty() {
+            return Err(anyhow::anyhow!("{}", layout::format_problems(&problems)));
+        }
+        let collisions = self.collisions(db, &map).await?;
+        if !collisions.is_empty() {
+            return Err(anyhow::anyhow!("{}", collisions.join("\n")));
+        }
+        layout::append(db, &map).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const VARS: &[(&str, i64)] = &[("can", 3), ("rack", 2), ("width", 4)];
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3", VARS).unwrap(), 7);
+        assert_eq!(eval("(1 + 2) * 3", VARS).unwrap(), 9);
+        assert_eq!(eval("10 - 4 - 3", VARS).unwrap(), 3);
+        assert_eq!(eval("17 % 5 * 2", VARS).unwrap(), 4);
+        assert_eq!(eval("can * width + rack", VARS).unwrap(), 14);
+    }
+
+    #[test]
+    fn unary_minus() {
+        assert_eq!(eval("-3 + 5", VARS).unwrap(), 2);
+        assert_eq!(eval("--can", VARS).unwrap(), 3);
+        assert_eq!(eval("2 * -(rack + 1)", VARS).unwrap(), -6);
+    }
+
+    #[test]
+    fn divide_by_zero() {
+        assert!(eval("1 / 0", VARS).is_err());
+        assert!(eval("1 % (rack - 2)", VARS).is_err());
+    }
+
+    #[test]
+    fn overflow() {
+        assert!(eval("9223372036854775807 + 1", VARS).is_err());
+        assert!(eval("(-9223372036854775807 - 1) / -1", VARS).is_err());
+        assert!(eval("(-9223372036854775807 - 1) % -1", VARS).is_err());
+        assert!(eval("-(-9223372036854775807 - 1)", VARS).is_err());
+    }
+
+    #[test]
+    fn nesting() {
+        let deep = format!("{}1{}", "(".repeat(MAX_DEPTH), ")".repeat(MAX_DEPTH));
+        assert_eq!(eval(&deep, VARS).unwrap(), 1);
+        let deeper = format!("{}1{}", "(".repeat(100_000), ")".repeat(100_000));
+        assert!(eval(&deeper, VARS).is_err());
+        assert!(eval(&"-".repeat(100_000), VARS).is_err());
+    }
+
+    #[test]
+    fn render_template() {
+        assert_eq!(render("10.{can}.{rack}.{width + 10}", VARS).unwrap(), "10.3.2.14");
+        assert_eq!(render("C{can}", VARS).unwrap(), "C3");
+        assert!(render("C{can", VARS).is_err());
+        assert!(render("C}can", VARS).is_err());
+        assert!(render("{row}", VARS).is_err());
+    }
+
+    fn template() -> Template {
+        Template {
+            first_can: 1,
+            cans: 2,
+            racks: 2,
+            width: 2,
+            height: 2,
+            can_name: "C{can}".to_string(),
+            rack_name: "R{rack}".to_string(),
+            ip: "10.{can}.{rack}.{slot + 10}".to_string(),
+        }
+    }
+
+    #[test]
+    fn rack_names_repeat_across_cans() {
+        let (map, problems) = template().expand().unwrap();
+        assert!(problems.is_empty(), "{}", layout::format_problems(&problems));
+        let racks = map.racks.iter().map(|r| (r.can.as_str(), r.name.as_str())).collect::<Vec<_>>();
+        assert_eq!(racks, [("C1", "R1"), ("C1", "R2"), ("C2", "R1"), ("C2", "R2")]);
+        assert_eq!(map.miners.len(), 16);
+        let last = map.miners.last().unwrap();
+        assert_eq!((last.ip.as_str(), last.slot.can.as_str(), last.slot.rack.as_str()), ("10.2.2.13", "C2", "R2"));
+    }
+
+    #[tokio::test]
+    async fn collisions_by_can() {
+        let db = crate::db::test_db().await;
+        template().generate(&db).await.unwrap();
+        // A third can reusing the rack names doesn't clash
+        let next = Template { first_can: 3, cans: 1, ..template() };
+        let (map, _) = next.expand().unwrap();
+        assert!(next.collisions(&db, &map).await.unwrap().is_empty());
+        // Generating an existing can again clashes with its racks, IPs left aside
+        let again = Template { first_can: 2, cans: 1, ip: "10.9.{rack}.{slot}".to_string(), ..template() };
+        let (map, _) = again.expand().unwrap();
+        let collisions = again.collisions(&db, &map).await.unwrap();
+        assert_eq!(collisions, [
+            "Can C2 #2 clashes with existing can C2 #2",
+            "Rack R1 already exists in can C2",
+            "Rack R2 already exists in can C2",
+        ]);
+    }
+}
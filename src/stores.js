@@ -8,6 +8,10 @@ export const settings = writable({
     maxConnections: 500,
     connectionTimeout: 10,
     readTimeout: 15,
+    backupInterval: 24,
+    backupGenerations: 7,
+    minerPower: 3500,
+    stageDelay: 120,
 });
 
 export const pools = writable([]);
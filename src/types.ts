@@ -16,8 +16,49 @@ export interface Profile {
   max_volt?: number;
 }
 
+export type Hashboard = {
+  index: number;
+  hashrate?: number;
+  chips?: number;
+  expected_chips?: number;
+  board_temp?: number;
+  chip_temp?: number;
+  freq?: number;
+  serial?: string;
+};
+
+export type PoolStatus = {
+  url: string;
+  user: string;
+  status: string;
+  active: boolean;
+  accepted: number;
+  rejected: number;
+  stale: number;
+  last_share_time?: number;
+};
+
+export interface Condition {
+  type: string;
+  board?: number;
+  found?: number;
+  expected?: number;
+  reason?: string;
+  url?: string;
+  rate?: number;
+}
+
+export type Anomaly = {
+  metric: "temp" | "fan" | "hashrate" | "efficiency";
+  scope: "rack" | "can";
+  value: number;
+  median: number;
+  score: number;
+};
+
 export type Miner = {
   ip: string;
+  external_id?: string;
   make?: string;
   model?: string;
   submodel?: string;
@@ -28,6 +69,7 @@ export type Miner = {
   uptime?: number;
   errors?: string[];
   pools?: Pool[];
+  pool_stats?: PoolStatus[];
   sleep?: boolean;
   locate?: boolean;
   power?: number;
@@ -35,12 +77,225 @@ export type Miner = {
   profile?: Profile;
   profiles?: Profile[];
   hashboard?: string;
+  boards?: Hashboard[];
+  conditions?: Condition[];
+  anomalies?: Anomaly[];
   nameplate?: number;
 };
 
+export type Numbering = {
+  start: "top_left" | "top_right" | "bottom_left" | "bottom_right";
+  serpentine: boolean;
+};
+
 export type Rack = {
   id: number;
   name: string;
   width: number;
+  numbering?: Numbering;
   miners: Miner[][];
+  labels?: number[][];
+};
+
+export type Area = {
+  id: number;
+  parent_id?: number;
+  name: string;
+  kind: string;
+};
+
+export type SiteNode =
+  | { kind: "site" }
+  | { kind: "area" | "can" | "rack"; id: number };
+
+export type Totals = {
+  miners: number;
+  sampled: number;
+  online: number;
+  healthy: number;
+  hashrate: number;
+  power: number;
+};
+
+export type NodeSummary = {
+  node: SiteNode;
+  name: string;
+  kind: string;
+  totals: Totals;
+  children: NodeSummary[];
+};
+
+export type CircuitFeed = {
+  id: number;
+  circuit_id: number;
+  rack_id: number;
+  first?: number;
+  last?: number;
+};
+
+export type CircuitLoad = {
+  miners: number;
+  estimated: number;
+  watts: number;
+};
+
+export type Circuit = {
+  id: number;
+  pdu_id: number;
+  name: string;
+  capacity: number;
+  feeds: CircuitFeed[];
+  load: CircuitLoad;
+};
+
+export type Pdu = {
+  id: number;
+  name: string;
+  circuits: Circuit[];
+};
+
+export type CurtailAction =
+  | { type: "sleep" }
+  | { type: "profile"; profile: Profile; previous?: Profile };
+
+export type PlannedAction = {
+  ip: string;
+  action: CurtailAction;
+  before: number;
+  expected: number;
+  efficiency?: number;
+};
+
+export type CurtailmentPlan = {
+  target: number;
+  before: number;
+  expected: number;
+  shortfall: number;
+  unknown: number;
+  curtailed: number;
+  actions: PlannedAction[];
+};
+
+export type CurtailmentAction = {
+  id: number;
+  ip: string;
+  action: CurtailAction;
+  before: number;
+  expected: number;
+  after?: number;
+  error?: string;
+  applied: boolean;
+  reversed: boolean;
+};
+
+export type Curtailment = {
+  id: number;
+  target: number;
+  scope: SiteNode[];
+  before: number;
+  expected: number;
+  achieved?: number;
+  state: "applying" | "applied" | "verified" | "reversed";
+  created: number;
+  actions: CurtailmentAction[];
+};
+
+export type ModelEconomics = {
+  model: string;
+  hashrate: number;
+  power: number;
+};
+
+export type Pricing = {
+  enabled: boolean;
+  url: string;
+  refresh: number;
+  hashprice: number;
+  models: ModelEconomics[];
+};
+
+export type PriceInterval = {
+  start: number;
+  end: number;
+  price: number;
+};
+
+export type PriceDecision = {
+  id: number;
+  decided: number;
+  ip: string;
+  model: string;
+  sleep: boolean;
+  price: number;
+  breakeven: number;
+  interval: number;
+  error?: string;
+};
+
+export type ReportRequest = {
+  start: number;
+  end: number;
+  target?: number;
+};
+
+export type LoadPoint = {
+  time: number;
+  load: number;
+};
+
+export type CanCurve = {
+  id: number;
+  name: string;
+  baseline: number;
+  minimum: number;
+  points: LoadPoint[];
+};
+
+export type FailedMiner = {
+  ip: string;
+  can: string;
+  rack: string;
+  baseline: number;
+  last: number;
+  reason: string;
+};
+
+export type JobRun = {
+  id: number;
+  job: string;
+  detail: string;
+  started: number;
+  finished?: number;
+  tasks: number;
+  failed?: number;
+  cancelled?: number;
+};
+
+export type ComplianceReport = {
+  start: number;
+  end: number;
+  target?: number;
+  baseline: number;
+  minimum: number;
+  reached?: number;
+  time_to_curtail?: number;
+  sustained?: number;
+  average?: number;
+  commanded: number;
+  unreachable: number;
+  site: LoadPoint[];
+  cans: CanCurve[];
+  failed: FailedMiner[];
+  jobs: JobRun[];
+};
+
+export type ModelProfile = {
+  model: string;
+  profile: Profile;
+};
+
+export type ProfilePreset = {
+  id: number;
+  name: string;
+  profiles: ModelProfile[];
 };
@@ -1,4 +1,4 @@
-import type { Profile } from "./types";
+import type { Profile, Condition, Anomaly } from "./types";
 
 export function round(number, precision) {
   var factor = Math.pow(10, precision);
@@ -16,4 +16,59 @@ export function pretty_profile(p: Profile) {
     case "lowpower":
       return `Low Power`;
   }
-}
\ No newline at end of file
+}
+
+export function pretty_condition(c: Condition) {
+  switch (c.type) {
+    case "MissingBoard":
+      return `Missing board (${c.found}/${c.expected})`;
+    case "DeadBoard":
+      return `Board ${c.board} dead`;
+    case "UnderperformingBoard":
+      return `Board ${c.board} underperforming: ${c.reason}`;
+    case "DeadPool":
+      return `Dead pool ${c.url}`;
+    case "HighRejectRate":
+      return `${round(c.rate * 100, 1)}% rejected on ${c.url}`;
+    default:
+      return c.type;
+  }
+}
+
+export function pretty_anomaly(a: Anomaly) {
+  const units = { temp: "°C", fan: "RPM", hashrate: "TH/s", efficiency: "W/TH" };
+  return `${a.metric} ${round(a.value, 1)} ${units[a.metric]} vs ${a.scope} median ${round(a.median, 1)}`;
+}
+
+function pretty_slot(s: any) {
+  return `${s.can} ${s.rack} ${s.row}-${s.index}`;
+}
+
+export function pretty_change(c: any) {
+  switch (c.kind) {
+    case "add_can":
+      return `Add can ${c.name} (#${c.num})`;
+    case "update_can":
+      return `Renumber can ${c.name} to #${c.num}`;
+    case "remove_can":
+      return `Remove can ${c.name}`;
+    case "add_rack":
+      return `Add rack ${c.name} to ${c.can} (${c.width}x${c.height})`;
+    case "update_rack":
+      return `Update rack ${c.name} in ${c.can} (${c.width}x${c.height}, position ${c.index})`;
+    case "remove_rack":
+      return `Remove rack ${c.name} from ${c.can}`;
+    case "add_miner":
+      return `Add ${c.ip} at ${pretty_slot(c.slot)}`;
+    case "move_miner":
+      return `Move ${c.ip} from ${pretty_slot(c.from)} to ${pretty_slot(c.to)}`;
+    case "change_ip":
+      return `Change ${pretty_slot(c.slot)} from ${c.old} to ${c.new}`;
+    case "update_miner":
+      return `Update ${c.ip} port ${c.port ?? "default"}, external id ${c.external_id ?? "none"}`;
+    case "remove_miner":
+      return `Remove ${c.ip} from ${pretty_slot(c.slot)}`;
+    default:
+      return c.kind;
+  }
+}